@@ -0,0 +1,121 @@
+use analysis::{chi2, histogram, rs};
+
+/// Prefixes the chi-square attack is run over, so that a short message at
+/// the start of the samples is not drowned out by the rest
+const STEPS: usize = 10;
+
+/// How much more detectable embedding made one channel, each from 0 to 1.
+///
+/// Every measure is taken relative to the cover, as some covers look like
+/// stego images to a detector before anything is embedded in them.
+#[derive(Copy, Clone, Debug)]
+pub struct Check
+{
+    /// The largest rise in chi-square probability over growing prefixes
+    pub chi2: f64,
+    /// The rise in the message length RS analysis estimates, as a fraction
+    /// of samples
+    pub rs: f64,
+    /// How much of the imbalance between value pairs the embedding removed
+    pub balance: f64,
+}
+
+impl Check
+{
+    /// The worst of the measures
+    pub fn score(&self) -> f64
+    {
+        self.chi2.max(self.rs).max(self.balance)
+    }
+}
+
+/// Check one channel of an image after embedding, against the same channel
+/// before it
+pub fn check(cover: &[u8], stego: &[u8], width: usize) -> Check
+{
+    let chi2 = chi2::sequential(cover, STEPS).iter()
+        .zip(chi2::sequential(stego, STEPS).iter())
+        .map(|(before, after)|
+            after.1.probability.unwrap_or(0.0) - before.1.probability.unwrap_or(0.0))
+        .fold(0.0, f64::max);
+
+    let estimate = |plane| rs::rs(plane, width).unwrap_or(0.0).clamp(0.0, 1.0);
+    let rs = (estimate(stego) - estimate(cover)).max(0.0);
+
+    let before = pair_balance(&histogram(cover));
+    let after = pair_balance(&histogram(stego));
+
+    let balance = if before < 1.0
+    {
+        ((after - before) / (1.0 - before)).clamp(0.0, 1.0)
+    }
+    else
+    {
+        0.0
+    };
+
+    Check
+    {
+        chi2,
+        rs,
+        balance,
+    }
+}
+
+/// How evenly the counts of each pair of values 2i and 2i + 1 are split,
+/// 1 when every pair is split exactly in half
+pub fn pair_balance(histogram: &[usize; 256]) -> f64
+{
+    let (difference, total) = histogram.chunks(2)
+        .fold((0, 0), |(d, t), pair| (d + pair[0].abs_diff(pair[1]), t + pair[0] + pair[1]));
+
+    if total == 0
+    {
+        1.0
+    }
+    else
+    {
+        1.0 - difference as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use rand::Rng;
+
+    use analysis::test::{cover, embed, rng};
+
+    use super::*;
+
+    #[test]
+    fn untouched_is_clean()
+    {
+        let (plane, width) = cover(&mut rng());
+
+        assert_eq!(check(&plane, &plane, width).score(), 0.0);
+    }
+
+    #[test]
+    fn flags_embedding()
+    {
+        let mut rng = rng();
+
+        // only even values, so the pairs start as unbalanced as they can be
+        let plane = (0..200 * 200)
+            .map(|_| rng.gen_range(0u8, 100) * 2)
+            .collect::<Vec<_>>();
+        let mut stego = plane.clone();
+        embed(&mut stego, 1.0, &mut rng);
+
+        let full = check(&plane, &stego, 200);
+
+        assert!(full.chi2 > 0.9 && full.balance > 0.9, "{:?}", full);
+
+        let (plane, width) = cover(&mut rng);
+        let mut stego = plane.clone();
+        embed(&mut stego, 0.5, &mut rng);
+
+        assert!(check(&plane, &stego, width).rs > 0.3);
+    }
+}
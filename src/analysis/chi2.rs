@@ -0,0 +1,117 @@
+use analysis::{chi2_tail, histogram};
+
+/// Pairs of values expected to occur fewer times than this are left out,
+/// the test is unreliable for them
+const MIN_EXPECTED: f64 = 5.0;
+
+/// The result of a chi-square attack
+#[derive(Copy, Clone, Debug)]
+pub struct Chi2
+{
+    /// The chi-square statistic
+    pub statistic: f64,
+    /// The degrees of freedom, one less than the pairs of values tested
+    pub degrees: usize,
+    /// The probability the samples have had their least significant bits
+    /// replaced, if any pairs could be tested
+    pub probability: Option<f64>,
+}
+
+/// The Westfeld-Pfitzmann chi-square attack.
+///
+/// Replacing least significant bits with message bits evens out the counts
+/// of each pair of values 2i and 2i + 1. This tests how well the counts fit
+/// that even split: the better the fit, the higher the probability.
+pub fn chi2(samples: &[u8]) -> Chi2
+{
+    let histogram = histogram(samples);
+
+    let mut statistic = 0.0;
+    let mut pairs = 0;
+
+    for pair in histogram.chunks(2)
+    {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+
+        if expected < MIN_EXPECTED
+        {
+            continue;
+        }
+
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+
+    let degrees = pairs.max(1) - 1;
+
+    Chi2
+    {
+        statistic,
+        degrees,
+        probability: if degrees > 0
+        {
+            Some(chi2_tail(statistic, degrees))
+        }
+        else
+        {
+            None
+        },
+    }
+}
+
+/// The chi-square attack over growing prefixes of the samples, in `steps`
+/// steps. Gives the number of samples tested and the result for each.
+///
+/// Sequential embedding shows up as a high probability that falls away
+/// where the message ends.
+pub fn sequential(samples: &[u8], steps: usize) -> Vec<(usize, Chi2)>
+{
+    (1..=steps)
+        .map(|step| samples.len() * step / steps)
+        .map(|len| (len, chi2(&samples[..len])))
+        .collect()
+}
+
+/// How far into the samples the embedding seems to go, as a fraction:
+/// the end of the last step with a probability over one half, as long as
+/// every step before it had one too
+pub fn extent(results: &[(usize, Chi2)], total: usize) -> f64
+{
+    results.iter()
+        .take_while(|r| r.1.probability.is_some_and(|p| p > 0.5))
+        .last()
+        .map_or(0.0, |r| r.0 as f64 / total as f64)
+}
+
+#[cfg(test)]
+mod test
+{
+    use rand::{Rng, StdRng};
+
+    use super::*;
+
+    #[test]
+    fn detects_sequential_embedding()
+    {
+        let mut rng = StdRng::new().unwrap();
+
+        // a cover with only even values has the worst possible fit
+        let mut samples = (0..20_000)
+            .map(|_| rng.gen_range(0u8, 100) * 2)
+            .collect::<Vec<_>>();
+
+        assert!(chi2(&samples).probability.unwrap() < 0.01);
+
+        // replace the least significant bits of the first half
+        for s in samples.iter_mut().take(10_000)
+        {
+            *s = (*s & !1) | rng.gen_range(0, 2);
+        }
+
+        let results = sequential(&samples, 10);
+
+        assert!(results[3].1.probability.unwrap() > 0.9);
+        assert!(results[9].1.probability.unwrap() < 0.01);
+        assert_eq!(extent(&results, samples.len()), 0.5);
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Differences are truncated to -T..=T for SPAM
+const SPAM_T: i32 = 3;
+/// Quantized residuals are truncated to -T..=T for SRM-lite
+const SRM_T: i32 = 2;
+
+/// Horizontal and vertical directions, then diagonal ones
+const STRAIGHT: [(i64, i64); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL: [(i64, i64); 4] = [(1, 1), (-1, -1), (1, -1), (-1, 1)];
+
+/// Residual kernels of the first three orders, each with its quantization
+/// step
+const KERNELS: [(&[i32], f64); 3] = [
+    (&[1, -1], 1.0),
+    (&[1, -2, 1], 2.0),
+    (&[-1, 3, -3, 1], 3.0),
+];
+
+/// A set of features
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Set
+{
+    /// Second order SPAM, 686 features
+    Spam,
+    /// Co-occurrences of quantized residuals of three orders, 375 features
+    SrmLite,
+}
+
+impl FromStr for Set
+{
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Set, ()>
+    {
+        match s
+        {
+            "spam" => Ok(Set::Spam),
+            "srm-lite" => Ok(Set::SrmLite),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The features of one channel
+pub fn extract(set: Set, plane: &[u8], width: usize) -> Vec<f64>
+{
+    match set
+    {
+        Set::Spam => spam(plane, width),
+        Set::SrmLite => srm_lite(plane, width),
+    }
+}
+
+/// SPAM, after Pevný, Bas and Fridrich.
+///
+/// Differences between neighbours, truncated to -3..=3, are modelled as a
+/// second order Markov chain along each of eight directions. The transition
+/// probabilities are averaged over the straight directions, then over the
+/// diagonal ones, giving 2 * 7^3 features.
+pub fn spam(plane: &[u8], width: usize) -> Vec<f64>
+{
+    let mut features = Vec::with_capacity(2 * 343);
+
+    for directions in &[STRAIGHT, DIAGONAL]
+    {
+        let mut average = vec![0.0; 343];
+
+        for &direction in directions
+        {
+            for (a, p) in average.iter_mut().zip(transitions(plane, width, direction))
+            {
+                *a += p / directions.len() as f64;
+            }
+        }
+
+        features.extend(average);
+    }
+
+    features
+}
+
+/// Pr(d3 | d2, d1) for successive truncated differences d1, d2, d3 along a
+/// direction, indexed by d1, then d2, then d3
+fn transitions(plane: &[u8], width: usize, direction: (i64, i64)) -> Vec<f64>
+{
+    let size = (2 * SPAM_T + 1) as usize;
+    let mut counts = vec![0usize; size * size * size];
+
+    let index = |d: i32| (d.clamp(-SPAM_T, SPAM_T) + SPAM_T) as usize;
+
+    for_runs(plane, width, direction, 4, |run|
+    {
+        let d = |i: usize| run[i] as i32 - run[i + 1] as i32;
+
+        counts[(index(d(0)) * size + index(d(1))) * size + index(d(2))] += 1;
+    });
+
+    counts.chunks(size)
+        .flat_map(|given|
+        {
+            let total = given.iter().sum::<usize>();
+
+            given.iter().map(move |&c| if total == 0 { 0.0 } else { c as f64 / total as f64 })
+        })
+        .collect()
+}
+
+/// A lite spatial rich model, after Fridrich and Kodovský.
+///
+/// Residuals of first, second and third order are quantized, truncated to
+/// -2..=2 and their co-occurrences along rows and columns counted, in runs
+/// of three, giving 3 * 5^3 features.
+pub fn srm_lite(plane: &[u8], width: usize) -> Vec<f64>
+{
+    let size = (2 * SRM_T + 1) as usize;
+    let mut features = Vec::with_capacity(KERNELS.len() * size.pow(3));
+
+    for &(kernel, q) in &KERNELS
+    {
+        let mut counts = vec![0usize; size.pow(3)];
+
+        for &direction in &[(1, 0), (0, 1)]
+        {
+            let index = |run: &[u8]|
+            {
+                let r = kernel.iter().zip(run).map(|(&k, &s)| k * s as i32).sum::<i32>();
+
+                ((r as f64 / q).round() as i32).clamp(-SRM_T, SRM_T) + SRM_T
+            };
+
+            // three residuals in a row, each over the kernel's width
+            for_runs(plane, width, direction, kernel.len() + 2, |run|
+            {
+                let i = (index(&run[0..]) as usize * size + index(&run[1..]) as usize) * size
+                    + index(&run[2..]) as usize;
+
+                counts[i] += 1;
+            });
+        }
+
+        let total = counts.iter().sum::<usize>().max(1);
+        features.extend(counts.into_iter().map(|c| c as f64 / total as f64));
+    }
+
+    features
+}
+
+/// Call `f` with every run of `len` samples along a direction
+fn for_runs<F: FnMut(&[u8])>(plane: &[u8], width: usize, (dx, dy): (i64, i64), len: usize, mut f: F)
+{
+    let height = (plane.len() / width.max(1)) as i64;
+    let width = width as i64;
+    let span = len as i64 - 1;
+
+    let mut run = vec![0u8; len];
+
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let (ex, ey) = (x + dx * span, y + dy * span);
+
+            if ex < 0 || ex >= width || ey < 0 || ey >= height
+            {
+                continue;
+            }
+
+            for (i, s) in run.iter_mut().enumerate()
+            {
+                let (px, py) = (x + dx * i as i64, y + dy * i as i64);
+                *s = plane[(py * width + px) as usize];
+            }
+
+            f(&run);
+        }
+    }
+}
+
+/// A header, then a row of features for each channel
+pub fn to_csv(names: &[&str], rows: &[Vec<f64>]) -> String
+{
+    let mut csv = String::from("channel");
+
+    for i in 0..rows.first().map_or(0, |r| r.len())
+    {
+        let _ = write!(csv, ",f{}", i);
+    }
+    csv.push('\n');
+
+    for (name, row) in names.iter().zip(rows)
+    {
+        csv.push_str(name);
+
+        for f in row
+        {
+            let _ = write!(csv, ",{}", f);
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// A NumPy array of little endian doubles, a row for each channel
+pub fn to_npy(rows: &[Vec<f64>]) -> Vec<u8>
+{
+    let columns = rows.first().map_or(0, |r| r.len());
+
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+        rows.len(), columns);
+
+    // the magic, version and header length take 10 bytes, and the data
+    // must start on a multiple of 64
+    while (10 + header.len() + 1) % 64 != 0
+    {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+    npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    npy.extend_from_slice(header.as_bytes());
+
+    for f in rows.iter().flatten()
+    {
+        npy.extend_from_slice(&f.to_le_bytes());
+    }
+
+    npy
+}
+
+#[cfg(test)]
+mod test
+{
+    use analysis::test::{cover, embed, rng};
+
+    use super::*;
+
+    #[test]
+    fn flat_plane()
+    {
+        let plane = vec![100u8; 32 * 32];
+
+        let spam = spam(&plane, 32);
+
+        // no differences: d3 = 0 always follows d1 = d2 = 0
+        assert_eq!(spam.len(), 686);
+        assert_eq!(spam[(3 * 7 + 3) * 7 + 3], 1.0);
+        assert_eq!(spam[343 + (3 * 7 + 3) * 7 + 3], 1.0);
+        assert_eq!(spam.iter().sum::<f64>(), 2.0);
+
+        let srm = srm_lite(&plane, 32);
+
+        assert_eq!(srm.len(), 375);
+        assert_eq!(srm[62], 1.0);
+        assert_eq!(srm[125 + 62], 1.0);
+    }
+
+    #[test]
+    fn embedding_moves_features()
+    {
+        let mut rng = rng();
+        let (plane, width) = cover(&mut rng);
+        let mut stego = plane.clone();
+        embed(&mut stego, 1.0, &mut rng);
+
+        for &set in &[Set::Spam, Set::SrmLite]
+        {
+            let distance = extract(set, &plane, width).iter()
+                .zip(extract(set, &stego, width))
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>();
+
+            assert!(distance > 0.1, "{:?} moved {}", set, distance);
+        }
+    }
+
+    #[test]
+    fn npy_layout()
+    {
+        let npy = to_npy(&[vec![1.0, 2.0], vec![3.0, 4.0]]);
+
+        let start = 10 + u16::from_le_bytes([npy[8], npy[9]]) as usize;
+
+        assert_eq!(&npy[..6], b"\x93NUMPY");
+        assert_eq!(start % 64, 0);
+        assert_eq!(npy.len(), start + 4 * 8);
+        assert_eq!(npy[start - 1], b'\n');
+        assert_eq!(&npy[start..start + 8], &1.0f64.to_le_bytes());
+        assert!(String::from_utf8_lossy(&npy[10..start]).contains("'shape': (2, 2)"));
+    }
+}
@@ -0,0 +1,172 @@
+pub mod check;
+pub mod chi2;
+pub mod features;
+pub mod rs;
+pub mod spa;
+
+/// The samples of one channel, in scan order
+pub fn channel(samples: &[u8], channels: usize, channel: usize) -> Vec<u8>
+{
+    samples.iter().skip(channel).step_by(channels).cloned().collect()
+}
+
+/// How many times each value occurs
+pub fn histogram(samples: &[u8]) -> [usize; 256]
+{
+    let mut histogram = [0; 256];
+
+    for &s in samples
+    {
+        histogram[s as usize] += 1;
+    }
+
+    histogram
+}
+
+/// The natural log of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64
+{
+    const G: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+
+    let series = G.iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |acc, (i, g)| acc + g / (x + 1.0 + i as f64));
+
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// The regularized lower incomplete gamma function P(a, x)
+fn gamma_p(a: f64, x: f64) -> f64
+{
+    if x <= 0.0
+    {
+        return 0.0;
+    }
+
+    if x < a + 1.0
+    {
+        // series expansion
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+
+        for _ in 0..1000
+        {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+
+            if term.abs() < sum.abs() * 1e-15
+            {
+                break;
+            }
+        }
+
+        sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    }
+    else
+    {
+        // continued fraction, by the modified Lentz method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+
+        for i in 1..1000
+        {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+
+            if (delta - 1.0).abs() < 1e-15
+            {
+                break;
+            }
+        }
+
+        1.0 - (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
+
+/// The probability a chi-square variable with `degrees` degrees of freedom
+/// is at least `x`
+pub fn chi2_tail(x: f64, degrees: usize) -> f64
+{
+    1.0 - gamma_p(degrees as f64 / 2.0, x / 2.0)
+}
+
+#[cfg(test)]
+pub mod test
+{
+    use rand::{ChaChaRng, Rng, SeedableRng};
+
+    use super::*;
+
+    /// A fixed source of randomness, so estimates are repeatable
+    pub fn rng() -> ChaChaRng
+    {
+        ChaChaRng::from_seed(&[0x7374_6167][..])
+    }
+
+    /// A smooth, noisy channel, like a photograph's, and its width
+    pub fn cover<R: Rng>(rng: &mut R) -> (Vec<u8>, usize)
+    {
+        let (width, height) = (200, 200);
+        let mut plane = vec![0u8; width * height];
+
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let base = 128.0
+                    + 60.0 * ((x as f64) / 17.0).sin()
+                    + 40.0 * ((y as f64) / 23.0).cos();
+                let noise = rng.gen_range(-3i32, 4);
+
+                plane[y * width + x] = (base as i32 + noise).clamp(0, 255) as u8;
+            }
+        }
+
+        (plane, width)
+    }
+
+    /// Replace the LSBs of a random `rate` of the samples with random bits
+    pub fn embed<R: Rng>(plane: &mut [u8], rate: f64, rng: &mut R)
+    {
+        for s in plane.iter_mut()
+        {
+            if rng.next_f64() < rate
+            {
+                *s = (*s & !1) | rng.gen_range(0, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn chi2_tail_values()
+    {
+        // from standard tables
+        assert!((chi2_tail(3.841, 1) - 0.05).abs() < 1e-3);
+        assert!((chi2_tail(18.307, 10) - 0.05).abs() < 1e-3);
+        assert!((chi2_tail(10.0, 10) - 0.4405).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,144 @@
+/// The mask applied to each group of samples
+const MASK: [bool; 4] = [false, true, true, false];
+
+/// Counts of regular and singular groups under a flipping
+#[derive(Copy, Clone, Debug, Default)]
+struct Counts
+{
+    regular: f64,
+    singular: f64,
+}
+
+impl Counts
+{
+    fn difference(self) -> f64
+    {
+        self.regular - self.singular
+    }
+}
+
+/// Fridrich's RS analysis: an estimate of the relative length of a message
+/// embedded by replacing least significant bits at random positions, from a
+/// channel with rows `width` samples long.
+///
+/// Groups of samples are flipped two ways: with the LSB flip 2n <-> 2n + 1,
+/// and with the shifted flip 2n - 1 <-> 2n. In a cover image each makes
+/// about as many groups more noisy (regular) as less (singular). LSB
+/// embedding pushes the two apart, and by how much tells the message length.
+pub fn rs(plane: &[u8], width: usize) -> Option<f64>
+{
+    let flipped = plane.iter().map(|s| s ^ 1).collect::<Vec<_>>();
+
+    let (m0, n0) = counts(plane, width);
+    let (m1, n1) = counts(&flipped, width);
+
+    let d0 = m0.difference();
+    let d1 = m1.difference();
+    let dn0 = n0.difference();
+    let dn1 = n1.difference();
+
+    // 2(d1 + d0)z^2 + (d-0 - d-1 - d1 - 3d0)z + d0 - d-0 = 0
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+
+    let z = smaller_root(a, b, c)?;
+
+    Some(z / (z - 0.5))
+}
+
+/// Regular and singular counts under the positive and negative masks
+fn counts(plane: &[u8], width: usize) -> (Counts, Counts)
+{
+    let mut positive = Counts::default();
+    let mut negative = Counts::default();
+
+    if width == 0
+    {
+        return (positive, negative);
+    }
+
+    for row in plane.chunks(width)
+    {
+        for group in row.chunks(MASK.len()).filter(|g| g.len() == MASK.len())
+        {
+            let noise = smoothness(group);
+
+            let mut pos = [0i16; 4];
+            let mut neg = [0i16; 4];
+
+            for (i, &s) in group.iter().enumerate()
+            {
+                let s = s as i16;
+                pos[i] = if MASK[i] { s ^ 1 } else { s };
+                neg[i] = if MASK[i] { ((s + 1) ^ 1) - 1 } else { s };
+            }
+
+            classify(&mut positive, noise, smoothness(&pos));
+            classify(&mut negative, noise, smoothness(&neg));
+        }
+    }
+
+    (positive, negative)
+}
+
+fn classify(counts: &mut Counts, before: i32, after: i32)
+{
+    if after > before
+    {
+        counts.regular += 1.0;
+    }
+    else if after < before
+    {
+        counts.singular += 1.0;
+    }
+}
+
+/// The discrimination function: how noisy a group is
+fn smoothness<T: Copy + Into<i32>>(group: &[T]) -> i32
+{
+    group.windows(2)
+        .map(|w| (w[1].into() - w[0].into()).abs())
+        .sum()
+}
+
+/// The root of ax^2 + bx + c = 0 closest to zero, if there is a real one
+pub fn smaller_root(a: f64, b: f64, c: f64) -> Option<f64>
+{
+    if a.abs() < 1e-12
+    {
+        return if b.abs() < 1e-12 { None } else { Some(-c / b) };
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant < 0.0
+    {
+        return None;
+    }
+
+    let r1 = (-b + discriminant.sqrt()) / (2.0 * a);
+    let r2 = (-b - discriminant.sqrt()) / (2.0 * a);
+
+    Some(if r1.abs() < r2.abs() { r1 } else { r2 })
+}
+
+#[cfg(test)]
+mod test
+{
+    use analysis::test::{cover, embed, rng};
+    use super::*;
+
+    #[test]
+    fn estimates_length()
+    {
+        let mut rng = rng();
+        let (mut plane, width) = cover(&mut rng);
+
+        assert!(rs(&plane, width).unwrap().abs() < 0.1);
+
+        embed(&mut plane, 0.5, &mut rng);
+
+        assert!((rs(&plane, width).unwrap() - 0.5).abs() < 0.1);
+    }
+}
@@ -0,0 +1,72 @@
+use analysis::rs::smaller_root;
+
+/// Dumitrescu, Wu and Wang's sample pair analysis: an estimate of the
+/// relative length of a message embedded by replacing least significant
+/// bits at random positions, from a channel with rows `width` samples long.
+///
+/// Pairs of horizontally adjacent samples (u, v) fall into X, where v is
+/// even and u < v or v is odd and u > v, Y, the same with u and v swapped,
+/// Z, where u = v, and W, where u and v differ only in their LSB. Covers
+/// have about as many pairs in X as Y, and embedding moves pairs between
+/// them in a way that depends on the message length p:
+///
+/// (|W| + |Z|) / 2 p^2 + (2|X| - |P|) p + |Y| - |X| = 0
+pub fn spa(plane: &[u8], width: usize) -> Option<f64>
+{
+    let (mut x, mut y, mut z, mut w, mut p) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    if width == 0
+    {
+        return None;
+    }
+
+    for row in plane.chunks(width)
+    {
+        for pair in row.windows(2)
+        {
+            let (u, v) = (pair[0], pair[1]);
+
+            p += 1.0;
+
+            if u == v
+            {
+                z += 1.0;
+            }
+            else if (v % 2 == 0) == (u < v)
+            {
+                x += 1.0;
+            }
+            else
+            {
+                y += 1.0;
+
+                if u / 2 == v / 2
+                {
+                    w += 1.0;
+                }
+            }
+        }
+    }
+
+    smaller_root((w + z) / 2.0, 2.0 * x - p, y - x)
+}
+
+#[cfg(test)]
+mod test
+{
+    use analysis::test::{cover, embed, rng};
+    use super::*;
+
+    #[test]
+    fn estimates_length()
+    {
+        let mut rng = rng();
+        let (mut plane, width) = cover(&mut rng);
+
+        assert!(spa(&plane, width).unwrap().abs() < 0.1);
+
+        embed(&mut plane, 0.5, &mut rng);
+
+        assert!((spa(&plane, width).unwrap() - 0.5).abs() < 0.1);
+    }
+}
@@ -0,0 +1,377 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use image::{self, DynamicImage, GenericImage, ImageBuffer, ImageFormat};
+
+use chunks::{self, Chunk};
+
+/// Chunks that describe pixels, which no longer apply when the frames are
+/// written back with another colour type
+const COLOUR_CHUNKS: &[&[u8; 4]] = &[b"PLTE", b"tRNS", b"sBIT", b"bKGD", b"hIST"];
+
+/// An animated PNG: every frame, and the chunks around them
+pub struct Apng
+{
+    /// The IHDR of the file
+    header: Vec<u8>,
+    /// How many times the animation plays, 0 for ever
+    plays: u32,
+    /// Chunks between the header and the first frame
+    before: Vec<Chunk>,
+    /// Chunks between the last frame and IEND
+    after: Vec<Chunk>,
+    frames: Vec<Frame>,
+}
+
+struct Frame
+{
+    /// The fcTL of the frame without its sequence number, or `None` for a
+    /// default image that is not part of the animation
+    control: Option<Vec<u8>>,
+    /// The frame's compressed image data
+    data: Vec<u8>,
+}
+
+impl Frame
+{
+    fn size(&self, header: &[u8]) -> (u32, u32)
+    {
+        let fields = self.control.as_ref().map_or(header, |c| &c[..]);
+
+        (be32(&fields[0..]), be32(&fields[4..]))
+    }
+}
+
+impl Apng
+{
+    /// Read an animated PNG, or give `None` for a PNG with only one image
+    pub fn read(bytes: &[u8]) -> Result<Option<Apng>, &'static str>
+    {
+        let chunks = chunks::read(bytes)?;
+
+        if !chunks.iter().any(|c| &c.kind == b"acTL")
+        {
+            return Ok(None);
+        }
+
+        let mut apng = Apng
+        {
+            header: Vec::new(),
+            plays: 0,
+            before: Vec::new(),
+            after: Vec::new(),
+            frames: Vec::new(),
+        };
+
+        for chunk in chunks
+        {
+            match &chunk.kind
+            {
+                b"IHDR" if chunk.data.len() == 13 => apng.header = chunk.data,
+                b"acTL" if chunk.data.len() == 8 => apng.plays = be32(&chunk.data[4..]),
+                b"fcTL" if chunk.data.len() == 26 => apng.frames.push(Frame
+                {
+                    control: Some(chunk.data[4..].to_vec()),
+                    data: Vec::new(),
+                }),
+                b"IDAT" =>
+                {
+                    // image data with no fcTL before it is a default image
+                    if apng.frames.is_empty()
+                    {
+                        apng.frames.push(Frame
+                        {
+                            control: None,
+                            data: Vec::new(),
+                        });
+                    }
+
+                    apng.frames.last_mut().unwrap().data.extend_from_slice(&chunk.data);
+                },
+                b"fdAT" if chunk.data.len() >= 4 => match apng.frames.last_mut()
+                {
+                    Some(frame) => frame.data.extend_from_slice(&chunk.data[4..]),
+                    None => return Err("An fdAT chunk comes before any fcTL chunk"),
+                },
+                b"IEND" => {},
+                b"IHDR" | b"acTL" | b"fcTL" | b"fdAT" => return Err("A malformed APNG chunk"),
+                _ if apng.frames.is_empty() => apng.before.push(chunk),
+                _ => apng.after.push(chunk),
+            }
+        }
+
+        if apng.header.is_empty() || apng.frames.iter().any(|f| f.data.is_empty())
+        {
+            return Err("An APNG frame has no image data");
+        }
+
+        Ok(Some(apng))
+    }
+
+    /// Decode every frame, in order
+    pub fn frames(&self) -> Result<Vec<DynamicImage>, &'static str>
+    {
+        self.frames.iter().map(|frame|
+        {
+            let (width, height) = frame.size(&self.header);
+
+            let mut header = self.header.clone();
+            header[0..4].copy_from_slice(&width.to_be_bytes());
+            header[4..8].copy_from_slice(&height.to_be_bytes());
+
+            // each frame is decoded as a PNG of its own
+            let mut png = vec![Chunk::new(b"IHDR", header)];
+            png.extend(self.before.iter().filter(|c| c.is_critical() || &c.kind == b"tRNS").cloned());
+            png.push(Chunk::new(b"IDAT", frame.data.clone()));
+            png.push(Chunk::new(b"IEND", Vec::new()));
+
+            image::load_from_memory(&chunks::write(&png))
+                .map_err(|_| "An APNG frame could not be decoded")
+        }).collect()
+    }
+
+    /// Leave out the ancillary chunks but tRNS, which the frames need
+    pub fn strip(&mut self)
+    {
+        self.before.retain(|c| c.is_critical() || &c.kind == b"tRNS");
+        self.after.retain(Chunk::is_critical);
+    }
+
+    /// The width and height of every frame, in order
+    pub fn sizes(&self) -> Vec<(u32, u32)>
+    {
+        self.frames.iter().map(|f| f.size(&self.header)).collect()
+    }
+
+    /// Save new frames of the same sizes in place of the old ones, keeping
+    /// the timing, disposal and blending of each
+    pub fn save<P: AsRef<Path>>(&self, frames: &[DynamicImage], path: P) -> io::Result<()>
+    {
+        assert_eq!(frames.len(), self.frames.len());
+
+        let mut header = Vec::new();
+        let mut data = Vec::new();
+
+        for frame in frames
+        {
+            let mut png = Vec::new();
+            frame.save(&mut png, ImageFormat::PNG)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+
+            let chunks = chunks::read(&png)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            header = chunks[0].data.clone();
+            data.push(chunks.into_iter()
+                .filter(|c| &c.kind == b"IDAT")
+                .flat_map(|c| c.data)
+                .collect::<Vec<_>>());
+        }
+
+        // the canvas keeps its size, the pixels take on the frames' format
+        header[0..8].copy_from_slice(&self.header[0..8]);
+        let recoloured = header[8..10] != self.header[8..10];
+
+        let animated = self.frames.iter().filter(|f| f.control.is_some()).count() as u32;
+        let mut actl = animated.to_be_bytes().to_vec();
+        actl.extend_from_slice(&self.plays.to_be_bytes());
+
+        let mut out = vec![Chunk::new(b"IHDR", header), Chunk::new(b"acTL", actl)];
+        out.extend(self.before.iter()
+            .filter(|c| !recoloured || !COLOUR_CHUNKS.contains(&&c.kind))
+            .cloned());
+
+        let mut sequence = 0u32;
+
+        for (i, (frame, data)) in self.frames.iter().zip(data).enumerate()
+        {
+            if let Some(ref control) = frame.control
+            {
+                let mut fctl = sequence.to_be_bytes().to_vec();
+                fctl.extend_from_slice(control);
+                out.push(Chunk::new(b"fcTL", fctl));
+                sequence += 1;
+            }
+
+            // the first frame is always IDAT, whether it's animated or not
+            if i == 0
+            {
+                out.push(Chunk::new(b"IDAT", data));
+            }
+            else
+            {
+                let mut fdat = sequence.to_be_bytes().to_vec();
+                fdat.extend_from_slice(&data);
+                out.push(Chunk::new(b"fdAT", fdat));
+                sequence += 1;
+            }
+        }
+
+        out.extend(self.after.iter().cloned());
+        out.push(Chunk::new(b"IEND", Vec::new()));
+
+        File::create(path)?.write_all(&chunks::write(&out))
+    }
+}
+
+/// The size of the image frames are stacked into, so that a codec runs
+/// through them in order: one under the other when they're all the same
+/// width, otherwise a single column of pixels
+pub fn stacked_size(sizes: &[(u32, u32)]) -> (u32, u32)
+{
+    let pixels = sizes.iter().map(|&(w, h)| w * h).sum::<u32>();
+
+    match sizes.first()
+    {
+        Some(&(width, _)) if width > 0 && sizes.iter().all(|s| s.0 == width) =>
+            (width, pixels / width),
+        _ => (1, pixels),
+    }
+}
+
+/// Stack frames of the same pixel type into one image
+pub fn stack(frames: &[DynamicImage]) -> Option<DynamicImage>
+{
+    let sizes = frames.iter().map(|f| (f.width(), f.height())).collect::<Vec<_>>();
+    let (width, height) = stacked_size(&sizes);
+
+    let color = frames.first()?.color();
+    if frames.iter().any(|f| f.color() != color)
+    {
+        return None;
+    }
+
+    let raw = frames.iter().flat_map(|f| f.raw_pixels()).collect::<Vec<_>>();
+
+    Some(match frames[0]
+    {
+        DynamicImage::ImageLuma8(_) =>
+            DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, raw)?),
+        DynamicImage::ImageLumaA8(_) =>
+            DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, raw)?),
+        DynamicImage::ImageRgb8(_) =>
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw)?),
+        DynamicImage::ImageRgba8(_) =>
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw)?),
+    })
+}
+
+/// Split an image made by `stack` back into frames of the given sizes
+pub fn unstack(image: &DynamicImage, sizes: &[(u32, u32)]) -> Vec<DynamicImage>
+{
+    let raw = image.raw_pixels();
+    let channels = raw.len() / (image.width() * image.height()) as usize;
+    let mut start = 0;
+
+    sizes.iter().map(|&(width, height)|
+    {
+        let end = start + (width * height) as usize * channels;
+        let part = raw[start..end].to_vec();
+        start = end;
+
+        match *image
+        {
+            DynamicImage::ImageLuma8(_) =>
+                DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, part).unwrap()),
+            DynamicImage::ImageLumaA8(_) =>
+                DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, part).unwrap()),
+            DynamicImage::ImageRgb8(_) =>
+                DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, part).unwrap()),
+            DynamicImage::ImageRgba8(_) =>
+                DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, part).unwrap()),
+        }
+    }).collect()
+}
+
+fn be32(b: &[u8]) -> u32
+{
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::env::temp_dir;
+    use std::fs;
+
+    use image::Rgba;
+
+    use super::*;
+
+    /// A two frame APNG, the second smaller than the first
+    fn apng() -> Vec<u8>
+    {
+        let frame = |w, h, v| DynamicImage::ImageRgba8(ImageBuffer::from_fn(w, h, |x, y|
+            Rgba { data: [v, x as u8, y as u8, 255] }));
+
+        let idat = |image: DynamicImage|
+        {
+            let mut png = Vec::new();
+            image.save(&mut png, ImageFormat::PNG).unwrap();
+            let chunks = chunks::read(&png).unwrap();
+            (chunks[0].data.clone(), chunks[1..].iter()
+                .filter(|c| &c.kind == b"IDAT")
+                .flat_map(|c| c.data.clone())
+                .collect::<Vec<_>>())
+        };
+
+        let (header, first) = idat(frame(8, 6, 10));
+        let (_, second) = idat(frame(4, 3, 20));
+
+        let control = |seq: u32, w: u32, h: u32, delay: u16, dispose: u8|
+        {
+            let mut c = seq.to_be_bytes().to_vec();
+            for v in &[w, h, 2, 1]
+            {
+                c.extend_from_slice(&v.to_be_bytes());
+            }
+            c.extend_from_slice(&delay.to_be_bytes());
+            c.extend_from_slice(&100u16.to_be_bytes());
+            c.extend_from_slice(&[dispose, 0]);
+            c
+        };
+
+        let mut fdat = 2u32.to_be_bytes().to_vec();
+        fdat.extend_from_slice(&second);
+
+        chunks::write(&[
+            Chunk::new(b"IHDR", header),
+            Chunk::new(b"acTL", vec![0, 0, 0, 2, 0, 0, 0, 3]),
+            Chunk::new(b"fcTL", control(0, 8, 6, 7, 0)),
+            Chunk::new(b"IDAT", first),
+            Chunk::new(b"fcTL", control(1, 4, 3, 9, 1)),
+            Chunk::new(b"fdAT", fdat),
+            Chunk::new(b"IEND", Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn frames_round_trip()
+    {
+        let bytes = apng();
+        let apng = Apng::read(&bytes).unwrap().unwrap();
+        let frames = apng.frames().unwrap();
+
+        assert_eq!(apng.sizes(), vec![(8, 6), (4, 3)]);
+        assert_eq!(frames[1].get_pixel(3, 2).data, [20, 3, 2, 255]);
+
+        // a column, as the frames differ in width
+        let mut stacked = stack(&frames).unwrap();
+        assert_eq!(stacked.dimensions(), (1, 60));
+
+        stacked.put_pixel(0, 59, Rgba { data: [1, 2, 3, 4] });
+
+        let path = temp_dir().join("stag-animation-test.png");
+        apng.save(&unstack(&stacked, &apng.sizes()), &path).unwrap();
+
+        let written = fs::read(&path).unwrap();
+        let read = Apng::read(&written).unwrap().unwrap();
+        let frames = read.frames().unwrap();
+
+        assert_eq!(frames[1].get_pixel(3, 2).data, [1, 2, 3, 4]);
+        assert_eq!(frames[0].get_pixel(7, 5).data, [10, 7, 5, 255]);
+        assert_eq!(read.plays, 3);
+        assert_eq!(read.frames[1].control, apng.frames[1].control);
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::{self, load_from_memory, DynamicImage, FilterType, GenericImage};
+use image::jpeg::JPEGEncoder;
+use image::math::nq::NeuQuant;
+use rand::Rng;
+
+/// How hard NeuQuant samples the image, 1 is every pixel and slowest
+const QUANT_SAMPLING: i32 = 10;
+
+/// The largest rescale factor, past which the scaled image is only more
+/// memory and no more of an attack
+pub const MAX_RESCALE: f32 = 4.0;
+
+/// A transform images commonly go through after they are shared
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Op
+{
+    /// Cut this many pixels off every edge
+    Crop(u32),
+    /// Scale by this factor, then back to the original size
+    Rescale(f32),
+    /// Save as a JPEG of this quality and open it again
+    Jpeg(u8),
+    /// Add uniform noise of up to this much to every sample
+    Noise(u8),
+    /// Reduce to a palette of this many colours, as PNG optimisers do
+    Requantize(usize),
+}
+
+/// The transforms tried when none are asked for
+pub const DEFAULT: &str = "crop,rescale,jpeg:90,noise,png-requantize";
+
+impl FromStr for Op
+{
+    type Err = ();
+
+    /// Parse `NAME` or `NAME:PARAMETER`, e.g. `jpeg:90`
+    fn from_str(s: &str) -> Result<Op, ()>
+    {
+        let (name, parameter) = match s.find(':')
+        {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        fn or<T: FromStr>(parameter: Option<&str>, default: T) -> Result<T, ()>
+        {
+            parameter.map_or(Ok(default), |p| p.parse().map_err(|_| ()))
+        }
+
+        let op = match name
+        {
+            "crop" => Op::Crop(or(parameter, 1)?),
+            "rescale" => Op::Rescale(or(parameter, 0.75)?),
+            "jpeg" => Op::Jpeg(or(parameter, 90)?),
+            "noise" => Op::Noise(or(parameter, 1)?),
+            "png-requantize" => Op::Requantize(or(parameter, 256)?),
+            _ => return Err(()),
+        };
+
+        match op
+        {
+            Op::Rescale(f) if f.is_nan() || f <= 0.0 || f > MAX_RESCALE => Err(()),
+            Op::Jpeg(q) if q == 0 || q > 100 => Err(()),
+            Op::Requantize(n) if !(2..=256).contains(&n) => Err(()),
+            _ => Ok(op),
+        }
+    }
+}
+
+impl fmt::Display for Op
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Op::Crop(n) => write!(f, "crop:{}", n),
+            Op::Rescale(s) => write!(f, "rescale:{}", s),
+            Op::Jpeg(q) => write!(f, "jpeg:{}", q),
+            Op::Noise(n) => write!(f, "noise:{}", n),
+            Op::Requantize(n) => write!(f, "png-requantize:{}", n),
+        }
+    }
+}
+
+/// Apply a transform, giving an image with the same pixel type back.
+/// Formats without alpha, like JPEG, leave it fully opaque.
+pub fn apply<R: Rng>(op: Op, source: &DynamicImage, rng: &mut R) -> DynamicImage
+{
+    let (width, height) = source.dimensions();
+
+    // no transform changes an image with no pixels
+    if width == 0 || height == 0
+    {
+        return source.clone();
+    }
+
+    let result = match op
+    {
+        Op::Crop(n) =>
+        {
+            let n = n.min((width - 1) / 2).min((height - 1) / 2);
+
+            source.clone().crop(n, n, width - 2 * n, height - 2 * n)
+        },
+        Op::Rescale(factor) =>
+        {
+            let scaled = |d: u32| ((d as f32 * factor).round() as u32).max(1);
+
+            source.resize_exact(scaled(width), scaled(height), FilterType::Triangle)
+                .resize_exact(width, height, FilterType::Triangle)
+        },
+        Op::Jpeg(quality) =>
+        {
+            let rgb = source.to_rgb();
+            let mut buffer = Vec::new();
+
+            JPEGEncoder::new_with_quality(&mut buffer, quality)
+                .encode(&rgb, width, height, image::RGB(8))
+                .expect("encoding into memory cannot fail");
+
+            load_from_memory(&buffer).expect("a JPEG just encoded can be decoded")
+        },
+        Op::Noise(amplitude) =>
+        {
+            let mut noisy = source.to_rgba();
+            let amplitude = amplitude as i32;
+
+            for s in noisy.iter_mut()
+            {
+                *s = (*s as i32 + rng.gen_range(-amplitude, amplitude + 1)).clamp(0, 255) as u8;
+            }
+
+            DynamicImage::ImageRgba8(noisy)
+        },
+        Op::Requantize(colors) =>
+        {
+            let mut quantized = source.to_rgba();
+            let quant = NeuQuant::new(QUANT_SAMPLING, colors, &quantized);
+
+            for pixel in quantized.chunks_mut(4)
+            {
+                quant.map_pixel(pixel);
+            }
+
+            DynamicImage::ImageRgba8(quantized)
+        },
+    };
+
+    same_type(source, &result)
+}
+
+/// Convert an image to the pixel type of another
+fn same_type(like: &DynamicImage, image: &DynamicImage) -> DynamicImage
+{
+    match *like
+    {
+        DynamicImage::ImageRgb8(_) => DynamicImage::ImageRgb8(image.to_rgb()),
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLumaA8(image.to_luma_alpha()),
+        DynamicImage::ImageLuma8(_) => DynamicImage::ImageLuma8(image.to_luma()),
+        _ => DynamicImage::ImageRgba8(image.to_rgba()),
+    }
+}
+
+/// The share of bits that differ between what was written and what is
+/// left, bits no longer there counting as errors
+pub fn bit_error_rate(written: &[u8], left: &[u8]) -> f64
+{
+    if written.is_empty()
+    {
+        return 0.0;
+    }
+
+    let errors = written.iter()
+        .enumerate()
+        .map(|(i, w)| left.get(i).map_or(8, |l| (w ^ l).count_ones()))
+        .sum::<u32>();
+
+    errors as f64 / (written.len() * 8) as f64
+}
+
+#[cfg(test)]
+mod test
+{
+    use image::{ImageBuffer, LumaA};
+
+    use analysis::test::rng;
+
+    use super::*;
+
+    #[test]
+    fn parse_ops()
+    {
+        assert_eq!("jpeg:75".parse(), Ok(Op::Jpeg(75)));
+        assert_eq!("crop".parse(), Ok(Op::Crop(1)));
+        assert_eq!("png-requantize:16".parse(), Ok(Op::Requantize(16)));
+        assert!("jpeg:0".parse::<Op>().is_err());
+        assert!("rescale:4".parse::<Op>().is_ok());
+        assert!("rescale:1e9".parse::<Op>().is_err());
+        assert!("rescale:inf".parse::<Op>().is_err());
+        assert!("blur".parse::<Op>().is_err());
+
+        for op in DEFAULT.split(',')
+        {
+            let op = op.parse::<Op>().unwrap();
+            assert_eq!(op.to_string().parse(), Ok(op));
+        }
+    }
+
+    #[test]
+    fn keeps_pixel_type()
+    {
+        let source = DynamicImage::ImageLumaA8(ImageBuffer::from_fn(32, 32, |x, y|
+            LumaA { data: [(x * 8) as u8, (y * 8) as u8] }));
+
+        for op in DEFAULT.split(',')
+        {
+            let result = apply(op.parse().unwrap(), &source, &mut rng());
+
+            match result
+            {
+                DynamicImage::ImageLumaA8(_) => {},
+                _ => panic!("{} changed the pixel type", op),
+            }
+        }
+
+        let cropped = apply(Op::Crop(2), &source, &mut rng());
+        assert_eq!(cropped.dimensions(), (28, 28));
+
+        let empty = DynamicImage::ImageLumaA8(ImageBuffer::new(0, 3));
+        for op in DEFAULT.split(',')
+        {
+            assert_eq!(apply(op.parse().unwrap(), &empty, &mut rng()).dimensions(), (0, 3));
+        }
+    }
+
+    #[test]
+    fn error_rate()
+    {
+        assert_eq!(bit_error_rate(&[0, 0], &[0, 0]), 0.0);
+        assert_eq!(bit_error_rate(&[0xff, 0], &[0x0f, 0]), 0.25);
+        assert_eq!(bit_error_rate(&[0, 0], &[0]), 0.5);
+    }
+}
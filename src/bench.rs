@@ -0,0 +1,245 @@
+use std::fmt::Write;
+
+use analysis::{chi2, rs, spa};
+
+/// Prefixes the chi-square attack is run over
+const CHI2_STEPS: usize = 10;
+
+/// A detector, scoring how likely a channel is to carry a message
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Detector
+{
+    Chi2,
+    Rs,
+    Spa,
+}
+
+pub const DETECTORS: [Detector; 3] = [Detector::Chi2, Detector::Rs, Detector::Spa];
+
+impl Detector
+{
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Detector::Chi2 => "chi2",
+            Detector::Rs => "rs",
+            Detector::Spa => "spa",
+        }
+    }
+
+    /// Score a channel from 0 to 1, higher meaning more likely stego
+    pub fn score(self, plane: &[u8], width: usize) -> f64
+    {
+        let score = match self
+        {
+            Detector::Chi2 => chi2::sequential(plane, CHI2_STEPS)
+                .iter()
+                .filter_map(|r| r.1.probability)
+                .fold(0.0, f64::max),
+            Detector::Rs => rs::rs(plane, width).unwrap_or(0.0),
+            Detector::Spa => spa::spa(plane, width).unwrap_or(0.0),
+        };
+
+        score.clamp(0.0, 1.0)
+    }
+}
+
+/// The detector scores for one image, a cover when `rate` is 0
+#[derive(Clone, Debug)]
+pub struct Trial
+{
+    /// The codec and mode, e.g. `rgba:alpha`
+    pub mode: String,
+    /// The share of the mode's capacity filled
+    pub rate: f64,
+    /// A score from each of `DETECTORS`
+    pub scores: [f64; 3],
+    /// Against the cover, infinite for covers
+    pub psnr: f64,
+}
+
+/// How well one detector separates covers from stego images at one rate
+#[derive(Clone, Debug)]
+pub struct Summary
+{
+    pub mode: String,
+    pub rate: f64,
+    pub detector: &'static str,
+    /// Stego images tested, each against every cover
+    pub images: usize,
+    pub auc: f64,
+    /// False and true positive rates at each threshold, highest first
+    pub roc: Vec<(f64, f64)>,
+    /// Mean PSNR of the stego images
+    pub psnr: f64,
+}
+
+/// Summarise trials for every mode, rate and detector
+pub fn summarize(trials: &[Trial]) -> Vec<Summary>
+{
+    let mut groups = trials.iter()
+        .filter(|t| t.rate > 0.0)
+        .map(|t| (t.mode.clone(), t.rate))
+        .collect::<Vec<_>>();
+
+    groups.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    groups.dedup();
+
+    let mut summaries = Vec::new();
+
+    for (mode, rate) in groups
+    {
+        let covers = trials.iter().filter(|t| t.mode == mode && t.rate == 0.0).collect::<Vec<_>>();
+        let stegos = trials.iter().filter(|t| t.mode == mode && t.rate == rate).collect::<Vec<_>>();
+
+        let finite = stegos.iter().map(|t| t.psnr).filter(|p| p.is_finite()).collect::<Vec<_>>();
+        let psnr = if finite.is_empty()
+        {
+            f64::INFINITY
+        }
+        else
+        {
+            finite.iter().sum::<f64>() / finite.len() as f64
+        };
+
+        for (d, detector) in DETECTORS.iter().enumerate()
+        {
+            let negatives = covers.iter().map(|t| t.scores[d]).collect::<Vec<_>>();
+            let positives = stegos.iter().map(|t| t.scores[d]).collect::<Vec<_>>();
+
+            summaries.push(Summary
+            {
+                mode: mode.clone(),
+                rate,
+                detector: detector.name(),
+                images: positives.len(),
+                auc: auc(&negatives, &positives),
+                roc: roc(&negatives, &positives),
+                psnr,
+            });
+        }
+    }
+
+    summaries
+}
+
+/// The area under the ROC curve: the chance a stego image scores higher
+/// than a cover, ties counting half
+pub fn auc(negatives: &[f64], positives: &[f64]) -> f64
+{
+    if negatives.is_empty() || positives.is_empty()
+    {
+        return 0.5;
+    }
+
+    let wins = positives.iter()
+        .flat_map(|p| negatives.iter().map(move |n| if p > n { 1.0 } else if p == n { 0.5 } else { 0.0 }))
+        .sum::<f64>();
+
+    wins / (negatives.len() * positives.len()) as f64
+}
+
+/// The ROC curve, a point for each distinct score used as a threshold,
+/// from (0, 0) to (1, 1)
+pub fn roc(negatives: &[f64], positives: &[f64]) -> Vec<(f64, f64)>
+{
+    let mut thresholds = negatives.iter().chain(positives).cloned().collect::<Vec<_>>();
+    thresholds.sort_by(|a, b| b.total_cmp(a));
+    thresholds.dedup();
+
+    let rate = |scores: &[f64], t: f64|
+        scores.iter().filter(|&&s| s >= t).count() as f64 / scores.len().max(1) as f64;
+
+    let mut curve = ::std::iter::once((0.0, 0.0))
+        .chain(thresholds.into_iter().map(|t| (rate(negatives, t), rate(positives, t))))
+        .collect::<Vec<_>>();
+
+    // the lowest threshold reaches (1, 1) unless a side has no scores
+    if curve.last() != Some(&(1.0, 1.0))
+    {
+        curve.push((1.0, 1.0));
+    }
+
+    curve
+}
+
+/// One row for each summary, leaving out the ROC curves
+pub fn to_csv(summaries: &[Summary]) -> String
+{
+    let mut csv = String::from("mode,rate,detector,images,auc,psnr\n");
+
+    for s in summaries
+    {
+        let _ = writeln!(csv, "{},{},{},{},{:.4},{:.2}",
+            s.mode, s.rate, s.detector, s.images, s.auc, s.psnr);
+    }
+
+    csv
+}
+
+/// An array of summaries with their ROC curves
+pub fn to_json(summaries: &[Summary]) -> String
+{
+    let number = |x: f64| if x.is_finite() { format!("{}", x) } else { "null".to_string() };
+
+    let objects = summaries.iter().map(|s|
+    {
+        let roc = s.roc.iter()
+            .map(|&(f, t)| format!("[{},{}]", number(f), number(t)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!("  {{\"mode\":\"{}\",\"rate\":{},\"detector\":\"{}\",\"images\":{},\"auc\":{},\"psnr\":{},\"roc\":[{}]}}",
+            s.mode, number(s.rate), s.detector, s.images, number(s.auc), number(s.psnr), roc)
+    }).collect::<Vec<_>>();
+
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn auc_and_roc()
+    {
+        assert_eq!(auc(&[0.1, 0.2], &[0.8, 0.9]), 1.0);
+        assert_eq!(auc(&[0.5, 0.5], &[0.5, 0.5]), 0.5);
+        assert_eq!(auc(&[0.1, 0.9], &[0.5, 0.95]), 0.75);
+
+        assert_eq!(roc(&[0.1], &[0.9]), vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+        assert_eq!(roc(&[], &[0.9]), vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn summarize_trials()
+    {
+        let trial = |rate, score, psnr| Trial
+        {
+            mode: "rgba:alpha".to_string(),
+            rate,
+            scores: [score, 0.0, 0.0],
+            psnr,
+        };
+
+        let trials = vec![
+            trial(0.0, 0.1, f64::INFINITY),
+            trial(0.0, 0.2, f64::INFINITY),
+            trial(0.5, 0.9, 50.0),
+            trial(0.5, 0.3, 52.0),
+        ];
+
+        let summaries = summarize(&trials);
+
+        assert_eq!(summaries.len(), 3);
+        assert_eq!(summaries[0].detector, "chi2");
+        assert_eq!(summaries[0].auc, 1.0);
+        assert_eq!(summaries[0].psnr, 51.0);
+        assert_eq!(summaries[1].auc, 0.5);
+
+        assert!(to_csv(&summaries).contains("rgba:alpha,0.5,chi2,2,1.0000,51.00"));
+        assert!(to_json(&summaries).contains("\"auc\":1,"));
+    }
+}
@@ -0,0 +1,229 @@
+use forensics::crc32;
+
+/// The PNG file signature
+pub const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A PNG chunk, without its length and CRC
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk
+{
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk
+{
+    pub fn new(kind: &[u8; 4], data: Vec<u8>) -> Chunk
+    {
+        Chunk
+        {
+            kind: *kind,
+            data,
+        }
+    }
+
+    /// Whether a decoder has to understand the chunk, which the case of the
+    /// first letter says
+    pub fn is_critical(&self) -> bool
+    {
+        self.kind[0].is_ascii_uppercase()
+    }
+}
+
+/// Split a PNG file into its chunks, up to and including IEND
+pub fn read(bytes: &[u8]) -> Result<Vec<Chunk>, &'static str>
+{
+    if !bytes.starts_with(SIGNATURE)
+    {
+        return Err("Not a PNG file");
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = SIGNATURE.len();
+
+    while offset + 12 <= bytes.len()
+    {
+        let len = u32::from_be_bytes([bytes[offset], bytes[offset + 1],
+            bytes[offset + 2], bytes[offset + 3]]) as usize;
+
+        if offset + 12 + len > bytes.len()
+        {
+            return Err("A PNG chunk runs past the end of the file");
+        }
+
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&bytes[offset + 4..offset + 8]);
+
+        chunks.push(Chunk::new(&kind, bytes[offset + 8..offset + 8 + len].to_vec()));
+        offset += 12 + len;
+
+        if &kind == b"IEND"
+        {
+            return Ok(chunks);
+        }
+    }
+
+    Err("The PNG file ends before an IEND chunk")
+}
+
+/// Put chunks together into a PNG file
+pub fn write(chunks: &[Chunk]) -> Vec<u8>
+{
+    let mut bytes = SIGNATURE.to_vec();
+
+    for chunk in chunks
+    {
+        let start = bytes.len() + 4;
+
+        bytes.extend_from_slice(&(chunk.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&chunk.kind);
+        bytes.extend_from_slice(&chunk.data);
+
+        let crc = crc32(&bytes[start..]);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    bytes
+}
+
+/// Ancillary chunks that still hold for new pixels of the same type, though
+/// their names do not say they are safe to copy
+const KNOWN: &[&[u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", // colour space
+    b"bKGD", b"hIST", b"pHYs", b"sPLT", b"tIME", // and the rest
+    b"tEXt", b"zTXt", b"iTXt", b"eXIf",
+];
+
+/// The ancillary chunks of a PNG: its colour profile, text, EXIF, resolution
+/// and the like, kept to be put into a PNG of new pixels so that it carries
+/// the same metadata
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ancillary
+{
+    /// Chunks before PLTE, or before IDAT when there is none
+    before_palette: Vec<Chunk>,
+    /// Chunks between PLTE and IDAT
+    before_data: Vec<Chunk>,
+    /// Chunks after IDAT
+    after_data: Vec<Chunk>,
+}
+
+impl Ancillary
+{
+    /// Keep the chunks that still hold once the pixels change: those known
+    /// here, and any other whose name says it is safe to copy. tRNS is left
+    /// to whatever writes the pixels.
+    pub fn new(chunks: &[Chunk]) -> Ancillary
+    {
+        let mut ancillary = Ancillary::default();
+        let (mut palette, mut data) = (false, false);
+
+        for chunk in chunks
+        {
+            match &chunk.kind
+            {
+                b"PLTE" => palette = true,
+                b"IDAT" => data = true,
+                b"tRNS" => {},
+                kind if chunk.is_critical() || !(KNOWN.contains(&kind) || kind[3].is_ascii_lowercase()) => {},
+                _ if data => ancillary.after_data.push(chunk.clone()),
+                _ if palette => ancillary.before_data.push(chunk.clone()),
+                _ => ancillary.before_palette.push(chunk.clone()),
+            }
+        }
+
+        ancillary
+    }
+
+    /// Put the chunks into a PNG file, in the places they came from
+    pub fn insert(&self, png: &[u8]) -> Result<Vec<u8>, &'static str>
+    {
+        let mut out = Vec::new();
+        let (mut palette, mut data) = (false, false);
+
+        for chunk in read(png)?
+        {
+            match &chunk.kind
+            {
+                b"PLTE" if !palette =>
+                {
+                    out.extend(self.before_palette.iter().cloned());
+                    palette = true;
+                },
+                b"IDAT" if !data =>
+                {
+                    if !palette
+                    {
+                        out.extend(self.before_palette.iter().cloned());
+                        palette = true;
+                    }
+
+                    out.extend(self.before_data.iter().cloned());
+                    data = true;
+                },
+                b"IEND" => out.extend(self.after_data.iter().cloned()),
+                _ => {},
+            }
+
+            out.push(chunk);
+        }
+
+        Ok(write(&out))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+        let chunks = vec![
+            Chunk::new(b"IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            Chunk::new(b"tEXt", b"Comment\0hello".to_vec()),
+            Chunk::new(b"IEND", Vec::new()),
+        ];
+
+        let bytes = write(&chunks);
+
+        assert_eq!(read(&bytes), Ok(chunks.clone()));
+        assert!(chunks[0].is_critical() && !chunks[1].is_critical());
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn keeps_ancillary_chunks_in_place()
+    {
+        let header = Chunk::new(b"IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0]);
+        let source = vec![
+            header.clone(),
+            Chunk::new(b"gAMA", vec![0, 0, 177, 143]),
+            Chunk::new(b"PLTE", vec![0; 6]),
+            Chunk::new(b"tRNS", vec![0]),
+            Chunk::new(b"pHYs", vec![0, 0, 11, 19, 0, 0, 11, 19, 1]),
+            // unknown, and not safe to copy
+            Chunk::new(b"abCD", vec![1]),
+            Chunk::new(b"IDAT", vec![1]),
+            Chunk::new(b"tEXt", b"Comment\0hello".to_vec()),
+            Chunk::new(b"IEND", Vec::new()),
+        ];
+
+        let ancillary = Ancillary::new(&source);
+        let written = vec![
+            header,
+            Chunk::new(b"PLTE", vec![1; 6]),
+            Chunk::new(b"IDAT", vec![2]),
+            Chunk::new(b"IDAT", vec![3]),
+            Chunk::new(b"IEND", Vec::new()),
+        ];
+
+        let kinds = read(&ancillary.insert(&write(&written)).unwrap()).unwrap()
+            .into_iter()
+            .map(|c| c.kind)
+            .collect::<Vec<_>>();
+
+        assert_eq!(kinds, vec![*b"IHDR", *b"gAMA", *b"PLTE", *b"pHYs", *b"IDAT", *b"IDAT", *b"tEXt", *b"IEND"]);
+    }
+}
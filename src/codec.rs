@@ -8,7 +8,17 @@ pub trait Codec
     /// The input or source type that it incodes into
     type Input;
     /// The input mode or modes that it supports, usually an enum
-    type Mode: Default + FromStr;
+    type Mode: Default + FromStr + Copy;
+
+    /// The number of samples that make up each pixel
+    const CHANNELS: usize;
+    /// The name of each channel, in sample order
+    const CHANNEL_NAMES: &'static [&'static str];
+    /// The name of each mode, as parsed by `Mode::from_str`
+    const MODE_NAMES: &'static [&'static str];
+    /// Whether the samples are pixel values, rather than bits held somewhere
+    /// else in the file, so that detectors and heat maps apply to them
+    const PIXELS: bool = true;
 
     /// Encode a payload into an input
     fn encode<R: Rng>(
@@ -30,4 +40,40 @@ pub trait Codec
     {
         None
     }
+
+    /// The channels a mode encodes into, in the order they are written
+    fn channels(mode: Self::Mode) -> &'static [usize];
+
+    /// The width and height of an input, in pixels
+    fn dimensions(source: &Self::Input) -> (u32, u32);
+
+    /// The raw, interleaved samples of an input
+    fn samples(source: &Self::Input) -> &[u8];
+    /// The raw, interleaved samples of an input, mutably
+    fn samples_mut(source: &mut Self::Input) -> &mut [u8];
+
+    /// Write bytes into the samples at `positions`, least significant bit
+    /// first. Codecs whose samples are not plain values override this.
+    fn embed<R: Rng>(
+        source: &mut Self::Input,
+        positions: &[usize],
+        bytes: &[u8],
+        rng: &mut R)
+    {
+        ::utils::embed(Self::samples_mut(source), positions, bytes, rng)
+    }
+
+    /// The sample positions a mode encodes into, in the order they are
+    /// written by `encode`
+    fn layout(
+        source: &Self::Input,
+        mode: Self::Mode) -> Vec<usize>
+    {
+        let channels = Self::channels(mode);
+        let pixels = Self::samples(source).len() / Self::CHANNELS;
+
+        (0..pixels)
+            .flat_map(|p| channels.iter().map(move |c| p * Self::CHANNELS + c))
+            .collect()
+    }
 }
@@ -0,0 +1,325 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The longest name an entry can have, as its length is stored in 16 bits
+pub const MAX_NAME: usize = u16::MAX as usize;
+
+/// A file stored in a container
+#[derive(Debug, PartialEq)]
+pub struct Entry
+{
+    /// The file's path within the container, separated by '/'
+    pub name: String,
+    /// Modification time in seconds since the unix epoch
+    pub modified: u64,
+    /// Unix permission bits
+    pub permissions: u32,
+    pub data: Vec<u8>,
+}
+
+impl Entry
+{
+    /// Read a file into an entry with the given name
+    pub fn read(path: &Path, name: String) -> io::Result<Entry>
+    {
+        if name.len() > MAX_NAME
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("the name of {} is longer than {} bytes", path.display(), MAX_NAME)));
+        }
+
+        let metadata = fs::metadata(path)?;
+
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        let modified = metadata.modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(Entry
+        {
+            name,
+            modified,
+            permissions: permissions(&metadata),
+            data,
+        })
+    }
+
+    /// Write the entry out under `dir`, restoring its modification time and
+    /// permissions
+    pub fn write(&self, dir: &Path) -> io::Result<()>
+    {
+        if !is_safe(&self.name)
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("refusing to write to {}", self.name)));
+        }
+
+        let path = dir.join(&self.name);
+
+        if let Some(parent) = path.parent()
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = File::create(&path)?;
+        file.write_all(&self.data)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(self.modified))?;
+
+        set_permissions(&path, self.permissions)
+    }
+}
+
+/// Collect entries from a file, or from every file under a directory.
+/// Files under a directory are named relative to it.
+pub fn collect(path: &Path) -> io::Result<Vec<Entry>>
+{
+    let mut entries = Vec::new();
+
+    if path.is_dir()
+    {
+        walk(path, "", &mut entries)?;
+    }
+    else
+    {
+        let name = match path.file_name()
+        {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "path has no file name")),
+        };
+
+        entries.push(Entry::read(path, name)?);
+    }
+
+    Ok(entries)
+}
+
+fn walk(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> io::Result<()>
+{
+    let mut children = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    children.sort_by_key(|c| c.file_name());
+
+    for child in children
+    {
+        let name = format!("{}{}", prefix, child.file_name().to_string_lossy());
+
+        if child.file_type()?.is_dir()
+        {
+            walk(&child.path(), &format!("{}/", name), entries)?;
+        }
+        else
+        {
+            entries.push(Entry::read(&child.path(), name)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Serialize entries: a count, then for each entry its name, modification
+/// time, permissions and data, all lengths big-endian
+pub fn pack(entries: &[Entry]) -> Vec<u8>
+{
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    for entry in entries
+    {
+        bytes.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(entry.name.as_bytes());
+        bytes.extend_from_slice(&entry.modified.to_be_bytes());
+        bytes.extend_from_slice(&entry.permissions.to_be_bytes());
+        bytes.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&entry.data);
+    }
+
+    bytes
+}
+
+/// Parse bytes written by `pack`
+pub fn unpack(bytes: &[u8]) -> Option<Vec<Entry>>
+{
+    let mut reader = Reader { bytes };
+
+    let count = u32::from_be_bytes(reader.array()?);
+    let mut entries = Vec::new();
+
+    for _ in 0..count
+    {
+        let len = u16::from_be_bytes(reader.array()?) as usize;
+        let name = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+        let modified = u64::from_be_bytes(reader.array()?);
+        let permissions = u32::from_be_bytes(reader.array()?);
+        let size = u64::from_be_bytes(reader.array()?) as usize;
+        let data = reader.take(size)?.to_vec();
+
+        entries.push(Entry
+        {
+            name,
+            modified,
+            permissions,
+            data,
+        });
+    }
+
+    if reader.bytes.is_empty()
+    {
+        Some(entries)
+    }
+    else
+    {
+        None
+    }
+}
+
+struct Reader<'a>
+{
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a>
+{
+    fn take(&mut self, len: usize) -> Option<&'a [u8]>
+    {
+        if len > self.bytes.len()
+        {
+            return None;
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Some(taken)
+    }
+
+    fn array<A: Default + AsMut<[u8]>>(&mut self) -> Option<A>
+    {
+        let mut array = A::default();
+        let len = array.as_mut().len();
+        array.as_mut().copy_from_slice(self.take(len)?);
+
+        Some(array)
+    }
+}
+
+/// Whether a name stays inside the directory it's extracted to
+fn is_safe(name: &str) -> bool
+{
+    !name.is_empty() && Path::new(name).components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32
+{
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32
+{
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+/// The mode comes from the image, so only the read, write and execute bits
+/// are given to the file, never setuid, setgid or sticky
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()>
+{
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()>
+{
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+        let entries = vec![
+            Entry
+            {
+                name: "doc.txt".to_string(),
+                modified: 1_500_000_000,
+                permissions: 0o644,
+                data: b"a document".to_vec(),
+            },
+            Entry
+            {
+                name: "sig/doc.txt.sig".to_string(),
+                modified: 1_500_000_001,
+                permissions: 0o600,
+                data: vec![1, 2, 3],
+            },
+        ];
+
+        let bytes = pack(&entries);
+
+        assert_eq!(unpack(&bytes), Some(entries));
+        assert_eq!(unpack(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn unsafe_names()
+    {
+        assert!(is_safe("a/b.txt"));
+        assert!(!is_safe("../b.txt"));
+        assert!(!is_safe("/etc/passwd"));
+        assert!(!is_safe(""));
+    }
+
+    #[test]
+    fn long_names()
+    {
+        let path = ::std::env::temp_dir().join("stag-container-test.txt");
+        fs::write(&path, b"data").unwrap();
+
+        assert!(Entry::read(&path, "a".repeat(MAX_NAME)).is_ok());
+        assert!(Entry::read(&path, "a".repeat(MAX_NAME + 1)).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_special_bits()
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = ::std::env::temp_dir().join("stag-container-mode-test");
+        let entry = Entry
+        {
+            name: "setuid".to_string(),
+            modified: 1_500_000_000,
+            permissions: 0o4755,
+            data: Vec::new(),
+        };
+
+        entry.write(&dir).unwrap();
+        let mode = fs::metadata(dir.join("setuid")).unwrap().permissions().mode();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+}
@@ -0,0 +1,216 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use rand::{ChaChaRng, Rng, SeedableRng};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The number of PBKDF2 rounds used to stretch a password
+const ROUNDS: u32 = 100_000;
+
+/// Bytes of nonce before, and tag after, encrypted data
+pub const NONCE: usize = 16;
+const TAG: usize = 32;
+
+/// How many bytes `encrypt` adds
+pub const OVERHEAD: usize = NONCE + TAG;
+
+/// Bytes of random salt stored with everything a password opens, so that
+/// no two images share a master key
+pub const SALT: usize = 16;
+
+/// Stretch a password into a master key
+pub fn master_key(password: &str, salt: &[u8]) -> [u8; 32]
+{
+    let mut key = [0u8; 32];
+    ::pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, ROUNDS, &mut key);
+
+    key
+}
+
+/// A new random salt
+pub fn salt<R: Rng>(rng: &mut R) -> [u8; SALT]
+{
+    let mut salt = [0u8; SALT];
+    rng.fill_bytes(&mut salt);
+
+    salt
+}
+
+/// Encrypt data under a password: a random salt, then the data encrypted
+/// with the master key it gives
+pub fn encrypt_password<R: Rng>(password: &str, data: &[u8], rng: &mut R) -> Vec<u8>
+{
+    let mut bytes = salt(rng).to_vec();
+    let sealed = encrypt(&master_key(password, &bytes), data, rng);
+    bytes.extend_from_slice(&sealed);
+
+    bytes
+}
+
+/// Check and decrypt bytes written by `encrypt_password`
+pub fn decrypt_password(password: &str, bytes: &[u8]) -> Option<Vec<u8>>
+{
+    if bytes.len() < SALT
+    {
+        return None;
+    }
+
+    let (salt, sealed) = bytes.split_at(SALT);
+
+    decrypt(&master_key(password, salt), sealed)
+}
+
+/// Derive a key for one purpose from a master key
+pub fn subkey(master: &[u8; 32], label: &str, data: &[u8]) -> [u8; 32]
+{
+    let mut mac = HmacSha256::new_from_slice(master)
+        .expect("HMAC accepts keys of any length");
+    mac.update(label.as_bytes());
+    mac.update(data);
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&mac.finalize().into_bytes());
+
+    key
+}
+
+/// Authenticate some data
+pub fn tag(key: &[u8; 32], data: &[u8]) -> [u8; 32]
+{
+    subkey(key, "", data)
+}
+
+/// Check a tag from `tag` in constant time
+pub fn verify(key: &[u8; 32], data: &[u8], tag: &[u8]) -> bool
+{
+    let mut mac = HmacSha256::new_from_slice(key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(data);
+
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Encrypt or decrypt data in place, XORing it with HMAC-SHA256 blocks of
+/// a counter
+pub fn apply_keystream(key: &[u8; 32], data: &mut [u8])
+{
+    for (counter, chunk) in data.chunks_mut(32).enumerate()
+    {
+        let block = subkey(key, "", &(counter as u64).to_be_bytes());
+
+        for (byte, k) in chunk.iter_mut().zip(block.iter())
+        {
+            *byte ^= k;
+        }
+    }
+}
+
+/// Encrypt and authenticate data: a random nonce, the data XORed with a
+/// keystream, then a tag over both
+pub fn encrypt<R: Rng>(master: &[u8; 32], data: &[u8], rng: &mut R) -> Vec<u8>
+{
+    let mut bytes = Vec::with_capacity(data.len() + OVERHEAD);
+
+    bytes.extend((0..NONCE).map(|_| rng.gen::<u8>()));
+    bytes.extend_from_slice(data);
+
+    let (nonce, body) = bytes.split_at_mut(NONCE);
+    apply_keystream(&subkey(master, "encrypt", nonce), body);
+
+    let tag = tag(&subkey(master, "tag", &bytes[..NONCE]), &bytes);
+    bytes.extend_from_slice(&tag);
+
+    bytes
+}
+
+/// Check and decrypt bytes written by `encrypt`
+pub fn decrypt(master: &[u8; 32], bytes: &[u8]) -> Option<Vec<u8>>
+{
+    if bytes.len() < OVERHEAD
+    {
+        return None;
+    }
+
+    let (sealed, tag) = bytes.split_at(bytes.len() - TAG);
+    let nonce = &sealed[..NONCE];
+
+    if !verify(&subkey(master, "tag", nonce), sealed, tag)
+    {
+        return None;
+    }
+
+    let mut data = sealed[NONCE..].to_vec();
+    apply_keystream(&subkey(master, "encrypt", nonce), &mut data);
+
+    Some(data)
+}
+
+/// Shuffle positions into an order only someone with the key can reproduce
+pub fn shuffle<T>(positions: &mut [T], key: &[u8; 32])
+{
+    let seed = key.chunks(4)
+        .map(|ch| u32::from_le_bytes([ch[0], ch[1], ch[2], ch[3]]))
+        .collect::<Vec<_>>();
+
+    ChaChaRng::from_seed(&seed[..]).shuffle(positions);
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn keystream()
+    {
+        let key = [7u8; 32];
+        let plain = (0..100).collect::<Vec<u8>>();
+        let mut data = plain.clone();
+
+        apply_keystream(&key, &mut data);
+        assert!(data != plain);
+
+        apply_keystream(&key, &mut data);
+        assert_eq!(data, plain);
+    }
+
+    #[test]
+    fn encrypt_round_trip()
+    {
+        let mut rng = ::rand::StdRng::new().unwrap();
+        let bytes = encrypt(&[1u8; 32], b"payload", &mut rng);
+
+        assert_eq!(decrypt(&[1u8; 32], &bytes), Some(b"payload".to_vec()));
+        assert_eq!(decrypt(&[2u8; 32], &bytes), None);
+    }
+
+    #[test]
+    fn salted_passwords()
+    {
+        let mut rng = ::rand::StdRng::new().unwrap();
+        let a = encrypt_password("hunter2", b"payload", &mut rng);
+        let b = encrypt_password("hunter2", b"payload", &mut rng);
+
+        assert!(a[..SALT] != b[..SALT]);
+        assert!(master_key("hunter2", &a[..SALT]) != master_key("hunter2", &b[..SALT]));
+        assert_eq!(decrypt_password("hunter2", &a), Some(b"payload".to_vec()));
+        assert_eq!(decrypt_password("hunter2", &b), Some(b"payload".to_vec()));
+        assert_eq!(decrypt_password("hunter3", &a), None);
+        assert_eq!(decrypt_password("hunter2", &a[..SALT - 1]), None);
+    }
+
+    #[test]
+    fn shuffle_is_keyed()
+    {
+        let mut a = (0..64).collect::<Vec<usize>>();
+        let mut b = a.clone();
+        let mut c = a.clone();
+
+        shuffle(&mut a, &[1u8; 32]);
+        shuffle(&mut b, &[1u8; 32]);
+        shuffle(&mut c, &[2u8; 32]);
+
+        assert_eq!(a, b);
+        assert!(a != c);
+    }
+}
@@ -0,0 +1,459 @@
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use codec::Codec;
+use jpeg::Jpeg;
+use utils::{extract, get_bit};
+
+/// A JPEG along with the bits its coefficients hold, read the way a mode
+/// reads them
+pub struct DctImage
+{
+    jpeg: Jpeg,
+    mode: DctMode,
+    /// The bits, one to a sample
+    bits: Vec<u8>,
+}
+
+impl DctImage
+{
+    pub fn new(jpeg: Jpeg, mode: DctMode) -> DctImage
+    {
+        let bits = read_bits(&jpeg, mode);
+
+        DctImage
+        {
+            jpeg,
+            mode,
+            bits,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        self.jpeg.save(path)
+    }
+
+    pub fn strip(&mut self)
+    {
+        self.jpeg.strip()
+    }
+}
+
+/// Every AC coefficient, as a block and a place in it
+fn places(jpeg: &Jpeg) -> Vec<(usize, usize)>
+{
+    (0..jpeg.blocks.len())
+        .flat_map(|b| (1..64).map(move |i| (b, i)))
+        .collect()
+}
+
+/// JSteg leaves 0 and 1 alone, as the low bit of neither can change without
+/// making a coefficient that is one of them or the other. It leaves -1023
+/// alone too, whose low bit pairs it with -1024, past the largest magnitude
+/// a baseline JPEG can code.
+fn jsteg_usable(c: i16) -> bool
+{
+    c != 0 && c != 1 && c != -1023
+}
+
+/// The bit F5 reads from a coefficient, which taking one from its size
+/// always flips
+fn f5_bit(c: i16) -> usize
+{
+    if c > 0
+    {
+        c as usize & 1
+    }
+    else
+    {
+        1 - ((-(c as i32)) as usize & 1)
+    }
+}
+
+/// The bits a group of coefficients holds in F5's matrix embedding: the
+/// XOR of the places, counting from 1, of those whose bit is set
+fn f5_hash<I: Iterator<Item = usize>>(bits: I) -> usize
+{
+    bits.enumerate()
+        .filter(|&(_, bit)| bit == 1)
+        .fold(0, |hash, (i, _)| hash ^ (i + 1))
+}
+
+fn read_bits(jpeg: &Jpeg, mode: DctMode) -> Vec<u8>
+{
+    let coefficients = places(jpeg).into_iter().map(|(b, i)| jpeg.blocks[b][i]);
+
+    match mode
+    {
+        DctMode::Jsteg => coefficients
+            .filter(|&c| jsteg_usable(c))
+            .map(|c| (c & 1) as u8)
+            .collect(),
+        DctMode::F5(k) =>
+        {
+            let n = (1 << k) - 1;
+            let nonzero = coefficients.filter(|&c| c != 0).collect::<Vec<_>>();
+
+            nonzero.chunks(n)
+                .filter(|group| group.len() == n)
+                .flat_map(|group|
+                {
+                    let hash = f5_hash(group.iter().map(|&c| f5_bit(c)));
+                    (0..k).map(move |j| (hash >> j & 1) as u8)
+                })
+                .collect()
+        },
+    }
+}
+
+/// Embed into groups of nonzero coefficients, changing at most one in each.
+/// When a change takes a coefficient to zero it no longer counts, so the
+/// group takes the next one along and is embedded again.
+fn embed_f5(jpeg: &mut Jpeg, k: usize, targets: &[Option<u8>])
+{
+    let last = match targets.iter().rposition(Option::is_some)
+    {
+        Some(last) => last,
+        None => return,
+    };
+
+    let n = (1 << k) - 1;
+    let places = places(jpeg);
+    let mut next = 0;
+    let mut group = Vec::with_capacity(n);
+
+    for g in 0..=last / k
+    {
+        group.clear();
+
+        loop
+        {
+            while group.len() < n
+            {
+                match places[next..].iter().position(|&(b, i)| jpeg.blocks[b][i] != 0)
+                {
+                    Some(p) =>
+                    {
+                        group.push(places[next + p]);
+                        next += p + 1;
+                    },
+                    // the rest of the bits do not fit
+                    None => return,
+                }
+            }
+
+            let hash = f5_hash(group.iter().map(|&(b, i)| f5_bit(jpeg.blocks[b][i])));
+            let want = (0..k).fold(0, |want, j|
+            {
+                let bit = targets.get(g * k + j)
+                    .cloned()
+                    .and_then(|t| t)
+                    .map_or(hash >> j & 1, |t| t as usize);
+
+                want | bit << j
+            });
+
+            let change = hash ^ want;
+            if change == 0
+            {
+                break;
+            }
+
+            let (b, i) = group[change - 1];
+            let c = &mut jpeg.blocks[b][i];
+            *c -= c.signum();
+
+            if *c != 0
+            {
+                break;
+            }
+
+            group.remove(change - 1);
+        }
+    }
+}
+
+/// Encodes into the quantised DCT coefficients of a JPEG, which is written
+/// back without decoding it, so the carrier stays a JPEG and nothing is lost
+/// to quantising it again
+pub struct DctCodec;
+
+impl Codec for DctCodec
+{
+    type Input = DctImage;
+    type Mode = DctMode;
+
+    const CHANNELS: usize = 1;
+    const CHANNEL_NAMES: &'static [&'static str] = &["c"];
+    const MODE_NAMES: &'static [&'static str] = &["f5", "jsteg"];
+    const PIXELS: bool = false;
+
+    fn encode<R: Rng>(
+        source: &mut DctImage,
+        payload: &[u8],
+        mode: DctMode,
+        mut rng: R)
+    {
+        let layout = Self::layout(source, mode);
+
+        Self::embed(source, &layout, payload, &mut rng);
+    }
+
+    fn decode(
+        source: &DctImage,
+        buffer: &mut [u8],
+        len: usize,
+        mode: DctMode)
+    {
+        let layout = Self::layout(source, mode);
+
+        buffer[..len].copy_from_slice(&extract(&source.bits, &layout, len));
+    }
+
+    /// For F5, every coefficient of 1 or -1 that has to change is lost to
+    /// shrinkage, which is about half of them
+    fn estimate(
+        source: &DctImage,
+        mode: DctMode) -> Option<usize>
+    {
+        match mode
+        {
+            DctMode::Jsteg => Some(read_bits(&source.jpeg, mode).len() / 8),
+            DctMode::F5(k) =>
+            {
+                let (mut large, mut ones) = (0.0, 0.0);
+
+                for (b, i) in places(&source.jpeg)
+                {
+                    match source.jpeg.blocks[b][i].abs()
+                    {
+                        0 => {},
+                        1 => ones += 1.0,
+                        _ => large += 1.0,
+                    }
+                }
+
+                let groups = ((large + 0.49 * ones) / ((1 << k) - 1) as f64) as usize;
+
+                Some(groups * k / 8)
+            },
+        }
+    }
+
+    fn channels(_mode: DctMode) -> &'static [usize]
+    {
+        &[0]
+    }
+
+    fn dimensions(source: &DctImage) -> (u32, u32)
+    {
+        source.jpeg.dimensions()
+    }
+
+    fn samples(source: &DctImage) -> &[u8]
+    {
+        &source.bits
+    }
+
+    fn samples_mut(source: &mut DctImage) -> &mut [u8]
+    {
+        &mut source.bits
+    }
+
+    fn embed<R: Rng>(
+        source: &mut DctImage,
+        positions: &[usize],
+        bytes: &[u8],
+        _rng: &mut R)
+    {
+        let bit = |i: usize| get_bit(bytes[i / 8], (i % 8) as u8) as u8;
+
+        match source.mode
+        {
+            DctMode::Jsteg =>
+            {
+                let usable = places(&source.jpeg).into_iter()
+                    .filter(|&(b, i)| jsteg_usable(source.jpeg.blocks[b][i]))
+                    .collect::<Vec<_>>();
+
+                for (i, &p) in positions.iter().take(bytes.len() * 8).enumerate()
+                {
+                    let (b, c) = usable[p];
+                    let coefficient = &mut source.jpeg.blocks[b][c];
+
+                    *coefficient = (*coefficient & !1) | bit(i) as i16;
+                }
+            },
+            DctMode::F5(k) =>
+            {
+                let mut targets = vec![None; source.bits.len()];
+
+                for (i, &p) in positions.iter().take(bytes.len() * 8).enumerate()
+                {
+                    targets[p] = Some(bit(i));
+                }
+
+                embed_f5(&mut source.jpeg, k, &targets);
+            },
+        }
+
+        // shrinkage leaves fewer groups, and the places past them read as 0
+        let len = source.bits.len();
+        source.bits = read_bits(&source.jpeg, source.mode);
+        source.bits.resize(len, 0);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DctMode
+{
+    /// encode in the low bit of every AC coefficient but 0 and 1, as JSteg
+    /// does
+    Jsteg,
+    /// encode K bits into each group of 2^K - 1 nonzero AC coefficients, as
+    /// F5 does, changing at most one of them by one towards zero
+    F5(usize),
+}
+
+impl Default for DctMode
+{
+    fn default() -> DctMode
+    {
+        DctMode::F5(1)
+    }
+}
+
+impl FromStr for DctMode
+{
+    type Err = ();
+
+    /// `jsteg`, `f5`, or `f5:K` for K from 1 to 7
+    fn from_str(s: &str) -> Result<DctMode, ()>
+    {
+        match s
+        {
+            "jsteg" => Ok(DctMode::Jsteg),
+            "f5" => Ok(DctMode::F5(1)),
+            _ => match s.strip_prefix("f5:").map(str::parse)
+            {
+                Some(Ok(k)) if (1..=7).contains(&k) => Ok(DctMode::F5(k)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use image::{self, ImageBuffer, Rgb};
+    use image::jpeg::JPEGEncoder;
+    use rand::StdRng;
+
+    use super::*;
+
+    fn jpeg() -> Jpeg
+    {
+        // texture, like a photo has, rather than smooth gradients that
+        // quantise to mostly ones and zeros
+        let noise = |x: u32, y: u32, c: u32| ((x * 7919 + y * 104_729 + c * 1_299_709) % 251) as u8;
+        let image = ImageBuffer::from_fn(96, 64, |x, y|
+            Rgb { data: [noise(x, y, 0), noise(x, y, 1) / 2 + (x as u8), noise(x, y, 2) / 4 + (y as u8) * 2] });
+        let mut bytes = Vec::new();
+
+        JPEGEncoder::new_with_quality(&mut bytes, 90)
+            .encode(&image, 96, 64, image::RGB(8))
+            .unwrap();
+
+        Jpeg::read(&bytes).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_modes()
+    {
+        assert_eq!("f5".parse(), Ok(DctMode::F5(1)));
+        assert_eq!("f5:3".parse(), Ok(DctMode::F5(3)));
+        assert_eq!("jsteg".parse(), Ok(DctMode::Jsteg));
+        assert!("f5:0".parse::<DctMode>().is_err());
+        assert!("f5:8".parse::<DctMode>().is_err());
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let cover = jpeg();
+
+        for &mode in &[DctMode::Jsteg, DctMode::F5(1), DctMode::F5(3)]
+        {
+            let mut image = DctImage::new(cover.clone(), mode);
+            let len = DctCodec::estimate(&image, mode).unwrap() / 2;
+            let payload = (0..len).map(|i| (i * 31 % 256) as u8).collect::<Vec<_>>();
+
+            DctCodec::encode(&mut image, &payload, mode, StdRng::new().unwrap());
+
+            let bytes = image.jpeg.to_bytes();
+            let read = DctImage::new(Jpeg::read(&bytes).unwrap().unwrap(), mode);
+            let mut buf = vec![0; len];
+            DctCodec::decode(&read, &mut buf, len, mode);
+
+            assert_eq!(buf, payload);
+            assert!(image::load_from_memory(&bytes).is_ok());
+
+            let pairs = cover.blocks.iter().flat_map(|b| b.iter()).zip(read.jpeg.blocks.iter().flat_map(|b| b.iter()));
+            for (&before, &after) in pairs
+            {
+                assert!((before - after).abs() <= 1);
+
+                // F5 only ever moves a coefficient towards zero
+                if mode != DctMode::Jsteg
+                {
+                    assert!(after.abs() <= before.abs() && before.signum() * after.signum() >= 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jsteg_stays_in_range()
+    {
+        assert!(!jsteg_usable(-1023));
+        assert!(jsteg_usable(1023) && jsteg_usable(-1022));
+
+        // every usable coefficient stays usable, and codable, whatever its bit
+        for c in -1023i16..=1023
+        {
+            if jsteg_usable(c)
+            {
+                for bit in 0..2
+                {
+                    let changed = (c & !1) | bit;
+                    assert!(jsteg_usable(changed) && changed.abs() <= 1023);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matrix_embedding_changes_less()
+    {
+        let cover = jpeg();
+        let payload = vec![0x5a; 40];
+
+        let changes = |mode|
+        {
+            let mut image = DctImage::new(cover.clone(), mode);
+            DctCodec::encode(&mut image, &payload, mode, StdRng::new().unwrap());
+
+            cover.blocks.iter().flat_map(|b| b.iter())
+                .zip(image.jpeg.blocks.iter().flat_map(|b| b.iter()))
+                .filter(|&(a, b)| a != b)
+                .count()
+        };
+
+        assert!(changes(DctMode::F5(3)) < changes(DctMode::F5(1)));
+    }
+}
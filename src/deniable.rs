@@ -0,0 +1,276 @@
+use rand::Rng;
+
+use codec::Codec;
+use crypto;
+use utils::extract;
+
+/// The number of independent payloads an image can hold
+pub const SLOTS: usize = 2;
+
+/// Bytes of each slot that are not payload: the salt, then the encryption's
+/// own overhead and the payload's length
+pub const OVERHEAD: usize = crypto::SALT + crypto::OVERHEAD + 4;
+
+/// A payload and the password that opens it
+pub struct Payload<'a>
+{
+    pub password: &'a str,
+    pub data: &'a [u8],
+}
+
+/// How many payload bytes each slot can hold, given the number of sample
+/// positions a mode encodes into
+pub fn capacity(positions: usize) -> usize
+{
+    slot_size(positions).saturating_sub(OVERHEAD)
+}
+
+/// Whether a payload of `len` bytes fits in a slot. A slot too small for its
+/// own overhead holds nothing, not even an empty payload.
+pub fn fits(positions: usize, len: usize) -> bool
+{
+    slot_size(positions) >= OVERHEAD && len <= capacity(positions)
+}
+
+/// Encode up to `SLOTS` payloads into an image at `layout`.
+///
+/// The payloads are put into slots in a random order, and any slot left
+/// over is filled with random bytes, so without a password every slot looks
+/// the same. Every payload must be one that `fits`.
+pub fn encode<C: Codec, R: Rng>(
+    image: &mut C::Input,
+    layout: &[usize],
+    payloads: &[Payload],
+    rng: &mut R)
+{
+    assert!(payloads.len() <= SLOTS);
+
+    let size = slot_size(layout.len());
+
+    let mut slots = (0..SLOTS).collect::<Vec<_>>();
+    rng.shuffle(&mut slots);
+
+    for (i, slot) in slots.into_iter().enumerate()
+    {
+        match payloads.get(i)
+        {
+            Some(payload) =>
+            {
+                assert!(fits(layout.len(), payload.data.len()));
+
+                let salt = crypto::salt(rng);
+                let master = crypto::master_key(payload.password, &salt);
+                let positions = slot_layout(layout, slot, &master);
+                let bytes = seal(&master, payload.data, size - crypto::SALT, rng);
+
+                C::embed(image, &salt_positions(layout, slot), &salt, rng);
+                C::embed(image, &positions, &bytes, rng);
+            },
+            None =>
+            {
+                let positions = slot_positions(layout, slot);
+                let bytes = (0..size).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+                C::embed(image, &positions, &bytes, rng);
+            },
+        }
+    }
+}
+
+/// Decode whichever payload `password` opens, if any
+pub fn decode(samples: &[u8], layout: &[usize], password: &str)
+    -> Option<Vec<u8>>
+{
+    let size = slot_size(layout.len());
+
+    if size < OVERHEAD
+    {
+        return None;
+    }
+
+    (0..SLOTS).filter_map(|slot|
+    {
+        let salt = extract(samples, &salt_positions(layout, slot), crypto::SALT);
+        let master = crypto::master_key(password, &salt);
+        let positions = slot_layout(layout, slot, &master);
+
+        open(&master, &extract(samples, &positions, size - crypto::SALT))
+    }).next()
+}
+
+/// The positions that could carry the bytes of a `len` byte payload for
+/// `password`. Slots are picked when encoding, so this covers every slot,
+/// and where in a slot depends on a salt also picked then, so this is
+/// where one salt would put it.
+pub fn payload_positions<R: Rng>(layout: &[usize], password: &str, len: usize, rng: &mut R) -> Vec<usize>
+{
+    let master = crypto::master_key(password, &crypto::salt(rng));
+
+    // the payload follows the nonce and its own length
+    let start = (crypto::NONCE + 4) * 8;
+
+    (0..SLOTS)
+        .flat_map(|slot| slot_layout(layout, slot, &master)
+            .into_iter()
+            .skip(start)
+            .take(len * 8))
+        .collect()
+}
+
+/// The number of bytes in each slot
+fn slot_size(positions: usize) -> usize
+{
+    positions / SLOTS / 8
+}
+
+/// The positions belonging to a slot, in layout order
+fn slot_positions(layout: &[usize], slot: usize) -> Vec<usize>
+{
+    layout.iter().skip(slot).step_by(SLOTS).cloned().collect()
+}
+
+/// The positions of a slot's salt, its first in layout order, as the salt
+/// is needed for the key before the rest can be found
+fn salt_positions(layout: &[usize], slot: usize) -> Vec<usize>
+{
+    slot_positions(layout, slot).into_iter().take(crypto::SALT * 8).collect()
+}
+
+/// The positions belonging to a slot after its salt, in the order a key
+/// writes them
+fn slot_layout(layout: &[usize], slot: usize, master: &[u8; 32]) -> Vec<usize>
+{
+    let mut positions = slot_positions(layout, slot).into_iter()
+        .skip(crypto::SALT * 8)
+        .collect::<Vec<_>>();
+    crypto::shuffle(&mut positions, &crypto::subkey(master, "layout", &[slot as u8]));
+
+    positions
+}
+
+/// Encrypt a payload into exactly `size` bytes, padding it out after its
+/// length
+fn seal<R: Rng>(master: &[u8; 32], data: &[u8], size: usize, rng: &mut R)
+    -> Vec<u8>
+{
+    let mut plain = Vec::with_capacity(size);
+
+    plain.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    plain.extend_from_slice(data);
+    while plain.len() < size - crypto::OVERHEAD
+    {
+        plain.push(rng.gen());
+    }
+
+    crypto::encrypt(master, &plain, rng)
+}
+
+/// Check and decrypt bytes written by `seal`
+fn open(master: &[u8; 32], bytes: &[u8]) -> Option<Vec<u8>>
+{
+    let plain = crypto::decrypt(master, bytes)?;
+
+    if plain.len() < 4
+    {
+        return None;
+    }
+
+    let len = u32::from_be_bytes([plain[0], plain[1], plain[2], plain[3]]) as usize;
+
+    if len > plain.len() - 4
+    {
+        return None;
+    }
+
+    Some(plain[4..4 + len].to_vec())
+}
+
+#[cfg(test)]
+mod test
+{
+    use image::{ImageBuffer, Rgba};
+    use rand::StdRng;
+
+    use rgba::{RgbaCodec, RgbaMode};
+    use super::*;
+
+    #[test]
+    fn two_payloads()
+    {
+        let mut image = ImageBuffer::from_pixel(
+            40,
+            40,
+            Rgba
+            {
+                data: [127u8; 4],
+            });
+
+        let layout = RgbaCodec::layout(&image, RgbaMode::All);
+        let mut rng = StdRng::new().unwrap();
+
+        encode::<RgbaCodec, _>(&mut image, &layout, &[
+            Payload { password: "real", data: b"the real message" },
+            Payload { password: "decoy", data: b"a shopping list" },
+        ], &mut rng);
+
+        let samples = RgbaCodec::samples(&image);
+
+        assert_eq!(decode(samples, &layout, "real"),
+            Some(b"the real message".to_vec()));
+        assert_eq!(decode(samples, &layout, "decoy"),
+            Some(b"a shopping list".to_vec()));
+        assert_eq!(decode(samples, &layout, "neither"), None);
+    }
+
+    #[test]
+    fn salted_slots()
+    {
+        let cover = ImageBuffer::from_pixel(40, 40, Rgba { data: [127u8; 4] });
+        let layout = RgbaCodec::layout(&cover, RgbaMode::All);
+        let mut rng = StdRng::new().unwrap();
+
+        // the same password and payload twice, each slot under its own salt
+        let images = (0..2).map(|_|
+        {
+            let mut image = cover.clone();
+            encode::<RgbaCodec, _>(&mut image, &layout, &[
+                Payload { password: "same", data: b"same" },
+                Payload { password: "same", data: b"same" },
+            ], &mut rng);
+
+            image
+        }).collect::<Vec<_>>();
+
+        let layout = &layout;
+        let salts = images.iter()
+            .flat_map(|image| (0..SLOTS).map(move |slot|
+                extract(RgbaCodec::samples(image), &salt_positions(layout, slot), crypto::SALT)))
+            .collect::<Vec<_>>();
+
+        for (i, a) in salts.iter().enumerate()
+        {
+            assert!(salts[i + 1..].iter().all(|b| a != b));
+        }
+
+        for image in &images
+        {
+            assert_eq!(decode(RgbaCodec::samples(image), layout, "same"), Some(b"same".to_vec()));
+        }
+    }
+
+    #[test]
+    fn tiny_carrier()
+    {
+        let image = ImageBuffer::from_pixel(16, 16, Rgba { data: [127u8; 4] });
+        let layout = RgbaCodec::layout(&image, RgbaMode::Alpha);
+
+        // 256 positions give slots of 16 bytes, short of the overhead
+        assert_eq!(capacity(layout.len()), 0);
+        assert!(!fits(layout.len(), 0));
+
+        let image = ImageBuffer::from_pixel(24, 24, Rgba { data: [127u8; 4] });
+        let layout = RgbaCodec::layout(&image, RgbaMode::All);
+        assert!(fits(layout.len(), capacity(layout.len())));
+        assert!(!fits(layout.len(), capacity(layout.len()) + 1));
+    }
+}
@@ -0,0 +1,217 @@
+use image::{GrayImage, ImageBuffer, Luma};
+
+use analysis::{channel, histogram};
+
+/// The side of the windows SSIM is computed over
+const WINDOW: usize = 8;
+/// How far apart SSIM windows start
+const STRIDE: usize = 4;
+/// SSIM stabilising constants, (0.01 * 255)^2 and (0.03 * 255)^2
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+
+/// How one channel of a stego image differs from its cover
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference
+{
+    /// Samples that differ at all
+    pub changed: usize,
+    /// Samples that differ by exactly one
+    pub unit: usize,
+    /// Samples that differ by more than one
+    pub larger: usize,
+    /// Peak signal to noise ratio in dB, infinite when nothing changed
+    pub psnr: f64,
+    /// Mean structural similarity, 1 when nothing changed
+    pub ssim: f64,
+    /// Samples with the least significant bit set, in the cover and stego
+    pub lsb_ones: (usize, usize),
+    /// The sum of absolute differences between the two value histograms
+    pub histogram_delta: usize,
+}
+
+/// Compare one channel of two images of the same size and layout
+pub fn compare(
+    cover: &[u8],
+    stego: &[u8],
+    channels: usize,
+    c: usize,
+    (width, height): (u32, u32)) -> Difference
+{
+    assert_eq!(cover.len(), stego.len());
+
+    let cover = channel(cover, channels, c);
+    let stego = channel(stego, channels, c);
+
+    let mut changed = 0;
+    let mut unit = 0;
+
+    for (&a, &b) in cover.iter().zip(&stego)
+    {
+        let d = a.abs_diff(b);
+
+        if d != 0
+        {
+            changed += 1;
+        }
+        if d == 1
+        {
+            unit += 1;
+        }
+    }
+
+    let ones = |plane: &[u8]| plane.iter().filter(|&&s| s & 1 == 1).count();
+
+    let (before, after) = (histogram(&cover), histogram(&stego));
+    let histogram_delta = before.iter()
+        .zip(after.iter())
+        .map(|(&a, &b)| (a as isize - b as isize).unsigned_abs())
+        .sum();
+
+    Difference
+    {
+        changed,
+        unit,
+        larger: changed - unit,
+        psnr: psnr(&cover, &stego),
+        ssim: ssim(&cover, &stego, width as usize, height as usize),
+        lsb_ones: (ones(&cover), ones(&stego)),
+        histogram_delta,
+    }
+}
+
+/// Peak signal to noise ratio of two sets of samples in dB, infinite when
+/// they are the same
+pub fn psnr(cover: &[u8], stego: &[u8]) -> f64
+{
+    let squares = cover.iter()
+        .zip(stego)
+        .map(|(&a, &b)| (a.abs_diff(b) as u64).pow(2))
+        .sum::<u64>();
+
+    if squares == 0
+    {
+        f64::INFINITY
+    }
+    else
+    {
+        let mse = squares as f64 / cover.len() as f64;
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+/// Mean structural similarity of two planes over overlapping square windows
+pub fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64
+{
+    let size = WINDOW.min(width).min(height);
+
+    if size == 0
+    {
+        return 1.0;
+    }
+
+    let starts = |len: usize| (0..=len - size).step_by(STRIDE.min(size));
+
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for top in starts(height)
+    {
+        for left in starts(width)
+        {
+            let n = (size * size) as f64;
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+            for y in top..top + size
+            {
+                for x in left..left + size
+                {
+                    let (va, vb) = (a[y * width + x] as f64, b[y * width + x] as f64);
+
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
+
+/// The largest difference over all channels of each pixel, multiplied by
+/// `gain`, so that single bit changes become visible
+pub fn amplified(
+    cover: &[u8],
+    stego: &[u8],
+    channels: usize,
+    gain: u32,
+    (width, height): (u32, u32)) -> GrayImage
+{
+    ImageBuffer::from_fn(width, height, |x, y|
+    {
+        let start = (y as usize * width as usize + x as usize) * channels;
+
+        let d = (start..start + channels)
+            .map(|i| (cover[i] as i32 - stego[i] as i32).unsigned_abs())
+            .max()
+            .unwrap_or(0);
+
+        Luma
+        {
+            data: [d.saturating_mul(gain).min(255) as u8],
+        }
+    })
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn counts_changes()
+    {
+        let cover = (0..64 * 64 * 2).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+        let mut stego = cover.clone();
+
+        stego[0] ^= 1;
+        stego[2] = stego[2].wrapping_add(5);
+        stego[1] ^= 1;
+
+        let first = compare(&cover, &stego, 2, 0, (64, 64));
+
+        assert_eq!((first.changed, first.unit, first.larger), (2, 1, 1));
+        assert!(first.psnr.is_finite() && first.psnr > 40.0);
+        assert!(first.ssim < 1.0 && first.ssim > 0.99);
+
+        let second = compare(&cover, &cover, 2, 1, (64, 64));
+
+        assert_eq!(second.changed, 0);
+        assert_eq!(second.psnr, f64::INFINITY);
+        assert_eq!(second.ssim, 1.0);
+        assert_eq!(second.histogram_delta, 0);
+
+        let image = amplified(&cover, &stego, 2, 64, (64, 64));
+
+        assert_eq!(image.get_pixel(0, 0).data[0], 64);
+        assert_eq!(image.get_pixel(1, 0).data[0], 255);
+        assert_eq!(image.get_pixel(2, 0).data[0], 0);
+
+        // any gain saturates rather than wrapping
+        let image = amplified(&cover, &stego, 2, u32::MAX, (64, 64));
+
+        assert_eq!(image.get_pixel(0, 0).data[0], 255);
+        assert_eq!(image.get_pixel(2, 0).data[0], 0);
+    }
+}
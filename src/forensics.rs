@@ -0,0 +1,705 @@
+use std::collections::HashSet;
+
+use scan::classify;
+
+/// Text, EXIF and XMP larger than this are worth a look
+const TEXT_LIMIT: usize = 1024;
+/// Bits of entropy per byte above which data looks compressed or encrypted
+const HIGH_ENTROPY: f64 = 7.5;
+/// Data shorter than this is too short to judge its entropy
+const MIN_ENTROPY_LEN: usize = 256;
+/// Runs of base64 this long in XMP look like embedded files
+const BASE64_RUN: usize = 256;
+/// Unreferenced bytes in EXIF beyond this are reported
+const EXIF_SLACK: usize = 64;
+
+/// The PNG chunks in the specification and its extensions
+const PNG_CHUNKS: &[&[u8; 4]] = &[
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP",
+    b"sBIT", b"sRGB", b"cICP", b"mDCv", b"cLLi", b"tEXt", b"zTXt", b"iTXt",
+    b"bKGD", b"hIST", b"pHYs", b"sPLT", b"eXIf", b"tIME", b"acTL", b"fcTL",
+    b"fdAT",
+];
+
+/// Something in a file that could be hiding data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding
+{
+    /// Where in the file it starts
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Parse a PNG, JPEG or BMP file and report what could be hiding data
+/// outside its pixels, returning the format's name with the findings
+pub fn inspect(bytes: &[u8]) -> Result<(&'static str, Vec<Finding>), &'static str>
+{
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n")
+    {
+        png(bytes).map(|f| ("PNG", f))
+    }
+    else if bytes.starts_with(b"\xff\xd8")
+    {
+        jpeg(bytes).map(|f| ("JPEG", f))
+    }
+    else if bytes.starts_with(b"BM")
+    {
+        bmp(bytes).map(|f| ("BMP", f))
+    }
+    else
+    {
+        Err("Not a PNG, JPEG or BMP file")
+    }
+}
+
+fn png(bytes: &[u8]) -> Result<Vec<Finding>, &'static str>
+{
+    let mut findings = Vec::new();
+    let mut offset = 8;
+    let mut bit_depth = 8;
+
+    loop
+    {
+        if offset + 12 > bytes.len()
+        {
+            findings.push(finding(offset, "the file ends before an IEND chunk"));
+            return Ok(findings);
+        }
+
+        let len = be32(&bytes[offset..]) as usize;
+        let kind = &bytes[offset + 4..offset + 8];
+        let name = String::from_utf8_lossy(kind).into_owned();
+
+        let end = offset + 12 + len;
+        if end > bytes.len()
+        {
+            return Err("A PNG chunk runs past the end of the file");
+        }
+
+        let data = &bytes[offset + 8..offset + 8 + len];
+
+        if crc32(&bytes[offset + 4..offset + 8 + len]) != be32(&bytes[offset + 8 + len..])
+        {
+            findings.push(finding(offset, format!("{} chunk has a bad CRC", name)));
+        }
+
+        match kind
+        {
+            b"IHDR" if len >= 9 => bit_depth = data[8],
+            b"PLTE" =>
+            {
+                if !len.is_multiple_of(3)
+                {
+                    findings.push(finding(offset, "PLTE length is not a multiple of 3"));
+                }
+                if len / 3 > 1 << bit_depth.min(8)
+                {
+                    findings.push(finding(offset, format!(
+                        "PLTE has {} entries, more than a bit depth of {} can index",
+                        len / 3, bit_depth)));
+                }
+
+                let colours = data.chunks(3)
+                    .filter(|c| c.len() == 3)
+                    .map(|c| [c[0], c[1], c[2]])
+                    .collect::<Vec<_>>();
+
+                findings.extend(palette(&colours).into_iter().map(|m| finding(offset, m)));
+            },
+            b"tEXt" | b"zTXt" | b"iTXt" =>
+            {
+                let keyword = data.split(|&b| b == 0).next().unwrap_or(&[]);
+                let keyword = String::from_utf8_lossy(keyword);
+
+                if keyword == "XML:com.adobe.xmp"
+                {
+                    findings.extend(xmp(offset, data));
+                }
+                else if len > TEXT_LIMIT
+                {
+                    findings.push(finding(offset, format!(
+                        "{} chunk \"{}\" holds {} bytes{}", name, keyword, len, entropy_note(data))));
+                }
+            },
+            b"eXIf" => findings.extend(exif(offset + 8, data)),
+            b"IEND" =>
+            {
+                if end < bytes.len()
+                {
+                    findings.push(trailing(end, &bytes[end..], "IEND"));
+                }
+
+                return Ok(findings);
+            },
+            _ if !PNG_CHUNKS.iter().any(|c| &c[..] == kind) =>
+            {
+                // the fifth bit of the second letter is set for private chunks
+                let kind_of = if kind[1] & 0x20 != 0 { "private" } else { "unknown public" };
+
+                findings.push(finding(offset, format!(
+                    "{} chunk {} holds {} bytes{}", kind_of, name, len, entropy_note(data))));
+            },
+            _ => {},
+        }
+
+        offset = end;
+    }
+}
+
+fn jpeg(bytes: &[u8]) -> Result<Vec<Finding>, &'static str>
+{
+    let mut findings = Vec::new();
+    let mut offset = 2;
+
+    loop
+    {
+        // markers may be padded with any number of 0xff bytes
+        while offset < bytes.len() && bytes[offset] == 0xff && bytes.get(offset + 1) == Some(&0xff)
+        {
+            offset += 1;
+        }
+
+        if offset + 2 > bytes.len() || bytes[offset] != 0xff
+        {
+            return Err("A JPEG marker is missing");
+        }
+
+        let marker = bytes[offset + 1];
+
+        if marker == 0xd9
+        {
+            let end = offset + 2;
+
+            if end < bytes.len()
+            {
+                findings.push(trailing(end, &bytes[end..], "EOI"));
+            }
+
+            return Ok(findings);
+        }
+
+        // markers without a length
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker)
+        {
+            offset += 2;
+            continue;
+        }
+
+        if offset + 4 > bytes.len()
+        {
+            return Err("A JPEG segment runs past the end of the file");
+        }
+
+        let len = be16(&bytes[offset + 2..]) as usize;
+        let end = offset + 2 + len;
+
+        if len < 2 || end > bytes.len()
+        {
+            return Err("A JPEG segment runs past the end of the file");
+        }
+
+        let data = &bytes[offset + 4..end];
+
+        match marker
+        {
+            0xe1 if data.starts_with(b"Exif\0\0") => findings.extend(exif(offset + 10, &data[6..])),
+            0xe1 if data.starts_with(b"http://ns.adobe.com/xap/1.0/\0") => findings.extend(xmp(offset, data)),
+            0xfe if data.len() > TEXT_LIMIT =>
+                findings.push(finding(offset, format!("comment holds {} bytes{}", data.len(), entropy_note(data)))),
+            // APP12 and APP13 are common, Ducky and Photoshop, so only
+            // large ones are worth reporting
+            0xec | 0xed if data.len() > TEXT_LIMIT =>
+                findings.push(finding(offset, format!("APP{} segment holds {} bytes{}",
+                    marker - 0xe0, data.len(), entropy_note(data)))),
+            // APP0, 1, 2 and 14 are JFIF, EXIF or XMP, ICC and Adobe
+            0xe3..=0xeb | 0xef =>
+                findings.push(finding(offset, format!("APP{} segment holds {} bytes{}",
+                    marker - 0xe0, data.len(), entropy_note(data)))),
+            0xda =>
+            {
+                offset = scan_end(bytes, end);
+                continue;
+            },
+            _ => {},
+        }
+
+        offset = end;
+    }
+}
+
+/// Where the entropy coded data starting at `offset` ends, at the next
+/// marker that is not a stuffed byte or restart
+fn scan_end(bytes: &[u8], mut offset: usize) -> usize
+{
+    while offset + 1 < bytes.len()
+    {
+        if bytes[offset] == 0xff && bytes[offset + 1] != 0 && !(0xd0..=0xd7).contains(&bytes[offset + 1])
+        {
+            return offset;
+        }
+
+        offset += 1;
+    }
+
+    bytes.len()
+}
+
+fn bmp(bytes: &[u8]) -> Result<Vec<Finding>, &'static str>
+{
+    if bytes.len() < 54
+    {
+        return Err("The BMP headers are cut short");
+    }
+
+    let mut findings = Vec::new();
+
+    let data_offset = le32(&bytes[10..]) as usize;
+    let header_size = le32(&bytes[14..]) as usize;
+    let width = le32(&bytes[18..]) as i32;
+    let height = le32(&bytes[22..]) as i32;
+    let bpp = le16(&bytes[28..]) as usize;
+    let compression = le32(&bytes[30..]);
+    let used = le32(&bytes[46..]) as usize;
+
+    if width <= 0 || height == 0 || bpp == 0
+    {
+        return Err("The BMP has no pixels");
+    }
+
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    // the palette sits between the headers and the pixels
+    let palette_start = 14 + header_size;
+    let entries = if bpp <= 8 { if used == 0 { 1 << bpp } else { used } } else { 0 };
+    let palette_end = (palette_start + entries * 4).min(bytes.len());
+
+    if entries > 0
+    {
+        let table = &bytes[palette_start.min(palette_end)..palette_end];
+
+        let reserved = table.chunks(4).filter(|e| e.len() == 4 && e[3] != 0).count();
+        if reserved > 0
+        {
+            findings.push(finding(palette_start, format!(
+                "{} palette entries have a non-zero reserved byte", reserved)));
+        }
+
+        let colours = table.chunks(4)
+            .filter(|e| e.len() == 4)
+            .map(|e| [e[2], e[1], e[0]])
+            .collect::<Vec<_>>();
+
+        findings.extend(palette(&colours).into_iter().map(|m| finding(palette_start, m)));
+    }
+
+    if data_offset > palette_end && data_offset <= bytes.len()
+    {
+        findings.push(finding(palette_end, format!(
+            "{} bytes lie between the headers and the pixels", data_offset - palette_end)));
+    }
+
+    // only uncompressed and bitfield images have rows to check
+    if compression != 0 && compression != 3
+    {
+        return Ok(findings);
+    }
+
+    // the header is not to be trusted, so sizes that overflow are as wrong
+    // as ones that run past the end
+    let sizes = width.checked_mul(bpp)
+        .and_then(|bits| Some((bits.div_ceil(8), bits.div_ceil(32).checked_mul(4)?)))
+        .and_then(|(row, stride)| Some((row, stride, stride.checked_mul(height)?.checked_add(data_offset)?)));
+
+    let (row, stride, end) = match sizes
+    {
+        Some((row, stride, end)) if end <= bytes.len() => (row, stride, end),
+        _ => return Err("The BMP pixels run past the end of the file"),
+    };
+
+    if stride > row
+    {
+        let padding = bytes[data_offset..end]
+            .chunks(stride)
+            .flat_map(|r| &r[row..])
+            .filter(|&&b| b != 0)
+            .count();
+
+        if padding > 0
+        {
+            findings.push(finding(data_offset, format!(
+                "row padding holds {} non-zero bytes of {}", padding, (stride - row) * height)));
+        }
+    }
+
+    if end < bytes.len()
+    {
+        findings.push(trailing(end, &bytes[end..], "the pixels"));
+    }
+
+    Ok(findings)
+}
+
+/// Duplicate and near duplicate colours, which palette based embedding
+/// leaves behind
+fn palette(colours: &[[u8; 3]]) -> Vec<String>
+{
+    let mut messages = Vec::new();
+
+    let distinct = colours.iter().collect::<HashSet<_>>().len();
+    if distinct < colours.len()
+    {
+        messages.push(format!("palette repeats {} of its {} colours",
+            colours.len() - distinct, colours.len()));
+    }
+
+    let twins = colours.iter()
+        .enumerate()
+        .filter(|&(i, a)| colours.iter().enumerate().any(|(j, b)| i != j && a != b
+            && a.iter().zip(b).all(|(x, y)| x.abs_diff(*y) <= 1)))
+        .count();
+
+    if twins >= 8 && twins * 4 >= colours.len()
+    {
+        messages.push(format!("{} of {} palette colours have a near twin differing by at most 1",
+            twins, colours.len()));
+    }
+
+    messages
+}
+
+/// Walk the IFDs of EXIF data, reporting large tags and bytes no tag
+/// refers to. `offset` is where the TIFF header is in the file.
+fn exif(offset: usize, data: &[u8]) -> Vec<Finding>
+{
+    let mut findings = Vec::new();
+
+    let big = match data.get(..4)
+    {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ =>
+        {
+            findings.push(finding(offset, "EXIF data has no TIFF header"));
+            return findings;
+        },
+    };
+
+    let u16_at = |at: usize| data.get(at..at + 2)
+        .map(|b| if big { be16(b) } else { le16(b) });
+    let u32_at = |at: usize| data.get(at..at + 4)
+        .map(|b| if big { be32(b) } else { le32(b) });
+
+    let mut covered = vec![false; data.len()];
+    let mut cover = |start: usize, len: usize|
+    {
+        for c in covered.iter_mut().skip(start).take(len)
+        {
+            *c = true;
+        }
+    };
+    cover(0, 8);
+
+    let mut ifds = vec![u32_at(4).unwrap_or(0) as usize];
+    let mut seen = HashSet::new();
+    let mut thumbnail = (None, None);
+
+    while let Some(ifd) = ifds.pop()
+    {
+        if ifd == 0 || !seen.insert(ifd)
+        {
+            continue;
+        }
+
+        let count = match u16_at(ifd)
+        {
+            Some(c) => c as usize,
+            None =>
+            {
+                findings.push(finding(offset + ifd, "EXIF IFD lies outside the data"));
+                continue;
+            },
+        };
+
+        cover(ifd, 2 + count * 12 + 4);
+
+        for entry in (0..count).map(|i| ifd + 2 + i * 12)
+        {
+            let (tag, kind, n, value) = match (u16_at(entry), u16_at(entry + 2), u32_at(entry + 4), u32_at(entry + 8))
+            {
+                (Some(t), Some(k), Some(n), Some(v)) => (t, k, n as usize, v as usize),
+                _ => break,
+            };
+
+            let size = match kind
+            {
+                1 | 2 | 6 | 7 => 1usize,
+                3 | 8 => 2,
+                4 | 9 | 11 | 13 => 4,
+                5 | 10 | 12 => 8,
+                _ => 0,
+            }.saturating_mul(n);
+
+            if size > 4
+            {
+                cover(value, size);
+
+                if size > TEXT_LIMIT
+                {
+                    let bytes = data.get(value..value.saturating_add(size)).unwrap_or(&[]);
+
+                    findings.push(finding(offset + value, format!(
+                        "EXIF tag {:#06x} holds {} bytes{}", tag, size, entropy_note(bytes))));
+                }
+            }
+
+            match tag
+            {
+                // the EXIF, GPS and interoperability IFDs
+                0x8769 | 0x8825 | 0xa005 => ifds.push(value),
+                0x0201 => thumbnail.0 = Some(value),
+                0x0202 => thumbnail.1 = Some(value),
+                _ => {},
+            }
+        }
+
+        if let Some(next) = u32_at(ifd + 2 + count * 12)
+        {
+            ifds.push(next as usize);
+        }
+    }
+
+    if let (Some(start), Some(len)) = thumbnail
+    {
+        cover(start, len);
+    }
+
+    let slack = covered.iter().filter(|&&c| !c).count();
+
+    if slack > EXIF_SLACK
+    {
+        findings.push(finding(offset, format!("{} bytes of EXIF data are not referred to by any tag", slack)));
+    }
+
+    findings
+}
+
+/// Large XMP packets and long runs of base64 inside them
+fn xmp(offset: usize, data: &[u8]) -> Vec<Finding>
+{
+    let mut findings = Vec::new();
+
+    let is_base64 = |b: &u8| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/' || *b == b'=';
+    let longest = data.split(|b| !is_base64(b)).map(|run| run.len()).max().unwrap_or(0);
+
+    if longest >= BASE64_RUN
+    {
+        findings.push(finding(offset, format!("XMP holds a {} character run of base64", longest)));
+    }
+    else if data.len() > TEXT_LIMIT * 64
+    {
+        findings.push(finding(offset, format!("XMP holds {} bytes", data.len())));
+    }
+
+    findings
+}
+
+/// Data after the end of an image, and what it looks like
+fn trailing(offset: usize, data: &[u8], after: &str) -> Finding
+{
+    let looks = classify(&data[..data.len().min(64)], usize::MAX)
+        .map(|(_, description)| format!(", starting with {}", description))
+        .unwrap_or_default();
+
+    finding(offset, format!("{} bytes after {}{}{}", data.len(), after, looks, entropy_note(data)))
+}
+
+/// Notes data that looks compressed or encrypted
+fn entropy_note(data: &[u8]) -> String
+{
+    let bits = entropy(data);
+
+    if data.len() >= MIN_ENTROPY_LEN && bits > HIGH_ENTROPY
+    {
+        format!(", {:.2} bits of entropy per byte", bits)
+    }
+    else
+    {
+        String::new()
+    }
+}
+
+/// Shannon entropy in bits per byte
+pub fn entropy(data: &[u8]) -> f64
+{
+    let mut counts = [0usize; 256];
+
+    for &b in data
+    {
+        counts[b as usize] += 1;
+    }
+
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| c as f64 / data.len() as f64)
+        .map(|p| -p * p.log2())
+        .sum()
+}
+
+/// The CRC-32 PNG chunks carry
+pub fn crc32(data: &[u8]) -> u32
+{
+    let mut crc = !0u32;
+
+    for &b in data
+    {
+        crc ^= b as u32;
+
+        for _ in 0..8
+        {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn finding<S: Into<String>>(offset: usize, message: S) -> Finding
+{
+    Finding
+    {
+        offset,
+        message: message.into(),
+    }
+}
+
+fn be16(b: &[u8]) -> u16
+{
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+fn be32(b: &[u8]) -> u32
+{
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le16(b: &[u8]) -> u16
+{
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le32(b: &[u8]) -> u32
+{
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8>
+    {
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(data);
+
+        let crc = crc32(&bytes[4..]);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+
+        bytes
+    }
+
+    #[test]
+    fn png_chunks_and_trailing_data()
+    {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0]));
+        png.extend(chunk(b"PLTE", &[0, 0, 0, 0, 0, 0, 9, 9, 9]));
+        png.extend(chunk(b"stGa", b"secret"));
+        png.extend(chunk(b"tEXt", &[&b"Comment\0"[..], &[b'a'; 2000]].concat()));
+        png.extend(chunk(b"IDAT", &[]));
+        png.extend(chunk(b"IEND", &[]));
+        png.extend_from_slice(b"PK\x03\x04 more");
+
+        let (format, findings) = inspect(&png).unwrap();
+        let messages = findings.iter().map(|f| f.message.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(format, "PNG");
+        assert_eq!(messages, vec![
+            "palette repeats 1 of its 3 colours",
+            "private chunk stGa holds 6 bytes",
+            "tEXt chunk \"Comment\" holds 2008 bytes",
+            "9 bytes after IEND, starting with zip archive",
+        ]);
+    }
+
+    #[test]
+    fn bmp_padding_and_palette()
+    {
+        // a 1x2 image of 24 bit pixels, so each row has one padding byte
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&(54u32 + 8).to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&1i32.to_le_bytes());
+        bmp.extend_from_slice(&2i32.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        bmp.extend_from_slice(&[1, 2, 3, 0x42, 4, 5, 6, 0]);
+
+        let (format, findings) = inspect(&bmp).unwrap();
+
+        assert_eq!(format, "BMP");
+        assert_eq!(findings, vec![finding(54, "row padding holds 1 non-zero bytes of 2")]);
+
+        let twins = (0..16u8).map(|i| [i * 2, 100, 100]).chain((0..16u8).map(|i| [i * 2 + 1, 100, 100]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(palette(&twins).len(), 1);
+    }
+
+    #[test]
+    fn hostile_bmp_sizes()
+    {
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&[0; 8]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&0x7fff_ffffi32.to_le_bytes());
+        bmp.extend_from_slice(&(-0x7fff_ffffi32).to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&0xffffu16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+
+        assert_eq!(inspect(&bmp), Err("The BMP pixels run past the end of the file"));
+
+        // a data offset at the very top of the range
+        bmp[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        bmp[18..22].copy_from_slice(&1i32.to_le_bytes());
+        bmp[22..26].copy_from_slice(&1i32.to_le_bytes());
+        bmp[28..30].copy_from_slice(&24u16.to_le_bytes());
+
+        assert_eq!(inspect(&bmp), Err("The BMP pixels run past the end of the file"));
+    }
+
+    #[test]
+    fn jpeg_trailing_data()
+    {
+        let mut jpeg = b"\xff\xd8\xff\xe0\x00\x04ab\xff\xda\x00\x02\x12\xff\x00\x34\xff\xd9".to_vec();
+        jpeg.extend_from_slice(b"hidden text after the end");
+
+        let (_, findings) = inspect(&jpeg).unwrap();
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].offset, 18);
+        assert!(findings[0].message.starts_with("25 bytes after EOI, starting with text"));
+    }
+
+    #[test]
+    fn crc()
+    {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+}
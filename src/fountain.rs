@@ -0,0 +1,327 @@
+use rand::{ChaChaRng, Rng, SeedableRng};
+
+/// Bytes each symbol spends on its seed
+pub const SEED_SIZE: usize = 4;
+
+/// Parameters of the robust soliton distribution
+const C: f64 = 0.1;
+const DELTA: f64 = 0.05;
+
+/// The most blocks a message is cut into, which bounds what the decoder
+/// sets aside for it
+pub const MAX_BLOCKS: usize = 1 << 16;
+
+/// The number of blocks a message of `len` bytes is cut into
+pub fn blocks(len: usize, size: usize) -> usize
+{
+    len.div_ceil(size).max(1)
+}
+
+/// The number of blocks, for a length and size read from an image. Gives
+/// `None` for a size of 0 or more than `MAX_BLOCKS` blocks.
+pub fn checked_blocks(len: usize, size: usize) -> Option<usize>
+{
+    if size == 0
+    {
+        return None;
+    }
+
+    Some(blocks(len, size)).filter(|&k| k <= MAX_BLOCKS)
+}
+
+/// Generates encoded symbols for a message: each one is the XOR of a
+/// random set of the message's blocks, chosen by the symbol's seed
+pub struct Encoder
+{
+    blocks: Vec<Vec<u8>>,
+    degrees: Vec<f64>,
+}
+
+impl Encoder
+{
+    pub fn new(message: &[u8], size: usize) -> Encoder
+    {
+        let k = blocks(message.len(), size);
+
+        let blocks = (0..k).map(|i|
+        {
+            let mut block = message[(i * size).min(message.len())..]
+                .iter()
+                .take(size)
+                .cloned()
+                .collect::<Vec<_>>();
+            block.resize(size, 0);
+
+            block
+        }).collect();
+
+        Encoder
+        {
+            blocks,
+            degrees: robust_soliton(k),
+        }
+    }
+
+    /// The symbol for a seed, seed first
+    pub fn symbol(&self, seed: u32) -> Vec<u8>
+    {
+        let mut symbol = seed.to_be_bytes().to_vec();
+        let mut data = vec![0u8; self.blocks[0].len()];
+
+        for i in neighbours(seed, &self.degrees)
+        {
+            for (d, b) in data.iter_mut().zip(self.blocks[i].iter())
+            {
+                *d ^= b;
+            }
+        }
+
+        symbol.extend(data);
+
+        symbol
+    }
+}
+
+/// Recovers a message from symbols by incremental Gaussian elimination,
+/// so any set of symbols whose blocks span the message will do
+pub struct Decoder
+{
+    degrees: Vec<f64>,
+    size: usize,
+    /// For each block, a row whose lowest set bit is that block, if found
+    pivots: Vec<Option<(Vec<u64>, Vec<u8>)>>,
+    rank: usize,
+}
+
+impl Decoder
+{
+    pub fn new(k: usize, size: usize) -> Decoder
+    {
+        Decoder
+        {
+            degrees: robust_soliton(k),
+            size,
+            pivots: vec![None; k],
+            rank: 0,
+        }
+    }
+
+    /// The number of blocks in the message
+    pub fn blocks(&self) -> usize
+    {
+        self.pivots.len()
+    }
+
+    /// The number of independent symbols seen so far
+    pub fn rank(&self) -> usize
+    {
+        self.rank
+    }
+
+    pub fn is_done(&self) -> bool
+    {
+        self.rank == self.blocks()
+    }
+
+    /// Add a symbol, as written by `Encoder::symbol`. Returns whether it
+    /// told us anything new.
+    pub fn add(&mut self, symbol: &[u8]) -> bool
+    {
+        if symbol.len() != SEED_SIZE + self.size || self.is_done()
+        {
+            return false;
+        }
+
+        let seed = u32::from_be_bytes([symbol[0], symbol[1], symbol[2], symbol[3]]);
+
+        let mut row = vec![0u64; self.blocks().div_ceil(64)];
+        for i in neighbours(seed, &self.degrees)
+        {
+            row[i / 64] ^= 1 << (i % 64);
+        }
+
+        let mut data = symbol[SEED_SIZE..].to_vec();
+
+        while let Some(column) = lowest_bit(&row)
+        {
+            match self.pivots[column]
+            {
+                Some((ref pivot, ref pivot_data)) =>
+                {
+                    xor(&mut row, pivot);
+                    xor(&mut data, pivot_data);
+                },
+                None =>
+                {
+                    self.pivots[column] = Some((row, data));
+                    self.rank += 1;
+
+                    return true;
+                },
+            }
+        }
+
+        false
+    }
+
+    /// Solve for the message, once every block is known
+    pub fn finish(mut self, len: usize) -> Option<Vec<u8>>
+    {
+        if !self.is_done()
+        {
+            return None;
+        }
+
+        // back substitute from the last block, so each pivot ends up
+        // holding only its own block
+        for column in (0..self.blocks()).rev()
+        {
+            let (row, mut data) = self.pivots[column].take()?;
+
+            for other in column + 1..self.blocks()
+            {
+                if row[other / 64] & (1 << (other % 64)) != 0
+                {
+                    xor(&mut data, &self.pivots[other].as_ref()?.1);
+                }
+            }
+
+            let mut solved = vec![0u64; row.len()];
+            solved[column / 64] = 1 << (column % 64);
+            self.pivots[column] = Some((solved, data));
+        }
+
+        let mut message = self.pivots.into_iter()
+            .flat_map(|p| p.map(|p| p.1).unwrap_or_default())
+            .collect::<Vec<_>>();
+        message.truncate(len);
+
+        Some(message)
+    }
+}
+
+/// The blocks a symbol's seed combines
+fn neighbours(seed: u32, degrees: &[f64]) -> Vec<usize>
+{
+    let k = degrees.len();
+    let mut rng = ChaChaRng::from_seed(&[seed, 0x666f_756e][..]);
+
+    let u = rng.next_f64();
+    let degree = degrees.iter().position(|&p| u < p).unwrap_or(k - 1) + 1;
+
+    let mut chosen = Vec::with_capacity(degree);
+    while chosen.len() < degree
+    {
+        let i = rng.gen_range(0, k);
+        if !chosen.contains(&i)
+        {
+            chosen.push(i);
+        }
+    }
+
+    chosen
+}
+
+/// The cumulative robust soliton distribution over degrees 1 to k
+fn robust_soliton(k: usize) -> Vec<f64>
+{
+    let kf = k as f64;
+    let r = C * (kf / DELTA).ln() * kf.sqrt();
+    let spike = ((kf / r).floor() as usize).clamp(1, k);
+
+    let weights = (1..=k).map(|d|
+    {
+        let rho = if d == 1 { 1.0 / kf } else { 1.0 / (d * (d - 1)) as f64 };
+
+        let tau = if d < spike
+        {
+            r / (d as f64 * kf)
+        }
+        else if d == spike
+        {
+            r * (r / DELTA).ln() / kf
+        }
+        else
+        {
+            0.0
+        };
+
+        rho + tau.max(0.0)
+    }).collect::<Vec<_>>();
+
+    let total = weights.iter().sum::<f64>();
+
+    weights.iter()
+        .scan(0.0, |sum, w|
+        {
+            *sum += w / total;
+            Some(*sum)
+        })
+        .collect()
+}
+
+fn lowest_bit(row: &[u64]) -> Option<usize>
+{
+    row.iter()
+        .position(|&w| w != 0)
+        .map(|i| i * 64 + row[i].trailing_zeros() as usize)
+}
+
+fn xor<T: ::std::ops::BitXorAssign + Copy>(a: &mut [T], b: &[T])
+{
+    for (a, &b) in a.iter_mut().zip(b.iter())
+    {
+        *a ^= b;
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use rand::{Rng, StdRng};
+
+    use super::*;
+
+    #[test]
+    fn any_large_enough_subset()
+    {
+        let mut rng = StdRng::new().unwrap();
+        let message = (0..1000).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+        let encoder = Encoder::new(&message, 16);
+        let k = blocks(message.len(), 16);
+
+        // lose every third symbol
+        let mut decoder = Decoder::new(k, 16);
+        for seed in (0..k as u32 * 3).filter(|s| s % 3 != 0)
+        {
+            decoder.add(&encoder.symbol(seed));
+        }
+
+        assert_eq!(decoder.finish(message.len()), Some(message));
+    }
+
+    #[test]
+    fn hostile_headers()
+    {
+        assert_eq!(checked_blocks(100, 0), None);
+        assert_eq!(checked_blocks(u32::MAX as usize, 1), None);
+        assert_eq!(checked_blocks(MAX_BLOCKS, 1), Some(MAX_BLOCKS));
+        assert_eq!(checked_blocks(0, 16), Some(1));
+    }
+
+    #[test]
+    fn too_few_symbols()
+    {
+        let encoder = Encoder::new(&[1; 100], 10);
+        let mut decoder = Decoder::new(10, 10);
+
+        for seed in 0..5
+        {
+            decoder.add(&encoder.symbol(seed));
+        }
+
+        assert!(!decoder.is_done());
+        assert_eq!(decoder.finish(100), None);
+    }
+}
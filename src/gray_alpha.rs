@@ -12,6 +12,10 @@ impl Codec for GrayAlphaCodec
     type Input = GrayAlphaImage;
     type Mode = GrayAlphaMode;
 
+    const CHANNELS: usize = 2;
+    const CHANNEL_NAMES: &'static [&'static str] = &["l", "a"];
+    const MODE_NAMES: &'static [&'static str] = &["alpha", "all"];
+
     fn encode<R: Rng>(
         source: &mut GrayAlphaImage,
         payload: &[u8],
@@ -133,6 +137,31 @@ impl Codec for GrayAlphaCodec
                 source.height() as usize * source.width() as usize / 4,
         })
     }
+
+    fn channels(mode: GrayAlphaMode) -> &'static [usize]
+    {
+        match mode
+        {
+            GrayAlphaMode::Alpha => &[1],
+            GrayAlphaMode::All => &[0, 1],
+        }
+    }
+
+    fn dimensions(source: &GrayAlphaImage) -> (u32, u32)
+    {
+        source.dimensions()
+    }
+
+    fn samples(source: &GrayAlphaImage) -> &[u8]
+    {
+        source
+    }
+
+    fn samples_mut(source: &mut GrayAlphaImage) -> &mut [u8]
+    {
+        source
+    }
+
 }
 
 #[derive(Copy, Clone)]
@@ -0,0 +1,422 @@
+use rand::Rng;
+
+use crypto;
+
+/// Marks the start of a header
+pub const MAGIC: [u8; 4] = *b"stag";
+
+/// The number of bytes a header takes up, without a piece
+pub const SIZE: usize = 9;
+/// The number of extra bytes a piece adds to a header
+pub const PIECE_SIZE: usize = 12;
+/// The number of extra bytes a share adds to a header
+pub const SHARE_SIZE: usize = 10;
+/// The number of extra bytes fountain coding adds to a header
+pub const FOUNTAIN_SIZE: usize = 14;
+/// The number of random bytes before a padded header, which key the
+/// keystream it and its payload are whitened with
+pub const NONCE: usize = 16;
+/// The most bytes a header can take up
+pub const MAX_SIZE: usize = NONCE + SIZE + PIECE_SIZE + SHARE_SIZE + FOUNTAIN_SIZE;
+
+/// The rest of the image after the payload is filled with random bits
+pub const PADDED: u8 = 1;
+/// The payload is a container of files
+pub const CONTAINER: u8 = 2;
+/// The payload is one piece of a message split across images
+pub const PIECE: u8 = 4;
+/// The payload is one share of a secret shared across images
+pub const SHARE: u8 = 8;
+/// The payload is encrypted with a password
+pub const ENCRYPTED: u8 = 16;
+/// The payload is fountain coded symbols of a message spread across images
+pub const FOUNTAIN: u8 = 32;
+
+/// Describes the payload that follows it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Header
+{
+    /// Bit flags describing the payload
+    pub flags: u8,
+    /// The length of the payload in bytes
+    pub length: u32,
+    /// Where the payload belongs, if it's a piece of a larger message
+    pub piece: Option<Piece>,
+    /// Which share of a secret the payload is, if it is one
+    pub share: Option<Share>,
+    /// Which message the payload's symbols encode, if it is fountain coded
+    pub fountain: Option<Fountain>,
+}
+
+/// Identifies one piece of a message split across images
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Piece
+{
+    /// Shared by every piece of the same message
+    pub id: u64,
+    /// This piece's place in the message, from zero
+    pub index: u16,
+    /// The number of pieces in the message
+    pub count: u16,
+}
+
+/// Identifies one share of a secret shared across images
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Share
+{
+    /// Shared by every share of the same secret
+    pub id: u64,
+    /// The x coordinate of this share, never zero
+    pub x: u8,
+    /// The number of shares needed to recover the secret
+    pub threshold: u8,
+}
+
+/// Identifies a fountain coded message spread across images
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fountain
+{
+    /// Shared by every image holding symbols of the same message
+    pub id: u64,
+    /// The length of the message in bytes
+    pub length: u32,
+    /// The number of bytes in each block of the message
+    pub symbol_size: u16,
+}
+
+impl Header
+{
+    pub fn new(flags: u8, length: usize) -> Header
+    {
+        Header
+        {
+            flags: flags & !(PIECE | SHARE | FOUNTAIN),
+            length: length as u32,
+            piece: None,
+            share: None,
+            fountain: None,
+        }
+    }
+
+    pub fn with_piece(flags: u8, length: usize, piece: Piece) -> Header
+    {
+        Header
+        {
+            flags: flags | PIECE,
+            piece: Some(piece),
+            ..Header::new(flags, length)
+        }
+    }
+
+    pub fn with_share(flags: u8, length: usize, share: Share) -> Header
+    {
+        Header
+        {
+            flags: flags | SHARE,
+            share: Some(share),
+            ..Header::new(flags, length)
+        }
+    }
+
+    pub fn with_fountain(flags: u8, length: usize, fountain: Fountain) -> Header
+    {
+        Header
+        {
+            flags: flags | FOUNTAIN,
+            fountain: Some(fountain),
+            ..Header::new(flags, length)
+        }
+    }
+
+    /// The number of bytes this header takes up
+    pub fn size(self) -> usize
+    {
+        size_of(self.flags)
+    }
+
+    /// Serialize the header followed by its payload. A padded one is
+    /// whitened, so that it looks like the random padding after it.
+    pub fn write<R: Rng>(self, payload: &[u8], rng: &mut R) -> Vec<u8>
+    {
+        if !self.has(PADDED)
+        {
+            let mut bytes = self.to_bytes();
+            bytes.extend_from_slice(payload);
+
+            return bytes;
+        }
+
+        let mut bytes = (0..NONCE).map(|_| rng.gen()).collect::<Vec<u8>>();
+        bytes.extend(self.to_bytes());
+        bytes.extend_from_slice(payload);
+
+        let (nonce, body) = bytes.split_at_mut(NONCE);
+        crypto::apply_keystream(&whitening_key(nonce), body);
+
+        bytes
+    }
+
+    /// The payload of `bytes` that start with this header and run to the
+    /// end of its payload
+    pub fn payload(self, bytes: &[u8]) -> Vec<u8>
+    {
+        let mut payload = bytes[self.size()..].to_vec();
+
+        if self.has(PADDED)
+        {
+            // the keystream runs on from the start of the header
+            let mut body = bytes[NONCE..].to_vec();
+            crypto::apply_keystream(&whitening_key(&bytes[..NONCE]), &mut body);
+
+            payload = body.split_off(self.size() - NONCE);
+        }
+
+        payload
+    }
+
+    /// Serialize: magic, flags, then big-endian length, followed by the
+    /// piece's id, index and count if there is one, then the share's id, x
+    /// and threshold if there is one, then the fountain coded message's id,
+    /// length and symbol size if there is one
+    fn to_bytes(self) -> Vec<u8>
+    {
+        let mut bytes = Vec::with_capacity(self.size());
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+
+        if let Some(piece) = self.piece
+        {
+            bytes.extend_from_slice(&piece.id.to_be_bytes());
+            bytes.extend_from_slice(&piece.index.to_be_bytes());
+            bytes.extend_from_slice(&piece.count.to_be_bytes());
+        }
+
+        if let Some(share) = self.share
+        {
+            bytes.extend_from_slice(&share.id.to_be_bytes());
+            bytes.push(share.x);
+            bytes.push(share.threshold);
+        }
+
+        if let Some(fountain) = self.fountain
+        {
+            bytes.extend_from_slice(&fountain.id.to_be_bytes());
+            bytes.extend_from_slice(&fountain.length.to_be_bytes());
+            bytes.extend_from_slice(&fountain.symbol_size.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Parse a header, if `bytes` starts with one, whitened or not
+    pub fn from_bytes(bytes: &[u8]) -> Option<Header>
+    {
+        Header::parse(bytes).or_else(||
+        {
+            if bytes.len() < NONCE
+            {
+                return None;
+            }
+
+            let mut body = bytes[NONCE..].to_vec();
+            crypto::apply_keystream(&whitening_key(&bytes[..NONCE]), &mut body);
+
+            Header::parse(&body).filter(|header| header.has(PADDED))
+        })
+    }
+
+    /// Parse a header that is not whitened
+    fn parse(bytes: &[u8]) -> Option<Header>
+    {
+        if bytes.len() < SIZE || bytes[..4] != MAGIC
+        {
+            return None;
+        }
+
+        let flags = bytes[4];
+        let length = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+
+        let mut rest = &bytes[SIZE..];
+
+        let piece = if flags & PIECE != 0
+        {
+            let p = take(&mut rest, PIECE_SIZE)?;
+
+            Some(Piece
+            {
+                id: u64::from_be_bytes([p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7]]),
+                index: u16::from_be_bytes([p[8], p[9]]),
+                count: u16::from_be_bytes([p[10], p[11]]),
+            })
+        }
+        else
+        {
+            None
+        };
+
+        let share = if flags & SHARE != 0
+        {
+            let s = take(&mut rest, SHARE_SIZE)?;
+
+            Some(Share
+            {
+                id: u64::from_be_bytes([s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]]),
+                x: s[8],
+                threshold: s[9],
+            })
+        }
+        else
+        {
+            None
+        };
+
+        let fountain = if flags & FOUNTAIN != 0
+        {
+            let f = take(&mut rest, FOUNTAIN_SIZE)?;
+
+            Some(Fountain
+            {
+                id: u64::from_be_bytes([f[0], f[1], f[2], f[3], f[4], f[5], f[6], f[7]]),
+                length: u32::from_be_bytes([f[8], f[9], f[10], f[11]]),
+                symbol_size: u16::from_be_bytes([f[12], f[13]]),
+            })
+        }
+        else
+        {
+            None
+        };
+
+        Some(Header
+        {
+            flags,
+            length,
+            piece,
+            share,
+            fountain,
+        })
+    }
+
+    pub fn has(self, flag: u8) -> bool
+    {
+        self.flags & flag != 0
+    }
+}
+
+/// The number of bytes a header with some flags takes up
+pub fn size_of(flags: u8) -> usize
+{
+    SIZE
+        + if flags & PADDED != 0 { NONCE } else { 0 }
+        + if flags & PIECE != 0 { PIECE_SIZE } else { 0 }
+        + if flags & SHARE != 0 { SHARE_SIZE } else { 0 }
+        + if flags & FOUNTAIN != 0 { FOUNTAIN_SIZE } else { 0 }
+}
+
+/// Whitening hides the header and payload from statistics, not from anyone
+/// who knows the format, so it is keyed by the nonce alone
+fn whitening_key(nonce: &[u8]) -> [u8; 32]
+{
+    crypto::subkey(&[0u8; 32], "whiten", nonce)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]>
+{
+    if bytes.len() < len
+    {
+        return None;
+    }
+
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+
+    Some(taken)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+        let header = Header::new(PADDED, 1234);
+
+        assert_eq!(Header::from_bytes(&header.to_bytes()), Some(header));
+        assert_eq!(Header::from_bytes(b"not a header"), None);
+    }
+
+    #[test]
+    fn whitened()
+    {
+        let mut rng = ::rand::StdRng::new().unwrap();
+        let header = Header::with_piece(PADDED, 11, Piece
+        {
+            id: 7,
+            index: 0,
+            count: 1,
+        });
+
+        let bytes = header.write(b"hello world", &mut rng);
+
+        assert_eq!(bytes.len(), header.size() + 11);
+        assert!(!bytes.windows(4).any(|w| w == MAGIC));
+        assert!(header.write(b"hello world", &mut rng) != bytes);
+        assert_eq!(Header::from_bytes(&bytes), Some(header));
+        assert_eq!(header.payload(&bytes), b"hello world".to_vec());
+
+        let plain = Header::new(0, 2);
+        assert_eq!(plain.payload(&plain.write(b"hi", &mut rng)), b"hi".to_vec());
+    }
+
+    #[test]
+    fn piece()
+    {
+        let header = Header::with_piece(CONTAINER, 99, Piece
+        {
+            id: 0xdead_beef,
+            index: 2,
+            count: 5,
+        });
+
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes.len(), header.size());
+        assert_eq!(Header::from_bytes(&bytes), Some(header));
+        assert_eq!(Header::from_bytes(&bytes[..SIZE]), None);
+    }
+
+    #[test]
+    fn share()
+    {
+        let header = Header::with_share(ENCRYPTED, 99, Share
+        {
+            id: 42,
+            x: 3,
+            threshold: 2,
+        });
+
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes.len(), SIZE + SHARE_SIZE);
+        assert_eq!(Header::from_bytes(&bytes), Some(header));
+    }
+
+    #[test]
+    fn fountain()
+    {
+        let header = Header::with_fountain(0, 500, Fountain
+        {
+            id: 42,
+            length: 10_000,
+            symbol_size: 64,
+        });
+
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes.len(), SIZE + FOUNTAIN_SIZE);
+        assert_eq!(Header::from_bytes(&bytes), Some(header));
+    }
+}
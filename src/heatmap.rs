@@ -0,0 +1,67 @@
+use image::{ImageBuffer, Rgb, RgbImage};
+
+/// What a sample is used for by an encoding
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Use
+{
+    /// Left as it is
+    Unused,
+    /// Overwritten with random bits, padding or an empty slot
+    Filler,
+    /// Carries the payload or its header
+    Payload,
+}
+
+/// The carrier, dimmed to grey, with each pixel tinted red by the share of
+/// its samples carrying the payload and blue by the share carrying filler
+pub fn render(
+    samples: &[u8],
+    channels: usize,
+    visible: &[usize],
+    usage: &[Use],
+    (width, height): (u32, u32)) -> RgbImage
+{
+    assert_eq!(samples.len(), usage.len());
+
+    ImageBuffer::from_fn(width, height, |x, y|
+    {
+        let start = (y as usize * width as usize + x as usize) * channels;
+        let pixel = start..start + channels;
+
+        let grey = visible.iter().map(|&c| samples[start + c] as f64).sum::<f64>()
+            / visible.len().max(1) as f64;
+        let base = grey / 2.0;
+
+        let share = |u| usage[pixel.clone()].iter().filter(|&&v| v == u).count() as f64
+            / channels as f64;
+        let (payload, filler) = (share(Use::Payload), share(Use::Filler));
+
+        Rgb
+        {
+            data: [
+                (base + (255.0 - base) * payload) as u8,
+                (base * (1.0 - payload - filler)) as u8,
+                (base + (255.0 - base) * filler) as u8,
+            ],
+        }
+    })
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn tints_used_pixels()
+    {
+        let samples = vec![100u8; 3 * 2];
+        let usage = vec![Use::Payload, Use::Payload, Use::Payload, Use::Unused, Use::Filler, Use::Unused];
+
+        let image = render(&samples, 2, &[0], &usage, (3, 1));
+
+        assert_eq!(image.get_pixel(0, 0).data, [255, 0, 50]);
+        assert_eq!(image.get_pixel(1, 0).data, [152, 25, 50]);
+        assert_eq!(image.get_pixel(2, 0).data, [50, 25, 152]);
+    }
+}
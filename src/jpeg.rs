@@ -0,0 +1,932 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A baseline JPEG read as far as its quantised DCT coefficients, which can
+/// be changed and written back without decoding the pixels, so nothing is
+/// lost to a second round of quantisation
+#[derive(Clone)]
+pub struct Jpeg
+{
+    /// The marker segments before the scan, in order
+    segments: Vec<Segment>,
+    /// The data of the SOS segment
+    scan: Vec<u8>,
+    /// Everything after the entropy-coded data, from EOI on
+    trailer: Vec<u8>,
+    width: u16,
+    height: u16,
+    /// The DC and AC table of each component in the scan
+    components: Vec<(u8, u8)>,
+    /// The MCUs between restart markers, 0 for none
+    restart: usize,
+    /// The scan component of each block in an MCU
+    mcu: Vec<usize>,
+    /// The coefficients of every block in the order the scan codes them,
+    /// each in zigzag order with DC first
+    pub blocks: Vec<[i16; 64]>,
+}
+
+#[derive(Clone)]
+enum Segment
+{
+    Marker(u8, Vec<u8>),
+    /// A DHT segment, kept apart as a table in it may have to change
+    Tables(Vec<Table>),
+}
+
+/// A Huffman table as DHT defines it
+#[derive(Clone, Debug, PartialEq)]
+struct Table
+{
+    /// 0 for DC, 1 for AC
+    class: u8,
+    id: u8,
+    /// The number of codes of each length from 1 to 16
+    counts: [u8; 16],
+    /// The symbols, shortest code first
+    symbols: Vec<u8>,
+}
+
+/// A symbol of the entropy-coded data, or a restart marker between them
+enum Code
+{
+    Symbol
+    {
+        table: (u8, u8),
+        symbol: u8,
+        /// The extra bits after the symbol, and how many there are
+        bits: u16,
+        len: u8,
+    },
+    Restart(u8),
+}
+
+impl Jpeg
+{
+    /// Read a JPEG. Gives `None` for anything that is not one.
+    pub fn read(bytes: &[u8]) -> Result<Option<Jpeg>, &'static str>
+    {
+        if !bytes.starts_with(&[0xff, 0xd8])
+        {
+            return Ok(None);
+        }
+
+        let mut segments = Vec::new();
+        let mut frame = None;
+        let mut restart = 0;
+        let mut pos = 2;
+
+        let scan = loop
+        {
+            if bytes.get(pos) != Some(&0xff)
+            {
+                return Err("A JPEG marker is missing");
+            }
+            while bytes.get(pos) == Some(&0xff)
+            {
+                pos += 1;
+            }
+
+            let marker = *bytes.get(pos).ok_or("The JPEG ends early")?;
+            if marker == 0xd9 || marker == 0xd8 || (0xd0..=0xd7).contains(&marker)
+            {
+                return Err("The JPEG has a marker out of place");
+            }
+
+            let len = be16(bytes.get(pos + 1..pos + 3).ok_or("The JPEG ends early")?) as usize;
+            let data = bytes.get(pos + 3..pos + 1 + len).ok_or("The JPEG ends early")?.to_vec();
+            pos += 1 + len;
+
+            match marker
+            {
+                0xc4 => segments.push(Segment::Tables(tables(&data)?)),
+                0xc0 | 0xc1 =>
+                {
+                    frame = Some(data.clone());
+                    segments.push(Segment::Marker(marker, data));
+                },
+                0xc2 | 0xc6 | 0xca | 0xce => return Err("Progressive JPEGs are not supported"),
+                0xc3 | 0xc5 | 0xc7 | 0xc9 | 0xcb | 0xcd | 0xcf =>
+                    return Err("Only baseline JPEGs are supported"),
+                0xdd =>
+                {
+                    restart = be16(data.get(..2).ok_or("The JPEG's DRI is too short")?) as usize;
+                    segments.push(Segment::Marker(marker, data));
+                },
+                0xda => break data,
+                _ => segments.push(Segment::Marker(marker, data)),
+            }
+        };
+
+        let frame = frame.ok_or("The JPEG has no frame header")?;
+        if frame.len() < 6 || frame[0] != 8
+        {
+            return Err("Only JPEGs of 8-bit samples are supported");
+        }
+
+        let height = be16(&frame[1..]);
+        let width = be16(&frame[3..]);
+        let count = frame[5] as usize;
+
+        if count == 0 || frame.len() < 6 + 3 * count
+        {
+            return Err("The JPEG's frame header is malformed");
+        }
+
+        // id, horizontal and vertical sampling of each component
+        let sampling = frame[6..].chunks(3)
+            .take(count)
+            .map(|c| (c[0], (c[1] >> 4) as usize, (c[1] & 15) as usize))
+            .collect::<Vec<_>>();
+
+        if sampling.iter().any(|c| c.1 == 0 || c.2 == 0)
+        {
+            return Err("The JPEG's frame header is malformed");
+        }
+        if scan.is_empty() || scan[0] as usize != count || scan.len() < 4 + 2 * count
+        {
+            return Err("JPEGs of more than one scan are not supported");
+        }
+
+        let components = scan[1..1 + 2 * count].chunks(2)
+            .map(|c| (c[1] >> 4, c[1] & 15))
+            .collect::<Vec<_>>();
+        let order = scan[1..1 + 2 * count].chunks(2)
+            .map(|c| sampling.iter().position(|s| s.0 == c[0]))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("The JPEG's scan names a component it does not have")?;
+
+        let hmax = sampling.iter().map(|c| c.1).max().unwrap();
+        let vmax = sampling.iter().map(|c| c.2).max().unwrap();
+        let (width, height) = (width as usize, height as usize);
+
+        let (mcu, mcus) = if count == 1
+        {
+            // a single component is coded block by block, ignoring its
+            // sampling
+            let (_, h, v) = sampling[0];
+            let columns = (width * h).div_ceil(hmax).div_ceil(8);
+            let rows = (height * v).div_ceil(vmax).div_ceil(8);
+
+            (vec![0], columns * rows)
+        }
+        else
+        {
+            let mcu = order.iter().enumerate()
+                .flat_map(|(i, &c)| vec![i; sampling[c].1 * sampling[c].2])
+                .collect();
+
+            (mcu, width.div_ceil(8 * hmax) * height.div_ceil(8 * vmax))
+        };
+
+        let start = pos;
+        let end = scan_end(bytes, start);
+
+        let mut jpeg = Jpeg
+        {
+            segments,
+            scan,
+            trailer: bytes[end..].to_vec(),
+            width: width as u16,
+            height: height as u16,
+            components,
+            restart,
+            mcu,
+            blocks: Vec::new(),
+        };
+
+        if !jpeg.trailer.starts_with(&[0xff, 0xd9])
+        {
+            return Err("JPEGs of more than one scan are not supported");
+        }
+
+        jpeg.decode(&bytes[start..end], mcus)?;
+
+        Ok(Some(jpeg))
+    }
+
+    pub fn dimensions(&self) -> (u32, u32)
+    {
+        (self.width as u32, self.height as u32)
+    }
+
+    /// Put the JPEG back together. A Huffman table that lacks a code for a
+    /// symbol the coefficients now need is built again to fit them, and every
+    /// other table is kept as it was.
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut frequencies = HashMap::new();
+
+        self.walk(|code| if let Code::Symbol { table, symbol, .. } = code
+        {
+            frequencies.entry(table).or_insert([0u32; 256])[symbol as usize] += 1;
+        });
+
+        let mut segments = self.segments.clone();
+        let mut codes = HashMap::new();
+
+        for (&key, counts) in &frequencies
+        {
+            let table = last_table(&mut segments, key).expect("a table the scan decoded with");
+
+            if (0..256).any(|s| counts[s] > 0 && !table.symbols.contains(&(s as u8)))
+            {
+                *table = optimal(key, counts);
+            }
+
+            codes.insert(key, table.codes());
+        }
+
+        let mut writer = BitWriter::default();
+
+        self.walk(|code| match code
+        {
+            Code::Symbol { table, symbol, bits, len } =>
+            {
+                let (code, size) = codes[&table][symbol as usize];
+                writer.put(code, size);
+                writer.put(bits, len);
+            },
+            Code::Restart(n) =>
+            {
+                writer.flush();
+                writer.bytes.extend_from_slice(&[0xff, 0xd0 + n]);
+            },
+        });
+        writer.flush();
+
+        let mut bytes = vec![0xff, 0xd8];
+
+        for segment in &segments
+        {
+            match *segment
+            {
+                Segment::Marker(marker, ref data) => put_segment(&mut bytes, marker, data),
+                Segment::Tables(ref tables) =>
+                {
+                    let data = tables.iter()
+                        .flat_map(|t|
+                        {
+                            let mut d = vec![t.class << 4 | t.id];
+                            d.extend_from_slice(&t.counts);
+                            d.extend_from_slice(&t.symbols);
+                            d
+                        })
+                        .collect::<Vec<_>>();
+
+                    put_segment(&mut bytes, 0xc4, &data);
+                },
+            }
+        }
+
+        put_segment(&mut bytes, 0xda, &self.scan);
+        bytes.append(&mut writer.bytes);
+        bytes.extend_from_slice(&self.trailer);
+
+        bytes
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+
+    /// Leave out the comments and application segments, which hold EXIF,
+    /// XMP, colour profiles and the like, and anything after the image.
+    /// JFIF and Adobe segments say how to read the pixels, so they stay.
+    pub fn strip(&mut self)
+    {
+        self.segments.retain(|segment| match *segment
+        {
+            Segment::Marker(marker, _) => !matches!(marker, 0xe1..=0xed | 0xef | 0xfe),
+            Segment::Tables(_) => true,
+        });
+        self.trailer.truncate(2);
+    }
+
+    /// The Huffman table the scan uses for a class and id, the last one
+    /// defined before it
+    fn table(&self, key: (u8, u8)) -> Option<&Table>
+    {
+        self.segments.iter().rev()
+            .filter_map(|s| match *s
+            {
+                Segment::Tables(ref tables) => tables.iter().rev().find(|t| (t.class, t.id) == key),
+                Segment::Marker(..) => None,
+            })
+            .next()
+    }
+
+    fn decode(&mut self, data: &[u8], mcus: usize) -> Result<(), &'static str>
+    {
+        let mut decoders = Vec::new();
+        for &(dc, ac) in &self.components
+        {
+            let table = |key| self.table(key)
+                .map(Decoder::new)
+                .ok_or("The JPEG's scan uses a Huffman table it does not define");
+
+            decoders.push((table((0, dc))?, table((1, ac))?));
+        }
+
+        // every block takes at least a bit for its DC and one for its end,
+        // so a frame header can not claim more than the scan could hold
+        if mcus.saturating_mul(self.mcu.len()) > data.len().saturating_mul(4)
+        {
+            return Err("The JPEG's scan is too short for the size of its frame");
+        }
+
+        let mut reader = BitReader::new(data);
+        let mut predictions = vec![0i16; self.components.len()];
+        let mut blocks = Vec::new();
+
+        for m in 0..mcus
+        {
+            if self.restart > 0 && m > 0 && m % self.restart == 0
+            {
+                reader.restart()?;
+                predictions.iter_mut().for_each(|p| *p = 0);
+            }
+
+            for &c in &self.mcu
+            {
+                let (ref dc, ref ac) = decoders[c];
+                let mut block = [0i16; 64];
+
+                let size = dc.decode(&mut reader)?;
+                if size > 11
+                {
+                    return Err("A JPEG DC difference is too large for 8-bit samples");
+                }
+
+                predictions[c] = predictions[c].wrapping_add(extend(reader.receive(size)?, size));
+                block[0] = predictions[c];
+
+                let mut k = 1;
+                while k < 64
+                {
+                    let rs = ac.decode(&mut reader)?;
+                    let (run, size) = ((rs >> 4) as usize, rs & 15);
+
+                    if size == 0
+                    {
+                        if run != 15
+                        {
+                            break;
+                        }
+
+                        k += 16;
+                        continue;
+                    }
+
+                    k += run;
+                    if k > 63
+                    {
+                        return Err("A JPEG block runs past its 64 coefficients");
+                    }
+
+                    block[k] = extend(reader.receive(size)?, size);
+                    k += 1;
+                }
+
+                blocks.push(block);
+            }
+        }
+
+        self.blocks = blocks;
+
+        Ok(())
+    }
+
+    /// Go through the entropy-coded data the blocks make, in order
+    fn walk<F: FnMut(Code)>(&self, mut f: F)
+    {
+        let mut predictions = vec![0i16; self.components.len()];
+
+        for (m, mcu) in self.blocks.chunks(self.mcu.len()).enumerate()
+        {
+            if self.restart > 0 && m > 0 && m % self.restart == 0
+            {
+                f(Code::Restart(((m / self.restart - 1) % 8) as u8));
+                predictions.iter_mut().for_each(|p| *p = 0);
+            }
+
+            for (block, &c) in mcu.iter().zip(&self.mcu)
+            {
+                let (dc, ac) = self.components[c];
+                let (bits, len) = magnitude(block[0].wrapping_sub(predictions[c]));
+                predictions[c] = block[0];
+
+                f(Code::Symbol { table: (0, dc), symbol: len, bits, len });
+
+                let mut run = 0;
+                for &coefficient in &block[1..]
+                {
+                    if coefficient == 0
+                    {
+                        run += 1;
+                        continue;
+                    }
+
+                    while run > 15
+                    {
+                        f(Code::Symbol { table: (1, ac), symbol: 0xf0, bits: 0, len: 0 });
+                        run -= 16;
+                    }
+
+                    let (bits, len) = magnitude(coefficient);
+                    f(Code::Symbol { table: (1, ac), symbol: run << 4 | len, bits, len });
+                    run = 0;
+                }
+
+                if run > 0
+                {
+                    f(Code::Symbol { table: (1, ac), symbol: 0, bits: 0, len: 0 });
+                }
+            }
+        }
+    }
+}
+
+impl Table
+{
+    /// The code and its length for each symbol, a length of 0 for a symbol
+    /// without one
+    fn codes(&self) -> Vec<(u16, u8)>
+    {
+        let mut codes = vec![(0, 0); 256];
+        let mut code = 0u16;
+        let mut symbols = self.symbols.iter();
+
+        for (len, &count) in (1..=16).zip(&self.counts)
+        {
+            for _ in 0..count
+            {
+                if let Some(&s) = symbols.next()
+                {
+                    codes[s as usize] = (code, len);
+                }
+                code = code.wrapping_add(1);
+            }
+            code <<= 1;
+        }
+
+        codes
+    }
+}
+
+/// Read the tables of a DHT segment
+fn tables(data: &[u8]) -> Result<Vec<Table>, &'static str>
+{
+    let mut tables = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len()
+    {
+        let head = data.get(pos..pos + 17).ok_or("A JPEG Huffman table is cut short")?;
+        let mut counts = [0; 16];
+        counts.copy_from_slice(&head[1..]);
+
+        let total = counts.iter().map(|&c| c as usize).sum::<usize>();
+        let symbols = data.get(pos + 17..pos + 17 + total).ok_or("A JPEG Huffman table is cut short")?;
+
+        if head[0] >> 4 > 1 || head[0] & 15 > 3
+        {
+            return Err("A JPEG Huffman table has a bad class or id");
+        }
+
+        tables.push(Table
+        {
+            class: head[0] >> 4,
+            id: head[0] & 15,
+            counts,
+            symbols: symbols.to_vec(),
+        });
+        pos += 17 + total;
+    }
+
+    Ok(tables)
+}
+
+/// The last definition of a table in the segments
+fn last_table(segments: &mut [Segment], key: (u8, u8)) -> Option<&mut Table>
+{
+    segments.iter_mut().rev()
+        .filter_map(|s| match *s
+        {
+            Segment::Tables(ref mut tables) => tables.iter_mut().rev().find(|t| (t.class, t.id) == key),
+            Segment::Marker(..) => None,
+        })
+        .next()
+}
+
+/// Build the Huffman table that codes the symbols in the fewest bits, with
+/// no code longer than 16 bits or made of only ones, the way Annex K of the
+/// standard lays out
+fn optimal(key: (u8, u8), frequencies: &[u32; 256]) -> Table
+{
+    // one more symbol, that no real one gets the code of all ones
+    let mut freq = frequencies.iter().map(|&f| f as u64).collect::<Vec<_>>();
+    freq.push(1);
+
+    let mut sizes = vec![0usize; 257];
+    let mut others = vec![None; 257];
+
+    loop
+    {
+        // the two least frequent, the later of equals first
+        let least = |freq: &[u64], not: Option<usize>| (0..257)
+            .filter(|&i| freq[i] > 0 && Some(i) != not)
+            .min_by_key(|&i| (freq[i], ::std::cmp::Reverse(i)));
+
+        let mut v1 = match least(&freq, None)
+        {
+            Some(v) => v,
+            None => break,
+        };
+        let mut v2 = match least(&freq, Some(v1))
+        {
+            Some(v) => v,
+            None => break,
+        };
+
+        freq[v1] += freq[v2];
+        freq[v2] = 0;
+
+        sizes[v1] += 1;
+        while let Some(next) = others[v1]
+        {
+            v1 = next;
+            sizes[v1] += 1;
+        }
+        others[v1] = Some(v2);
+
+        sizes[v2] += 1;
+        while let Some(next) = others[v2]
+        {
+            v2 = next;
+            sizes[v2] += 1;
+        }
+    }
+
+    let mut bits = [0usize; 33];
+    for &size in sizes.iter().filter(|&&s| s > 0)
+    {
+        bits[size] += 1;
+    }
+
+    // move codes longer than 16 bits up the tree
+    for i in (17..33).rev()
+    {
+        while bits[i] > 0
+        {
+            let mut j = i - 2;
+            while bits[j] == 0
+            {
+                j -= 1;
+            }
+
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+
+    // drop the extra symbol's code, which is the longest
+    let longest = (1..17).rev().find(|&i| bits[i] > 0).unwrap();
+    bits[longest] -= 1;
+
+    let mut counts = [0; 16];
+    for (count, &b) in counts.iter_mut().zip(&bits[1..17])
+    {
+        *count = b as u8;
+    }
+
+    let mut symbols = (0..256).filter(|&s| sizes[s] > 0).collect::<Vec<_>>();
+    symbols.sort_by_key(|&s| sizes[s]);
+
+    Table
+    {
+        class: key.0,
+        id: key.1,
+        counts,
+        symbols: symbols.into_iter().map(|s| s as u8).collect(),
+    }
+}
+
+/// Where the entropy-coded data starting at `start` ends, at the first
+/// marker that is neither stuffing nor a restart
+fn scan_end(bytes: &[u8], start: usize) -> usize
+{
+    let mut pos = start;
+
+    while pos + 1 < bytes.len()
+    {
+        if bytes[pos] == 0xff && bytes[pos + 1] != 0 && !(0xd0..=0xd7).contains(&bytes[pos + 1])
+        {
+            return pos;
+        }
+
+        pos += 1;
+    }
+
+    bytes.len()
+}
+
+fn put_segment(bytes: &mut Vec<u8>, marker: u8, data: &[u8])
+{
+    bytes.extend_from_slice(&[0xff, marker]);
+    bytes.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
+
+fn be16(b: &[u8]) -> u16
+{
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+/// Turn `size` received bits into the signed value they code
+fn extend(bits: u16, size: u8) -> i16
+{
+    if size == 0
+    {
+        0
+    }
+    else if bits < 1 << (size - 1)
+    {
+        (bits as i32 - (1 << size) + 1) as i16
+    }
+    else
+    {
+        bits as i16
+    }
+}
+
+/// The bits that code a value after its size, and that size
+fn magnitude(value: i16) -> (u16, u8)
+{
+    let size = (32 - (value as i32).unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value as i32 - 1 } else { value as i32 };
+
+    ((bits & ((1 << size) - 1)) as u16, size)
+}
+
+/// Decodes the codes of a Huffman table, as in Annex F of the standard
+struct Decoder
+{
+    maxcode: [i32; 17],
+    mincode: [i32; 17],
+    offsets: [usize; 17],
+    symbols: Vec<u8>,
+}
+
+impl Decoder
+{
+    fn new(table: &Table) -> Decoder
+    {
+        let mut decoder = Decoder
+        {
+            maxcode: [-1; 17],
+            mincode: [0; 17],
+            offsets: [0; 17],
+            symbols: table.symbols.clone(),
+        };
+
+        let mut code = 0;
+        let mut k = 0;
+
+        for len in 1..17
+        {
+            let count = table.counts[len - 1] as usize;
+
+            if count > 0
+            {
+                decoder.offsets[len] = k;
+                decoder.mincode[len] = code;
+                code += count as i32;
+                k += count;
+                decoder.maxcode[len] = code - 1;
+            }
+
+            code <<= 1;
+        }
+
+        decoder
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, &'static str>
+    {
+        let mut code = 0;
+
+        for len in 1..17
+        {
+            code = code << 1 | reader.bit()? as i32;
+
+            if code <= self.maxcode[len]
+            {
+                return self.symbols.get(self.offsets[len] + (code - self.mincode[len]) as usize)
+                    .cloned()
+                    .ok_or("A JPEG Huffman code has no symbol");
+            }
+        }
+
+        Err("The JPEG's scan has a code its Huffman table does not")
+    }
+}
+
+struct BitReader<'a>
+{
+    data: &'a [u8],
+    pos: usize,
+    byte: u8,
+    left: u8,
+}
+
+impl<'a> BitReader<'a>
+{
+    fn new(data: &'a [u8]) -> BitReader<'a>
+    {
+        BitReader
+        {
+            data,
+            pos: 0,
+            byte: 0,
+            left: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u16, &'static str>
+    {
+        if self.left == 0
+        {
+            let byte = *self.data.get(self.pos).ok_or("The JPEG's scan ends early")?;
+
+            // a 0xff byte is followed by a zero, so it is not read as a marker
+            if byte == 0xff && self.data.get(self.pos + 1) != Some(&0)
+            {
+                return Err("The JPEG's scan ends early");
+            }
+
+            self.pos += if byte == 0xff { 2 } else { 1 };
+            self.byte = byte;
+            self.left = 8;
+        }
+
+        self.left -= 1;
+
+        Ok((self.byte >> self.left) as u16 & 1)
+    }
+
+    fn receive(&mut self, size: u8) -> Result<u16, &'static str>
+    {
+        let mut bits = 0;
+
+        for _ in 0..size
+        {
+            bits = bits << 1 | self.bit()?;
+        }
+
+        Ok(bits)
+    }
+
+    /// Skip to the byte after the next restart marker
+    fn restart(&mut self) -> Result<(), &'static str>
+    {
+        self.left = 0;
+
+        match self.data.get(self.pos..self.pos + 2)
+        {
+            Some(&[0xff, m]) if (0xd0..=0xd7).contains(&m) =>
+            {
+                self.pos += 2;
+                Ok(())
+            },
+            _ => Err("A JPEG restart marker is missing"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter
+{
+    bytes: Vec<u8>,
+    byte: u8,
+    len: u8,
+}
+
+impl BitWriter
+{
+    fn put(&mut self, bits: u16, len: u8)
+    {
+        for i in (0..len).rev()
+        {
+            self.byte = self.byte << 1 | (bits >> i) as u8 & 1;
+            self.len += 1;
+
+            if self.len == 8
+            {
+                self.bytes.push(self.byte);
+                if self.byte == 0xff
+                {
+                    self.bytes.push(0);
+                }
+
+                self.byte = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Fill the last byte with ones
+    fn flush(&mut self)
+    {
+        while self.len > 0
+        {
+            self.put(1, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use image::{self, ImageBuffer, Rgb};
+    use image::jpeg::JPEGEncoder;
+
+    use super::*;
+
+    fn jpeg() -> Vec<u8>
+    {
+        let image = ImageBuffer::from_fn(37, 21, |x, y|
+            Rgb { data: [(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8] });
+        let mut bytes = Vec::new();
+
+        JPEGEncoder::new_with_quality(&mut bytes, 85)
+            .encode(&image, 37, 21, image::RGB(8))
+            .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn writes_back_the_same_file()
+    {
+        let bytes = jpeg();
+        let jpeg = Jpeg::read(&bytes).unwrap().unwrap();
+
+        assert_eq!(jpeg.dimensions(), (37, 21));
+        assert_eq!(jpeg.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn refuses_a_frame_larger_than_its_scan()
+    {
+        let mut bytes = jpeg();
+        let sof = bytes.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+        bytes[sof + 5..sof + 9].copy_from_slice(&[0xff; 4]);
+
+        assert_eq!(Jpeg::read(&bytes).err(), Some("The JPEG's scan is too short for the size of its frame"));
+    }
+
+    #[test]
+    fn new_symbols_get_a_new_table()
+    {
+        let bytes = jpeg();
+        let mut jpeg = Jpeg::read(&bytes).unwrap().unwrap();
+
+        // large values, far from anything the tables were built for
+        for (i, block) in jpeg.blocks.iter_mut().enumerate()
+        {
+            block[63] = if i % 2 == 0 { 700 } else { -3 };
+            block[20] = (i % 50) as i16 - 25;
+        }
+
+        let written = jpeg.to_bytes();
+        let read = Jpeg::read(&written).unwrap().unwrap();
+
+        assert!(read.blocks == jpeg.blocks);
+        assert!(image::load_from_memory(&written).is_ok());
+    }
+
+    #[test]
+    fn optimal_codes_fit()
+    {
+        let mut frequencies = [0; 256];
+        for (s, f) in frequencies.iter_mut().enumerate()
+        {
+            // a long tail of rare symbols, which would want codes over 16 bits
+            *f = if s < 40 { 1 << (s / 2) } else { 1 };
+        }
+
+        let table = optimal((1, 0), &frequencies);
+        let codes = table.codes();
+
+        assert_eq!(table.symbols.len(), 256);
+        assert!(codes.iter().all(|&(_, len)| (1..=16).contains(&len)));
+        // the most frequent symbols get the shortest codes
+        assert_eq!(codes[39].1, codes.iter().map(|c| c.1).min().unwrap());
+    }
+}
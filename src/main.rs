@@ -2,15 +2,53 @@ extern crate image;
 extern crate clap;
 extern crate rand;
 extern crate itertools;
+extern crate sha2;
+extern crate hmac;
+extern crate pbkdf2;
+extern crate png;
+extern crate gif;
+extern crate lzw;
+extern crate inflate;
 
 use clap::*;
 
+mod analysis;
+mod animation;
+mod attack;
+mod bench;
+mod chunks;
 mod codec;
+mod container;
+mod crypto;
+mod deniable;
+mod diff;
+mod header;
+mod heatmap;
+mod planes;
+mod rank;
+mod scan;
+mod split;
+mod shamir;
+mod forensics;
+mod fountain;
+mod tiff;
+mod jpeg;
+mod dct;
+mod webp;
+mod output;
+mod png_image;
+mod pages;
 
+// the pixel codecs predate these lints
+#[allow(clippy::map_clone, clippy::derivable_impls)]
 mod rgba;
+#[allow(clippy::map_clone, clippy::derivable_impls)]
 mod rgb;
+#[allow(clippy::map_clone, clippy::derivable_impls)]
 mod gray_alpha;
+mod palette;
 
+#[allow(clippy::manual_is_multiple_of)]
 mod utils;
 
 fn main()
@@ -33,7 +71,68 @@ fn main()
             .arg(Arg::with_name("OUTPUT")
                  .help("The output image")
                  .index(2)
-                 .required(true)))
+                 .required_unless("dry-run"))
+            .arg(Arg::with_name("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .help("Write OUTPUT as FORMAT, whatever its extension")
+                 .takes_value(true)
+                 .possible_values(&["png", "bmp", "tiff", "webp-lossless"]))
+            .arg(Arg::with_name("strip")
+                 .long("strip")
+                 .help("Leave out the metadata of SOURCE, such as text, EXIF and colour profiles, rather than carry it over"))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Encrypt the payload so only PASSWORD can decode it")
+                 .takes_value(true))
+            .arg(Arg::with_name("decoy")
+                 .long("decoy")
+                 .value_name("FILE")
+                 .help("Also encode FILE as a decoy payload")
+                 .takes_value(true)
+                 .requires_all(&["password", "decoy-password"]))
+            .arg(Arg::with_name("decoy-password")
+                 .long("decoy-password")
+                 .value_name("PASSWORD")
+                 .help("The password that decodes the decoy payload")
+                 .takes_value(true)
+                 .requires("decoy"))
+            .arg(Arg::with_name("pad")
+                 .long("pad")
+                 .help("Fill the rest of the image with random bits, and whiten the header and payload to look like them")
+                 .conflicts_with("password"))
+            .arg(Arg::with_name("file")
+                 .short("f")
+                 .long("file")
+                 .value_name("FILE")
+                 .help("Encode FILE, or every file under a directory, instead of stdin")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("dry-run")
+                 .long("dry-run")
+                 .help("Report which samples would be used, without writing OUTPUT"))
+            .arg(Arg::with_name("heatmap")
+                 .long("heatmap")
+                 .value_name("IMAGE")
+                 .help("Save an image of the samples a dry run would use")
+                 .takes_value(true)
+                 .requires("dry-run"))
+            .arg(Arg::with_name("check")
+                 .long("check")
+                 .value_name("ACTION")
+                 .help("What to do when the output looks detectable")
+                 .takes_value(true)
+                 .possible_values(&["off", "warn", "refuse"])
+                 .default_value("warn"))
+            .arg(Arg::with_name("threshold")
+                 .long("threshold")
+                 .value_name("SCORE")
+                 .help("The detectability, from 0 to 1, the check acts above")
+                 .takes_value(true)
+                 .default_value("0.5")))
         .subcommand(SubCommand::with_name("decode")
             .about("decodes a file")
             .arg(Arg::with_name("mode")
@@ -43,16 +142,23 @@ fn main()
                  .help("Set the decoding mode, default depends on SOURCE type")
                  .takes_value(true))
             .arg(Arg::with_name("SOURCE")
-                 .help("The image source")
+                 .help("The image source, or several images or directories of fountain coded images")
                  .index(1)
+                 .multiple(true)
                  .required(true))
             .arg(Arg::with_name("length")
                  .short("l")
                  .long("length")
-                 .help("the amount of bytes to decode")
+                 .help("the amount of bytes to decode, read from the header if not given")
                  .value_name("LENGTH")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Decode the payload PASSWORD opens")
                  .takes_value(true)
-                 .required(true)))
+                 .conflicts_with("length")))
         .subcommand(SubCommand::with_name("estimate")
                 .about("estimate how many bytes will fit into a file")
                 .arg(Arg::with_name("SOURCE")
@@ -64,201 +170,3109 @@ fn main()
                      .long("mode")
                      .value_name("MODE")
                      .help("The encoding mode, default depends on SOURCE type")
+                     .takes_value(true))
+                .arg(Arg::with_name("deniable")
+                     .short("d")
+                     .long("deniable")
+                     .help("Estimate the size of each password protected payload")))
+        .subcommand(SubCommand::with_name("ls")
+            .about("lists the files encoded into an image")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The image source")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the decoding mode, default depends on SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("List the files PASSWORD opens")
+                 .takes_value(true)))
+        .subcommand(SubCommand::with_name("extract")
+            .about("writes out the files encoded into an image")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The image source")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("NAME")
+                 .help("Only extract the file called NAME")
+                 .index(2))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the decoding mode, default depends on SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Extract the files PASSWORD opens")
+                 .takes_value(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("DIR")
+                 .help("The directory to write files to, default is the current directory")
+                 .takes_value(true)))
+        .subcommand(SubCommand::with_name("split")
+            .about("splits a file across several images")
+            .arg(Arg::with_name("PAYLOAD")
+                 .help("The file to split")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("SOURCES")
+                 .help("The image sources, filled in order")
+                 .index(2)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("DIR")
+                 .help("The directory to write the output images to")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the encoding mode, default depends on each SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("pad")
+                 .long("pad")
+                 .help("Fill the rest of each image with random bits")))
+        .subcommand(SubCommand::with_name("join")
+            .about("joins a file split across several images")
+            .arg(Arg::with_name("SOURCES")
+                 .help("The image sources, in any order")
+                 .index(1)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("The file to write to, default is stdout")
+                 .takes_value(true))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the decoding mode, default depends on each SOURCE type")
+                 .takes_value(true)))
+        .subcommand(SubCommand::with_name("fountain")
+            .about("spreads fountain coded symbols of a file across several images, so any large enough subset recovers it")
+            .arg(Arg::with_name("PAYLOAD")
+                 .help("The file to encode")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("SOURCES")
+                 .help("The image sources, each is filled with symbols")
+                 .index(2)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("DIR")
+                 .help("The directory to write the output images to")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("symbol-size")
+                 .short("s")
+                 .long("symbol-size")
+                 .value_name("BYTES")
+                 .help("The number of bytes in each symbol")
+                 .takes_value(true)
+                 .default_value("64"))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the encoding mode, default depends on each SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("pad")
+                 .long("pad")
+                 .help("Fill the rest of each image with random bits")))
+        .subcommand(SubCommand::with_name("share")
+            .about("shares a file across several images, so that any THRESHOLD of them recover it")
+            .arg(Arg::with_name("PAYLOAD")
+                 .help("The file to share")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("SOURCES")
+                 .help("The image sources, one share is encoded into each")
+                 .index(2)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("threshold")
+                 .short("k")
+                 .long("threshold")
+                 .value_name("THRESHOLD")
+                 .help("The number of images needed to recover the file")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("DIR")
+                 .help("The directory to write the output images to")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the encoding mode, default depends on each SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Encrypt the file with PASSWORD before sharing it")
+                 .takes_value(true))
+            .arg(Arg::with_name("pad")
+                 .long("pad")
+                 .help("Fill the rest of each image with random bits")))
+        .subcommand(SubCommand::with_name("combine")
+            .about("recovers a file shared across several images")
+            .arg(Arg::with_name("SOURCES")
+                 .help("The image sources, in any order")
+                 .index(1)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("The file to write to, default is stdout")
+                 .takes_value(true))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the decoding mode, default depends on each SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Decrypt the file with PASSWORD")
+                 .takes_value(true)))
+        .subcommand(SubCommand::with_name("planes")
+            .about("exports bit planes as black and white images")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The image source")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("OUTPUT")
+                 .help("The output image")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("channel")
+                 .short("c")
+                 .long("channel")
+                 .value_name("CHANNEL")
+                 .help("The channel, by name, e.g. r or a")
+                 .takes_value(true)
+                 .required_unless("all"))
+            .arg(Arg::with_name("bit")
+                 .short("b")
+                 .long("bit")
+                 .value_name("BIT")
+                 .help("The bit, 0 is least significant")
+                 .takes_value(true)
+                 .default_value("0"))
+            .arg(Arg::with_name("all")
+                 .short("a")
+                 .long("all")
+                 .help("Export every bit of every channel, or of CHANNEL, as a contact sheet")))
+        .subcommand(SubCommand::with_name("attack")
+            .about("checks whether a payload survives common transforms")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The encoded image")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("ops")
+                 .long("ops")
+                 .value_name("OPS")
+                 .help("The transforms to try, comma separated, from crop[:PIXELS], rescale[:FACTOR] up to 4, \
+                        jpeg[:QUALITY], noise[:AMPLITUDE] and png-requantize[:COLOURS]")
+                 .takes_value(true)
+                 .default_value(attack::DEFAULT))
+            .arg(Arg::with_name("mode")
+                 .short("m")
+                 .long("mode")
+                 .value_name("MODE")
+                 .help("Set the decoding mode, default depends on SOURCE type")
+                 .takes_value(true))
+            .arg(Arg::with_name("length")
+                 .short("l")
+                 .long("length")
+                 .value_name("LENGTH")
+                 .help("the amount of bytes to decode, read from the header if not given")
+                 .takes_value(true))
+            .arg(Arg::with_name("password")
+                 .short("p")
+                 .long("password")
+                 .value_name("PASSWORD")
+                 .help("Decode the payload PASSWORD opens")
+                 .takes_value(true)
+                 .conflicts_with("length")))
+        .subcommand(SubCommand::with_name("bench-detect")
+            .about("measures how well the detectors find each mode over a corpus of covers")
+            .arg(Arg::with_name("SOURCES")
+                 .help("The cover images, or directories of them")
+                 .index(1)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("modes")
+                 .long("modes")
+                 .value_name("MODES")
+                 .help("The modes to test, comma separated, e.g. alpha or rgba:all, default is every mode")
+                 .takes_value(true))
+            .arg(Arg::with_name("rates")
+                 .long("rates")
+                 .value_name("RATES")
+                 .help("The shares of each mode's capacity to fill, comma separated")
+                 .takes_value(true)
+                 .default_value("0.05,0.1,0.25,0.5,1"))
+            .arg(Arg::with_name("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .help("The report format")
+                 .takes_value(true)
+                 .possible_values(&["csv", "json"])
+                 .default_value("csv"))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("Write the report to FILE instead of stdout")
+                 .takes_value(true)))
+        .subcommand(SubCommand::with_name("diff")
+            .about("compares a stego image with its cover")
+            .arg(Arg::with_name("COVER")
+                 .help("The cover image")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("STEGO")
+                 .help("The stego image")
+                 .index(2)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("OUTPUT")
+                 .help("Write the amplified difference to OUTPUT")
+                 .takes_value(true))
+            .arg(Arg::with_name("gain")
+                 .short("g")
+                 .long("gain")
+                 .value_name("GAIN")
+                 .help("What differences are multiplied by in OUTPUT")
+                 .takes_value(true)
+                 .default_value("128")))
+        .subcommand(SubCommand::with_name("rank")
+            .about("ranks candidate carriers for a payload, best first")
+            .arg(Arg::with_name("SOURCES")
+                 .help("The candidate images, or directories of them")
+                 .index(1)
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("payload")
+                 .long("payload")
+                 .value_name("FILE")
+                 .help("The payload to find a carrier for, as encode would read it from stdin")
+                 .takes_value(true)
+                 .required_unless("file"))
+            .arg(Arg::with_name("file")
+                 .short("f")
+                 .long("file")
+                 .value_name("FILE")
+                 .help("Find a carrier for FILE, or every file under a directory, packed as encode -f would")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .conflicts_with("payload"))
+            .arg(Arg::with_name("pad")
+                 .long("pad")
+                 .help("Allow for the header encode --pad writes")))
+        .subcommand(SubCommand::with_name("scan")
+            .about("tries every common way of hiding bits and reports what it finds")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The image source")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("top")
+                 .short("n")
+                 .long("top")
+                 .value_name("COUNT")
+                 .help("The number of hits to show")
+                 .takes_value(true)
+                 .default_value("20"))
+            .arg(Arg::with_name("extract")
+                 .short("x")
+                 .long("extract")
+                 .value_name("LAYOUT")
+                 .help("Write out the bytes of LAYOUT, as printed for a hit, e.g. 0,rgb,lsb,xy, or 1-0,a,msb,yx to read bit 1 of each sample before bit 0")
+                 .takes_value(true))
+            .arg(Arg::with_name("length")
+                 .short("l")
+                 .long("length")
+                 .value_name("LENGTH")
+                 .help("The amount of bytes to extract, default is as many as there are")
+                 .takes_value(true)
+                 .requires("extract")))
+        .subcommand(SubCommand::with_name("features")
+            .about("extracts steganalysis features of each channel, for training detectors")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The image source")
+                 .index(1)
+                 .required(true))
+            .arg(Arg::with_name("set")
+                 .long("set")
+                 .value_name("SET")
+                 .help("The feature set")
+                 .takes_value(true)
+                 .possible_values(&["spam", "srm-lite"])
+                 .default_value("spam"))
+            .arg(Arg::with_name("channel")
+                 .short("c")
+                 .long("channel")
+                 .value_name("CHANNEL")
+                 .help("Only extract features of CHANNEL, by name, e.g. r or a")
+                 .takes_value(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("Write the features to FILE instead of stdout")
+                 .takes_value(true))
+            .arg(Arg::with_name("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .help("The output format, default is npy for a FILE ending in .npy and csv otherwise")
+                 .takes_value(true)
+                 .possible_values(&["csv", "npy"])))
+        .subcommand(SubCommand::with_name("forensics")
+            .about("looks for data hidden in a file outside its pixels")
+            .arg(Arg::with_name("SOURCE")
+                 .help("The PNG, JPEG or BMP file")
+                 .index(1)
+                 .required(true)))
+        .subcommand(SubCommand::with_name("analyze")
+            .about("looks for signs of steganography in an image")
+            .subcommand(SubCommand::with_name("chi2")
+                .about("the chi-square attack on each channel, over the whole image and along the scan order")
+                .arg(Arg::with_name("SOURCE")
+                     .help("The image source")
+                     .index(1)
+                     .required(true))
+                .arg(Arg::with_name("channel")
+                     .short("c")
+                     .long("channel")
+                     .value_name("CHANNEL")
+                     .help("Only analyze CHANNEL, by name, e.g. r or a")
+                     .takes_value(true))
+                .arg(Arg::with_name("steps")
+                     .short("s")
+                     .long("steps")
+                     .value_name("STEPS")
+                     .help("The number of steps along the scan order")
+                     .takes_value(true)
+                     .default_value("20")))
+            .subcommand(SubCommand::with_name("rs")
+                .about("estimates the relative message length in each channel by RS analysis")
+                .arg(Arg::with_name("SOURCE")
+                     .help("The image source")
+                     .index(1)
+                     .required(true))
+                .arg(Arg::with_name("channel")
+                     .short("c")
+                     .long("channel")
+                     .value_name("CHANNEL")
+                     .help("Only analyze CHANNEL, by name, e.g. r or a")
                      .takes_value(true)))
+            .subcommand(SubCommand::with_name("spa")
+                .about("estimates the relative message length in each channel by sample pair analysis")
+                .arg(Arg::with_name("SOURCE")
+                     .help("The image source")
+                     .index(1)
+                     .required(true))
+                .arg(Arg::with_name("channel")
+                     .short("c")
+                     .long("channel")
+                     .value_name("CHANNEL")
+                     .help("Only analyze CHANNEL, by name, e.g. r or a")
+                     .takes_value(true))))
         .get_matches();
 
-    if let Some(matches) = matches.subcommand_matches("encode")
-    {
-        dispatch_encode(
-            matches.value_of("mode"),
-            matches.value_of("SOURCE").unwrap(),
-            matches.value_of("OUTPUT").unwrap()
-        );
+    if let Some(matches) = matches.subcommand_matches("encode")
+    {
+        dispatch_encode(
+            matches.value_of("mode"),
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("OUTPUT"),
+            &EncodeOptions
+            {
+                password: matches.value_of("password"),
+                decoy: matches.value_of("decoy").map(|file|
+                    (matches.value_of("decoy-password").unwrap(), file)),
+                pad: matches.is_present("pad"),
+                files: matches.values_of("file")
+                    .map(|files| files.collect())
+                    .unwrap_or_default(),
+                dry_run: matches.is_present("dry-run"),
+                heatmap: matches.value_of("heatmap"),
+                check: match matches.value_of("check").unwrap()
+                {
+                    "off" => SelfCheck::Off,
+                    "refuse" => SelfCheck::Refuse,
+                    _ => SelfCheck::Warn,
+                },
+                threshold: match matches.value_of("threshold").unwrap().parse::<f64>()
+                {
+                    Ok(t) if (0.0..=1.0).contains(&t) => t,
+                    _ => error_out("threshold must be a number from 0 to 1"),
+                },
+                format: matches.value_of("format").map(|f| f.parse().unwrap()),
+                strip: matches.is_present("strip"),
+            }
+        );
+    }
+    
+    if let Some(matches) = matches.subcommand_matches("decode")
+    {
+        let sources = matches.values_of("SOURCE").unwrap().collect::<Vec<_>>();
+
+        if sources.len() > 1 || Path::new(sources[0]).is_dir()
+        {
+            dispatch_fountain_decode(
+                matches.value_of("mode"),
+                &expand_sources(&sources)
+            );
+        }
+        else
+        {
+            dispatch_decode(
+                matches.value_of("mode"),
+                sources[0],
+                matches.value_of("length"),
+                matches.value_of("password")
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("estimate")
+    {
+        dispatch_estimate(
+            matches.value_of("mode"),
+            matches.value_of("SOURCE").unwrap(),
+            matches.is_present("deniable")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ls")
+    {
+        list(&dispatch_container(
+            matches.value_of("mode"),
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("password")
+        ));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("extract")
+    {
+        extract_files(
+            &dispatch_container(
+                matches.value_of("mode"),
+                matches.value_of("SOURCE").unwrap(),
+                matches.value_of("password")
+            ),
+            matches.value_of("NAME"),
+            matches.value_of("output").unwrap_or(".")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("split")
+    {
+        dispatch_split(
+            matches.value_of("mode"),
+            matches.value_of("PAYLOAD").unwrap(),
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("output").unwrap(),
+            matches.is_present("pad")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("join")
+    {
+        dispatch_join(
+            matches.value_of("mode"),
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("output")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("fountain")
+    {
+        dispatch_fountain(
+            matches.value_of("mode"),
+            matches.value_of("PAYLOAD").unwrap(),
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("symbol-size").unwrap(),
+            matches.is_present("pad")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("planes")
+    {
+        dispatch_planes(
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("channel"),
+            matches.value_of("bit").unwrap(),
+            matches.is_present("all")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("attack")
+    {
+        dispatch_attack(
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("ops").unwrap(),
+            matches.value_of("mode"),
+            matches.value_of("length"),
+            matches.value_of("password")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("bench-detect")
+    {
+        dispatch_bench(
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("modes"),
+            matches.value_of("rates").unwrap(),
+            matches.value_of("format").unwrap(),
+            matches.value_of("output")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("diff")
+    {
+        dispatch_diff(
+            matches.value_of("COVER").unwrap(),
+            matches.value_of("STEGO").unwrap(),
+            matches.value_of("output"),
+            matches.value_of("gain").unwrap()
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("rank")
+    {
+        dispatch_rank(
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("payload"),
+            &matches.values_of("file").map(|v| v.collect::<Vec<_>>()).unwrap_or_default(),
+            matches.is_present("pad")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("scan")
+    {
+        dispatch_scan(
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("top").unwrap(),
+            matches.value_of("extract"),
+            matches.value_of("length")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("features")
+    {
+        dispatch_features(
+            matches.value_of("SOURCE").unwrap(),
+            matches.value_of("set").unwrap(),
+            matches.value_of("channel"),
+            matches.value_of("output"),
+            matches.value_of("format")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("forensics")
+    {
+        dispatch_forensics(matches.value_of("SOURCE").unwrap());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("analyze")
+    {
+        if let Some(matches) = matches.subcommand_matches("chi2")
+        {
+            dispatch_chi2(
+                matches.value_of("SOURCE").unwrap(),
+                matches.value_of("channel"),
+                matches.value_of("steps").unwrap()
+            );
+        }
+
+        if let Some(matches) = matches.subcommand_matches("rs")
+        {
+            dispatch_length(
+                matches.value_of("SOURCE").unwrap(),
+                matches.value_of("channel"),
+                rs::rs
+            );
+        }
+
+        if let Some(matches) = matches.subcommand_matches("spa")
+        {
+            dispatch_length(
+                matches.value_of("SOURCE").unwrap(),
+                matches.value_of("channel"),
+                spa::spa
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("share")
+    {
+        dispatch_share(
+            matches.value_of("mode"),
+            matches.value_of("PAYLOAD").unwrap(),
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("threshold").unwrap(),
+            matches.value_of("password"),
+            matches.is_present("pad")
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("combine")
+    {
+        dispatch_combine(
+            matches.value_of("mode"),
+            &matches.values_of("SOURCES").unwrap().collect::<Vec<_>>(),
+            matches.value_of("output"),
+            matches.value_of("password")
+        );
+    }
+}
+
+use std::fs::File;
+use std::io::{stdin, stdout, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::{open, DynamicImage, GenericImage};
+use rand::{OsRng, Rng, StdRng};
+
+use codec::Codec;
+use rgba::RgbaCodec;
+use rgb::RgbCodec;
+use gray_alpha::GrayAlphaCodec;
+use animation::Apng;
+use tiff::Tiff;
+use jpeg::Jpeg;
+use dct::{DctCodec, DctImage, DctMode};
+use output::Format;
+use png_image::PngImage;
+use pages::{Pages, PagesCodec};
+use palette::{IndexedImage, OrderedImage, PaletteCodec, PermutationCodec};
+use header::{Fountain, Header, Piece, Share};
+use container::Entry;
+use utils::extract;
+use analysis::{check, chi2, features, rs, spa};
+
+/// A header and the payload after it, or why they could not be found
+type Found = ::std::result::Result<(Header, Vec<u8>), &'static str>;
+
+/// Options for encoding beyond the mode
+struct EncodeOptions<'a>
+{
+    /// Encrypt the payload so only this password can decode it
+    password: Option<&'a str>,
+    /// The password for, and file containing, a decoy payload
+    decoy: Option<(&'a str, &'a str)>,
+    /// Write a header and fill the rest of the image with random bits
+    pad: bool,
+    /// Files or directories to encode as a container, instead of stdin
+    files: Vec<&'a str>,
+    /// Only report what would be encoded where
+    dry_run: bool,
+    /// Where to save a map of the samples a dry run would use
+    heatmap: Option<&'a str>,
+    /// What to do when the encoded image looks detectable
+    check: SelfCheck,
+    /// The detectability score the check acts above
+    threshold: f64,
+    /// The format to write the output in, rather than the one its extension
+    /// names
+    format: Option<Format>,
+    /// Leave out the metadata of the source rather than carry it over
+    strip: bool,
+}
+
+/// What to do when an encoded image fails the detectability check
+#[derive(Copy, Clone, PartialEq)]
+enum SelfCheck
+{
+    Off,
+    Warn,
+    Refuse,
+}
+
+fn dispatch_encode(
+    mode: Option<&str>,
+    source: &str,
+    output: Option<&str>,
+    options: &EncodeOptions)
+{
+    // the format OUTPUT is written in, by name or else by its extension
+    let format = match options.format
+    {
+        Some(format) => Some(format),
+        None => match output.map(Format::from_path)
+        {
+            Some(Ok(format)) => Some(format),
+            Some(Err(e)) if !options.dry_run => error_out(&e),
+            _ => None,
+        },
+    };
+
+    // an indexed image keeps its palette, unless it is saved as something
+    // that has none
+    let indexed = match format
+    {
+        None | Some(Format::Png) | Some(Format::Gif) => open_indexed(source),
+        _ => None,
+    };
+
+    if let Some(mut image) = indexed
+    {
+        if options.strip
+        {
+            image.strip();
+        }
+
+        let (payload, flags) = read_payload(options);
+        let gif = format == Some(Format::Gif);
+
+        let saved = if is_order_mode(mode)
+        {
+            encode_image::<PermutationCodec>(OrderedImage::new(image), mode, &payload, flags, options)
+                .map(|image| image.save(output.unwrap(), gif))
+        }
+        else
+        {
+            encode_image::<PaletteCodec>(image, mode, &payload, flags, options)
+                .map(|image| image.save(output.unwrap(), gif))
+        };
+
+        check_saved(saved, output, mode, &payload, flags, options);
+
+        return;
+    }
+
+    // a JPEG stays one, unless it is asked to be saved as something else
+    let jpeg = if format.is_none_or(|f| f == Format::Jpeg) { open_jpeg(source, mode) } else { None };
+
+    if let Some(mut image) = jpeg
+    {
+        check_dct_password(mode, options.password.or(options.decoy.map(|d| d.0)));
+
+        if options.strip
+        {
+            image.strip();
+        }
+
+        let (payload, flags) = read_payload(options);
+        let saved = encode_image::<DctCodec>(image, mode, &payload, flags, options)
+            .map(|image| image.save(output.unwrap()));
+
+        check_saved(saved, output, mode, &payload, flags, options);
+
+        return;
+    }
+
+    if let Some(mut pages) = open_pages(source)
+    {
+        if format.is_some_and(|f| f != Format::Tiff) && !options.dry_run
+        {
+            error_out("The pages of a TIFF with several pages, 16-bit samples or grey without alpha can only be saved as a TIFF");
+        }
+
+        if options.strip
+        {
+            pages.strip();
+        }
+
+        let (payload, flags) = read_payload(options);
+        let saved = encode_image::<PagesCodec>(pages, mode, &payload, flags, options)
+            .map(|pages| pages.save(output.unwrap()));
+
+        check_saved(saved, output, mode, &payload, flags, options);
+
+        return;
+    }
+
+    let (dyimage, frames) = match open_frames(source)
+    {
+        Some(opened) => opened,
+        None => error_out("Error opening source image for encoding")
+    };
+
+    // a PNG or a TIFF saved as something else is written from its pixels
+    // alone
+    let mut frames = match frames
+    {
+        Some(Frames::Png(ref png)) if format.is_some_and(|f| f != Format::Png) && !png.is_deep() => None,
+        Some(Frames::Tiff(_)) if format.is_some_and(|f| f != Format::Tiff) => None,
+        frames => frames,
+    };
+
+    if let Some(format) = format.filter(|_| !options.dry_run)
+    {
+        check_format(format, &dyimage, frames.as_ref());
+    }
+
+    if let (true, Some(frames)) = (options.strip, frames.as_mut())
+    {
+        frames.strip();
+    }
+
+    let (payload, flags) = read_payload(options);
+
+    // nothing is saved on a dry run
+    let encoded = match dyimage
+    {
+        DynamicImage::ImageRgba8(image) => encode_image::<RgbaCodec>(image, mode, &payload, flags, options)
+            .map(DynamicImage::ImageRgba8),
+        DynamicImage::ImageRgb8(image) => encode_image::<RgbCodec>(image, mode, &payload, flags, options)
+            .map(DynamicImage::ImageRgb8),
+        DynamicImage::ImageLumaA8(image) => encode_image::<GrayAlphaCodec>(image, mode, &payload, flags, options)
+            .map(DynamicImage::ImageLumaA8),
+        _ => error_out("Unsupported filetype"),
+    };
+
+    let saved = encoded.map(|image| match frames
+    {
+        Some(frames) => frames.save(&image, output.unwrap()),
+        None => output::save(&image, output.unwrap(), format.unwrap()),
+    });
+
+    check_saved(saved, output, mode, &payload, flags, options);
+}
+
+/// Stop before encoding when the output format would lose the payload
+fn check_format(format: Format, image: &DynamicImage, frames: Option<&Frames>)
+{
+    let refusal = match (frames, format)
+    {
+        (Some(&Frames::Apng(_)), Format::Png)
+            | (Some(&Frames::Png(_)), Format::Png)
+            | (Some(&Frames::Tiff(_)), Format::Tiff) => return,
+        (Some(&Frames::Apng(_)), _) =>
+            "The frames of an animated PNG can only be saved as a PNG".to_string(),
+        (Some(&Frames::Png(_)), _) =>
+            "A 16-bit PNG can only be saved as a PNG, which keeps the high byte of each sample".to_string(),
+        (_, _) if format.keeps(image) => return,
+        (_, Format::Jpeg) | (_, Format::Gif) => format!(
+            "Saving as a {} is lossy and would destroy the payload; use a .png, .bmp or .tif name, or --format png|bmp|tiff|webp-lossless",
+            format.name()),
+        (_, _) => format!(
+            "A {} can not hold the pixels of this image without loss; save it as a PNG or TIFF",
+            format.name()),
+    };
+
+    error_out(&refusal);
+}
+
+/// Report an image that could not be saved, and make sure that one that was
+/// gives back its payload. There is nothing saved on a dry run.
+fn check_saved(
+    saved: Option<std::io::Result<()>>,
+    output: Option<&str>,
+    mode: Option<&str>,
+    payload: &[u8],
+    flags: u8,
+    options: &EncodeOptions)
+{
+    match saved
+    {
+        Some(Ok(())) => verify_output(output.unwrap(), mode, payload, flags, options.password),
+        Some(Err(e)) => error_out(&format!("Error saving encoded output file: {}", e)),
+        None => {},
+    }
+}
+
+/// Open a saved image the way decode would and make sure the payload comes
+/// back out of it, removing the file when it does not
+fn verify_output(output: &str, mode: Option<&str>, payload: &[u8], flags: u8, password: Option<&str>)
+{
+    let recovered = if let Some(image) = open_indexed(output)
+    {
+        if is_order_mode(mode)
+        {
+            recovers::<PermutationCodec>(&OrderedImage::new(image), mode, payload, flags, password)
+        }
+        else
+        {
+            recovers::<PaletteCodec>(&image, mode, payload, flags, password)
+        }
+    }
+    else if let Some(image) = open_jpeg(output, mode)
+    {
+        recovers::<DctCodec>(&image, mode, payload, flags, password)
+    }
+    else if let Some(pages) = open_pages(output)
+    {
+        recovers::<PagesCodec>(&pages, mode, payload, flags, password)
+    }
+    else
+    {
+        match open_frames(output).map(|opened| opened.0)
+        {
+            Some(DynamicImage::ImageRgba8(image)) =>
+                recovers::<RgbaCodec>(&image, mode, payload, flags, password),
+            Some(DynamicImage::ImageRgb8(image)) =>
+                recovers::<RgbCodec>(&image, mode, payload, flags, password),
+            Some(DynamicImage::ImageLumaA8(image)) =>
+                recovers::<GrayAlphaCodec>(&image, mode, payload, flags, password),
+            _ => false,
+        }
+    };
+
+    if !recovered
+    {
+        let _ = std::fs::remove_file(output);
+
+        error_out(&format!("The payload could not be decoded back out of {}, so it was removed", output));
+    }
+}
+
+/// Whether decoding an image gives back the payload that was encoded into it
+fn recovers<C: Codec>(
+    image: &C::Input,
+    mode: Option<&str>,
+    payload: &[u8],
+    flags: u8,
+    password: Option<&str>) -> bool
+{
+    let mode = parse_mode::<C>(mode);
+    let layout = C::layout(image, mode);
+
+    let decoded = if let Some(password) = password
+    {
+        deniable::decode(C::samples(image), &layout, password)
+    }
+    else if flags != 0
+    {
+        find_header::<C>(image, mode).ok().map(|(_, payload)| payload)
+    }
+    else if payload.len() * 8 <= layout.len()
+    {
+        let mut buf = vec![0; payload.len()];
+        C::decode(image, &mut buf, payload.len(), mode);
+
+        Some(buf)
+    }
+    else
+    {
+        None
+    };
+
+    decoded.as_deref() == Some(payload)
+}
+
+fn dispatch_decode(
+    mode: Option<&str>,
+    source: &str,
+    len: Option<&str>,
+    password: Option<&str>)
+{
+    let len = len.map(|len| match len.parse::<usize>()
+    {
+        Ok(l) => l,
+        Err(_) => error_out("len argument to decode is not a number"),
+    });
+
+    if let Some(image) = open_indexed(source)
+    {
+        if is_order_mode(mode)
+        {
+            decode::<PermutationCodec>(OrderedImage::new(image), mode, len, password);
+        }
+        else
+        {
+            decode::<PaletteCodec>(image, mode, len, password);
+        }
+
+        return;
+    }
+
+    if let Some(image) = open_jpeg(source, mode)
+    {
+        check_dct_password(mode, password);
+        decode::<DctCodec>(image, mode, len, password);
+
+        return;
+    }
+
+    if let Some(pages) = open_pages(source)
+    {
+        decode::<PagesCodec>(pages, mode, len, password);
+
+        return;
+    }
+
+    let dyimage = match open_frames(source)
+    {
+        Some((di, _)) => di,
+        None => error_out("Error opening source image for decoding"),
+    };
+
+    match dyimage
+    {
+        DynamicImage::ImageRgba8(image) =>
+        {
+            decode::<RgbaCodec>(image, mode, len, password);
+        },
+        DynamicImage::ImageRgb8(image) =>
+        {
+            decode::<RgbCodec>(image, mode, len, password);
+        },
+        DynamicImage::ImageLumaA8(image) =>
+        {
+            decode::<GrayAlphaCodec>(image, mode, len, password);
+        },
+        _ => error_out("Unsupported filetype"),
+    }
+}
+
+fn dispatch_estimate(mode: Option<&str>, source: &str, deniable: bool)
+{
+    if let Some(image) = open_indexed(source)
+    {
+        if is_order_mode(mode)
+        {
+            let image = OrderedImage::new(image);
+
+            println!("The order of {} distinct colours holds log2({}!) = {:.1} bits",
+                image.distinct(), image.distinct(), image.order_bits());
+
+            estimate::<PermutationCodec>(image, mode, deniable);
+        }
+        else
+        {
+            estimate::<PaletteCodec>(image, mode, deniable);
+        }
+
+        return;
+    }
+
+    if let Some(image) = open_jpeg(source, mode)
+    {
+        estimate::<DctCodec>(image, mode, deniable);
+
+        return;
+    }
+
+    if let Some(pages) = open_pages(source)
+    {
+        estimate::<PagesCodec>(pages, mode, deniable);
+
+        return;
+    }
+
+    let dyimage = match open_frames(source)
+    {
+        Some((di, _)) => di,
+        None => error_out("Error opening source image for estimating"),
+    };
+
+    match dyimage
+    {
+        DynamicImage::ImageRgba8(image) =>
+        {
+            estimate::<RgbaCodec>(image, mode, deniable);
+        },
+        DynamicImage::ImageRgb8(image) =>
+        {
+            estimate::<RgbCodec>(image, mode, deniable);
+        },
+        DynamicImage::ImageLumaA8(image) =>
+        {
+            estimate::<GrayAlphaCodec>(image, mode, deniable);
+        },
+        _ => error_out("Unsupported filetype"),
+    }
+}
+
+/// Encode an image as the options say, or report on a dry run of it
+fn encode_image<C: Codec>(
+    image: C::Input,
+    mode: Option<&str>,
+    payload: &[u8],
+    flags: u8,
+    options: &EncodeOptions) -> Option<C::Input>
+{
+    let mode = parse_mode::<C>(mode);
+
+    if options.dry_run
+    {
+        dry_run::<C>(&image, mode, payload.len(), flags, options);
+
+        return None;
+    }
+
+    let cover = C::samples(&image).to_vec();
+    let image = encode::<C>(image, mode, payload, flags, options);
+
+    if options.check != SelfCheck::Off
+    {
+        self_check::<C>(&cover, &image, mode, options);
+    }
+
+    Some(image)
+}
+
+/// Run the detectors over the channels a mode encoded into, and warn or
+/// stop when any of them looks detectable
+fn self_check<C: Codec>(
+    cover: &[u8],
+    image: &C::Input,
+    mode: C::Mode,
+    options: &EncodeOptions)
+{
+    // the detectors look at pixels, which other modes leave alone
+    if !C::PIXELS
+    {
+        return;
+    }
+
+    let width = C::dimensions(image).0 as usize;
+
+    let results = C::channels(mode).iter()
+        .map(|&c| (c, check::check(
+            &analysis::channel(cover, C::CHANNELS, c),
+            &analysis::channel(C::samples(image), C::CHANNELS, c),
+            width)))
+        .collect::<Vec<_>>();
+
+    let score = results.iter().map(|r| r.1.score()).fold(0.0, f64::max);
+
+    if score <= options.threshold
+    {
+        return;
+    }
+
+    let details = results.iter()
+        .map(|&(c, r)| format!("{}: chi2 {:.2}, rs {:.2}, pair balance {:.2}",
+            C::CHANNEL_NAMES[c], r.chi2, r.rs, r.balance))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let message = format!("The output looks detectable, scoring {:.2} over the threshold of {:.2} ({})",
+        score, options.threshold, details);
+
+    if options.check == SelfCheck::Refuse
+    {
+        error_out(&format!("{}, not saving it", message));
+    }
+
+    eprintln!("Warning: {}", message);
+}
+
+/// Read the payload from stdin, or pack the files to encode into a
+/// container, returning it with the header flags it needs
+fn read_payload(options: &EncodeOptions) -> (Vec<u8>, u8)
+{
+    let mut payload = Vec::new();
+
+    if options.files.is_empty()
+    {
+        if stdin().read_to_end(&mut payload).is_err()
+        {
+            error_out("Error reading payload");
+        }
+
+        return (payload, payload_flags(false, options.pad));
+    }
+
+    (pack_files(&options.files), payload_flags(true, options.pad))
+}
+
+/// Pack files, or every file under directories, into a container
+fn pack_files(files: &[&str]) -> Vec<u8>
+{
+    let mut entries = Vec::new();
+
+    for file in files
+    {
+        match container::collect(Path::new(file))
+        {
+            Ok(e) => entries.extend(e),
+            Err(e) => error_out(&format!("Error reading files to encode: {}", e)),
+        }
+    }
+
+    container::pack(&entries)
+}
+
+/// The header flags encode gives a payload
+fn payload_flags(container: bool, pad: bool) -> u8
+{
+    (if container { header::CONTAINER } else { 0 }) | (if pad { header::PADDED } else { 0 })
+}
+
+/// The number of bytes encode writes for a payload, with its header if the
+/// flags call for one
+fn encoded_len(len: usize, flags: u8) -> usize
+{
+    if flags != 0 { Header::new(flags, len).size() + len } else { len }
+}
+
+fn encode<C: Codec>(
+    mut image: C::Input,
+    mode: C::Mode,
+    payload: &[u8],
+    flags: u8,
+    options: &EncodeOptions) -> C::Input
+{
+    if let Some(password) = options.password
+    {
+        encode_deniable::<C>(&mut image, mode, payload, password, options.decoy);
+
+        return image;
+    }
+
+    if flags != 0
+    {
+        encode_header::<C>(&mut image, mode, payload, Header::new(flags, payload.len()));
+
+        return image;
+    }
+
+    let rng = match StdRng::new()
+    {
+        Ok(r) => r,
+        Err(_) => error_out("Error creating source of randomness"),
+    };
+
+    C::encode(&mut image, payload, mode, rng);
+
+    image
+}
+
+/// Work out which samples an encoding would touch, without encoding
+fn dry_run<C: Codec>(
+    image: &C::Input,
+    mode: C::Mode,
+    len: usize,
+    flags: u8,
+    options: &EncodeOptions)
+{
+    let layout = C::layout(image, mode);
+    let mut usage = vec![heatmap::Use::Unused; C::samples(image).len()];
+
+    if let Some(password) = options.password
+    {
+        let mut payloads = vec![(password, len)];
+
+        if let Some((password, file)) = options.decoy
+        {
+            payloads.push((password, read_file(file).len()));
+        }
+
+        if payloads.iter().any(|&(_, len)| !deniable::fits(layout.len(), len))
+        {
+            error_out("Payload is too large for the source image");
+        }
+
+        // every slot is written, whether or not it holds a payload
+        for &p in &layout
+        {
+            usage[p] = heatmap::Use::Filler;
+        }
+
+        let mut rng = os_rng();
+        for (password, len) in payloads
+        {
+            for p in deniable::payload_positions(&layout, password, len, &mut rng)
+            {
+                usage[p] = heatmap::Use::Payload;
+            }
+        }
+
+        println!("The payload goes into a slot picked when encoding, every slot it could use is shown, \
+            at the places one of the random salts picked when encoding would put it");
+    }
+    else
+    {
+        let bytes = encoded_len(len, flags);
+
+        if bytes * 8 > layout.len()
+        {
+            error_out("Payload is too large for the source image");
+        }
+
+        for (i, &p) in layout.iter().enumerate()
+        {
+            usage[p] = if i < bytes * 8
+            {
+                heatmap::Use::Payload
+            }
+            else if flags & header::PADDED != 0
+            {
+                heatmap::Use::Filler
+            }
+            else
+            {
+                heatmap::Use::Unused
+            };
+        }
+    }
+
+    let count = |u| usage.iter().filter(|&&v| v == u).count();
+    let (payload, filler) = (count(heatmap::Use::Payload), count(heatmap::Use::Filler));
+
+    println!("{} of {} samples would carry payload, {} filler, {:.1}% of the mode's capacity",
+        payload, usage.len(), filler, 100.0 * payload as f64 / layout.len() as f64);
+
+    if let Some(path) = options.heatmap
+    {
+        if !C::PIXELS
+        {
+            error_out("This mode leaves the pixels alone, there is no heat map to draw");
+        }
+
+        let visible = (0..C::CHANNELS)
+            .filter(|&c| C::CHANNEL_NAMES[c] != "a")
+            .collect::<Vec<_>>();
+
+        let image = heatmap::render(C::samples(image), C::CHANNELS, &visible, &usage,
+            C::dimensions(image));
+
+        if image.save(path).is_err()
+        {
+            error_out("Error saving heat map");
+        }
+    }
+}
+
+fn encode_deniable<C: Codec>(
+    image: &mut C::Input,
+    mode: C::Mode,
+    payload: &[u8],
+    password: &str,
+    decoy: Option<(&str, &str)>)
+{
+    let decoy = decoy.map(|(password, file)| (password, read_file(file)));
+
+    let mut payloads = vec![deniable::Payload
+    {
+        password,
+        data: payload,
+    }];
+
+    if let Some((password, ref data)) = decoy
+    {
+        if password == payloads[0].password
+        {
+            error_out("The decoy password must differ from the password");
+        }
+
+        payloads.push(deniable::Payload
+        {
+            password,
+            data,
+        });
+    }
+
+    let layout = C::layout(image, mode);
+
+    if payloads.iter().any(|p| !deniable::fits(layout.len(), p.data.len()))
+    {
+        error_out("Payload is too large for the source image");
+    }
+
+    deniable::encode::<C, _>(image, &layout, &payloads, &mut os_rng());
+}
+
+fn encode_header<C: Codec>(
+    image: &mut C::Input,
+    mode: C::Mode,
+    payload: &[u8],
+    header: Header)
+{
+    let layout = C::layout(image, mode);
+    let mut rng = os_rng();
+
+    let mut bytes = header.write(payload, &mut rng);
+
+    if bytes.len() * 8 > layout.len()
+    {
+        error_out("Payload is too large for the source image");
+    }
+
+    while header.has(header::PADDED) && bytes.len() * 8 < layout.len()
+    {
+        bytes.push(rng.gen());
+    }
+
+    C::embed(image, &layout, &bytes, &mut rng);
+}
+
+fn decode<C: Codec>(
+    image: C::Input,
+    mode: Option<&str>,
+    len: Option<usize>,
+    password: Option<&str>)
+{
+    let mode = parse_mode::<C>(mode);
+
+    let buf = if let Some(password) = password
+    {
+        let layout = C::layout(&image, mode);
+
+        match deniable::decode(C::samples(&image), &layout, password)
+        {
+            Some(buf) => buf,
+            None => error_out("No payload could be decoded with that password"),
+        }
+    }
+    else if let Some(len) = len
+    {
+        let mut buf = vec![0; len];
+
+        C::decode(&image, &mut buf, len, mode);
+
+        buf
+    }
+    else
+    {
+        let (header, payload) = decode_header::<C>(&image, mode);
+
+        if header.has(header::FOUNTAIN)
+        {
+            fountain_decode(::std::iter::once(("the image".to_string(), Ok((header, payload)))))
+        }
+        else
+        {
+            payload
+        }
+    };
+
+    match stdout().write_all(&buf)
+    {
+        Ok(_) => {},
+        Err(_) => error_out("Error writing decoded payload"),
+    };
+}
+
+fn decode_header<C: Codec>(image: &C::Input, mode: C::Mode) -> (Header, Vec<u8>)
+{
+    match find_header::<C>(image, mode)
+    {
+        Ok(found) => found,
+        Err(e) => error_out(e),
+    }
+}
+
+fn find_header<C: Codec>(image: &C::Input, mode: C::Mode)
+    -> Found
+{
+    let layout = C::layout(image, mode);
+    let samples = C::samples(image);
+
+    let header = match Header::from_bytes(&extract(samples, &layout, header::MAX_SIZE))
+    {
+        Some(h) => h,
+        None => return Err("No header found, the length to decode must be given"),
+    };
+
+    let end = header.size() + header.length as usize;
+
+    if end * 8 > layout.len()
+    {
+        return Err("The header's length is larger than the image");
+    }
+
+    Ok((header, header.payload(&extract(samples, &layout, end))))
+}
+
+fn estimate<C: Codec>(image: C::Input, mode: Option<&str>, deniable: bool)
+{
+    let mode = parse_mode::<C>(mode);
+
+    if deniable
+    {
+        let positions = C::layout(&image, mode).len();
+
+        println!("Estimate {} bytes in each of {} payloads",
+            deniable::capacity(positions), deniable::SLOTS);
+
+        return;
+    }
+
+    match C::estimate(&image, mode)
+    {
+        Some(i) => println!("Estimate {} bytes", i),
+        None => println!("Could not make an estimate"),
+    }
+}
+
+fn dispatch_container(
+    mode: Option<&str>,
+    source: &str,
+    password: Option<&str>) -> Vec<Entry>
+{
+    if let Some(image) = open_indexed(source)
+    {
+        if is_order_mode(mode)
+        {
+            return read_container::<PermutationCodec>(OrderedImage::new(image), mode, password);
+        }
+
+        return read_container::<PaletteCodec>(image, mode, password);
+    }
+
+    if let Some(image) = open_jpeg(source, mode)
+    {
+        check_dct_password(mode, password);
+
+        return read_container::<DctCodec>(image, mode, password);
+    }
+
+    if let Some(pages) = open_pages(source)
+    {
+        return read_container::<PagesCodec>(pages, mode, password);
+    }
+
+    let dyimage = match open_frames(source)
+    {
+        Some((di, _)) => di,
+        None => error_out("Error opening source image for decoding"),
+    };
+
+    match dyimage
+    {
+        DynamicImage::ImageRgba8(image) =>
+            read_container::<RgbaCodec>(image, mode, password),
+        DynamicImage::ImageRgb8(image) =>
+            read_container::<RgbCodec>(image, mode, password),
+        DynamicImage::ImageLumaA8(image) =>
+            read_container::<GrayAlphaCodec>(image, mode, password),
+        _ => error_out("Unsupported filetype"),
+    }
+}
+
+fn read_container<C: Codec>(
+    image: C::Input,
+    mode: Option<&str>,
+    password: Option<&str>) -> Vec<Entry>
+{
+    let mode = parse_mode::<C>(mode);
+
+    let payload = if let Some(password) = password
+    {
+        let layout = C::layout(&image, mode);
+
+        match deniable::decode(C::samples(&image), &layout, password)
+        {
+            Some(payload) => payload,
+            None => error_out("No payload could be decoded with that password"),
+        }
+    }
+    else
+    {
+        let (header, payload) = decode_header::<C>(&image, mode);
+
+        if !header.has(header::CONTAINER)
+        {
+            error_out("The image does not contain any files");
+        }
+
+        payload
+    };
+
+    match container::unpack(&payload)
+    {
+        Some(entries) => entries,
+        None => error_out("The image does not contain any files"),
+    }
+}
+
+fn list(entries: &[Entry])
+{
+    for entry in entries
+    {
+        println!("{:04o} {:>10} {} {}",
+            entry.permissions,
+            entry.data.len(),
+            format_time(entry.modified),
+            entry.name);
+    }
+}
+
+fn extract_files(entries: &[Entry], name: Option<&str>, dir: &str)
+{
+    let mut found = false;
+
+    for entry in entries.iter().filter(|e| name.is_none_or(|n| n == e.name))
+    {
+        found = true;
+
+        if entry.write(Path::new(dir)).is_err()
+        {
+            error_out(&format!("Error writing {}", entry.name));
+        }
+    }
+
+    if !found
+    {
+        error_out("No file with that name was encoded");
+    }
+}
+
+/// Format seconds since the unix epoch as a UTC date and time
+fn format_time(secs: u64) -> String
+{
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, time / 3600, time / 60 % 60)
+}
+
+fn dispatch_split(
+    mode: Option<&str>,
+    payload: &str,
+    sources: &[&str],
+    output: &str,
+    pad: bool)
+{
+    let payload = read_file(payload);
+    let images = open_all(sources);
+    check_outputs(&images, sources, output);
+
+    let flags = if pad { header::PADDED } else { 0 };
+
+    let capacities = images.iter()
+        .map(|image| header_capacity(image, mode, header::size_of(flags | header::PIECE)))
+        .collect::<Vec<_>>();
+
+    let ranges = match split::plan(payload.len(), &capacities)
+    {
+        Some(r) => r,
+        None => error_out(&format!("Payload is too large for the source images, they fit {} bytes",
+            capacities.iter().sum::<usize>())),
+    };
+
+    let count = ranges.iter().filter(|r| !r.is_empty()).count();
+    let id = os_rng().gen();
+
+    for (index, ((image, source), range)) in images.into_iter()
+        .zip(sources)
+        .zip(ranges)
+        .filter(|(_, r)| !r.is_empty())
+        .enumerate()
+    {
+        let header = Header::with_piece(flags, range.len(), Piece
+        {
+            id,
+            index: index as u16,
+            count: count as u16,
+        });
+
+        encode_and_save(image, mode, &payload[range], header, &output_path(output, source));
+    }
+
+    if count < sources.len()
+    {
+        eprintln!("Only the first {} of {} images were needed", count, sources.len());
+    }
+}
+
+fn dispatch_join(mode: Option<&str>, sources: &[&str], output: Option<&str>)
+{
+    let pieces = sources.iter().map(|source|
+    {
+        let (header, payload) = read_header(source, mode);
+
+        match header.piece
+        {
+            Some(piece) => (piece, payload),
+            None => error_out(&format!("{} is not part of a split file", source)),
+        }
+    }).collect();
+
+    let payload = match split::join(pieces)
+    {
+        Ok(p) => p,
+        Err(split::JoinError::Empty) => error_out("No pieces to join"),
+        Err(split::JoinError::Mixed(ids)) =>
+            error_out(&format!("The images hold pieces of {} different files", ids.len())),
+        Err(split::JoinError::Missing(missing, count)) =>
+            error_out(&format!("Missing pieces {} of {}",
+                missing.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>().join(", "),
+                count)),
+    };
+
+    write_output(output, &payload);
+}
+
+fn dispatch_share(
+    mode: Option<&str>,
+    payload: &str,
+    sources: &[&str],
+    output: &str,
+    threshold: &str,
+    password: Option<&str>,
+    pad: bool)
+{
+    let threshold = match threshold.parse::<u8>()
+    {
+        Ok(t) if t >= 1 && t as usize <= sources.len() => t,
+        _ => error_out("threshold must be a number between 1 and the number of images"),
+    };
+
+    if sources.len() > 255
+    {
+        error_out("A secret can be shared across at most 255 images");
+    }
+
+    let mut rng = os_rng();
+    let mut flags = if pad { header::PADDED } else { 0 };
+
+    let mut payload = read_file(payload);
+    if let Some(password) = password
+    {
+        payload = crypto::encrypt_password(password, &payload, &mut rng);
+        flags |= header::ENCRYPTED;
+    }
+
+    let images = open_all(sources);
+    check_outputs(&images, sources, output);
+
+    for (image, source) in images.iter().zip(sources)
+    {
+        if header_capacity(image, mode, header::size_of(flags | header::SHARE)) < payload.len()
+        {
+            error_out(&format!("Payload is too large for {}", source));
+        }
+    }
+
+    let id = rng.gen();
+    let shares = shamir::split(&payload, threshold, sources.len() as u8, &mut rng);
+
+    for ((image, source), (x, share)) in images.into_iter().zip(sources).zip(shares)
+    {
+        let header = Header::with_share(flags, share.len(), Share
+        {
+            id,
+            x,
+            threshold,
+        });
+
+        encode_and_save(image, mode, &share, header, &output_path(output, source));
+    }
+}
+
+fn dispatch_combine(
+    mode: Option<&str>,
+    sources: &[&str],
+    output: Option<&str>,
+    password: Option<&str>)
+{
+    let mut shares = Vec::new();
+    let mut info = None;
+    let mut encrypted = false;
+
+    for source in sources
+    {
+        let (header, payload) = read_header(source, mode);
+
+        let share = match header.share
+        {
+            Some(share) => share,
+            None => error_out(&format!("{} does not hold a share", source)),
+        };
+
+        match info
+        {
+            Some(Share { id, .. }) if id != share.id =>
+                error_out("The images hold shares of different secrets"),
+            _ => info = Some(share),
+        }
+
+        if shares.iter().all(|s: &(u8, Vec<u8>)| s.0 != share.x)
+        {
+            shares.push((share.x, payload));
+        }
+
+        encrypted = header.has(header::ENCRYPTED);
+    }
+
+    let threshold = match info
+    {
+        Some(share) => share.threshold as usize,
+        None => error_out("No shares to combine"),
+    };
+
+    if shares.len() < threshold
+    {
+        error_out(&format!("{} shares are needed, only {} were given", threshold, shares.len()));
+    }
+
+    shares.truncate(threshold);
+
+    let mut payload = match shamir::combine(&shares)
+    {
+        Some(p) => p,
+        None => error_out("The shares are corrupt"),
+    };
+
+    if encrypted
+    {
+        let password = match password
+        {
+            Some(p) => p,
+            None => error_out("The secret is encrypted, a password must be given"),
+        };
+
+        payload = match crypto::decrypt_password(password, &payload)
+        {
+            Some(p) => p,
+            None => error_out("The secret could not be decrypted with that password"),
+        };
+    }
+
+    write_output(output, &payload);
+}
+
+fn dispatch_fountain(
+    mode: Option<&str>,
+    payload: &str,
+    sources: &[&str],
+    output: &str,
+    symbol_size: &str,
+    pad: bool)
+{
+    let size = match symbol_size.parse::<u16>()
+    {
+        Ok(s) if s > 0 => s as usize,
+        _ => error_out("symbol size must be a number between 1 and 65535"),
+    };
+
+    let payload = read_file(payload);
+    let images = open_all(sources);
+    check_outputs(&images, sources, output);
+    let flags = if pad { header::PADDED } else { 0 };
+
+    let counts = images.iter()
+        .map(|image| header_capacity(image, mode, header::size_of(flags | header::FOUNTAIN))
+            / (fountain::SEED_SIZE + size))
+        .collect::<Vec<_>>();
+
+    let total = counts.iter().sum::<usize>();
+    let blocks = fountain::blocks(payload.len(), size);
+
+    if blocks > fountain::MAX_BLOCKS
+    {
+        error_out(&format!("The payload would be cut into {} blocks, more than the {} a decoder takes; use a larger --symbol-size",
+            blocks, fountain::MAX_BLOCKS));
+    }
+
+    // with no symbols to spare, any one that repeats what others say leaves
+    // the message short
+    if total <= blocks
+    {
+        error_out(&format!("The images only fit {} symbols, more than {} are needed",
+            total, blocks));
+    }
+
+    let mut rng = os_rng();
+    let info = Fountain
+    {
+        id: rng.gen(),
+        length: payload.len() as u32,
+        symbol_size: size as u16,
+    };
+
+    let encoder = fountain::Encoder::new(&payload, size);
+    let mut seed = rng.gen::<u32>();
+
+    for ((image, source), count) in images.into_iter()
+        .zip(sources)
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+    {
+        let mut symbols = Vec::with_capacity(count * (fountain::SEED_SIZE + size));
+
+        for _ in 0..count
+        {
+            symbols.extend(encoder.symbol(seed));
+            seed = seed.wrapping_add(1);
+        }
+
+        let header = Header::with_fountain(flags, symbols.len(), info);
+
+        encode_and_save(image, mode, &symbols, header, &output_path(output, source));
+    }
+
+    eprintln!("Encoded {} symbols for {} blocks, {:.0}% redundancy",
+        total, blocks, (total - blocks) as f64 / blocks as f64 * 100.0);
+}
+
+fn dispatch_fountain_decode(mode: Option<&str>, sources: &[String])
+{
+    let payload = fountain_decode(sources.iter()
+        .map(|source| (source.clone(), open_header(source, mode))));
+
+    write_output(None, &payload);
+}
+
+/// Decode a fountain coded message from the headers and payloads of
+/// several images, reporting progress as it goes. Images that could not be
+/// read are skipped, and no more are read once the message is recovered.
+fn fountain_decode<I>(images: I) -> Vec<u8>
+    where I: IntoIterator<Item = (String, Found)>
+{
+    let mut decoder: Option<(Fountain, fountain::Decoder)> = None;
+
+    for (source, found) in images
+    {
+        let (header, payload) = match found
+        {
+            Ok(found) => found,
+            Err(e) =>
+            {
+                eprintln!("{}: {}, skipping", source, e);
+                continue;
+            },
+        };
+
+        let info = match header.fountain
+        {
+            Some(info) => info,
+            None =>
+            {
+                eprintln!("{}: not fountain coded, skipping", source);
+                continue;
+            },
+        };
+
+        let blocks = match fountain::checked_blocks(info.length as usize, info.symbol_size as usize)
+        {
+            Some(blocks) => blocks,
+            None =>
+            {
+                eprintln!("{}: the fountain header's sizes can not be right, skipping", source);
+                continue;
+            },
+        };
+
+        let (expected, decoder) = decoder.get_or_insert_with(||
+            (info, fountain::Decoder::new(blocks, info.symbol_size as usize)));
+
+        if expected.id != info.id
+        {
+            eprintln!("{}: holds a different message, skipping", source);
+            continue;
+        }
+
+        let symbols = payload.chunks(fountain::SEED_SIZE + info.symbol_size as usize)
+            .filter(|symbol| decoder.add(symbol))
+            .count();
+
+        eprintln!("{}: {} useful symbols, {} of {} blocks recovered",
+            source, symbols, decoder.rank(), decoder.blocks());
+
+        if decoder.is_done()
+        {
+            break;
+        }
+    }
+
+    let (info, decoder) = match decoder
+    {
+        Some(d) => d,
+        None => error_out("No fountain coded images found"),
+    };
+
+    let (rank, blocks) = (decoder.rank(), decoder.blocks());
+
+    match decoder.finish(info.length as usize)
+    {
+        Some(payload) => payload,
+        None => error_out(&format!("Only {} of {} blocks were recovered, more images are needed",
+            rank, blocks)),
+    }
+}
+
+/// Replace any directories with the files in them
+fn expand_sources(sources: &[&str]) -> Vec<String>
+{
+    let mut expanded = Vec::new();
+
+    for source in sources
+    {
+        if Path::new(source).is_dir()
+        {
+            let mut files = match ::std::fs::read_dir(source)
+            {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file())
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>(),
+                Err(_) => error_out(&format!("Error reading directory {}", source)),
+            };
+
+            files.sort();
+            expanded.extend(files);
+        }
+        else
+        {
+            expanded.push(source.to_string());
+        }
+    }
+
+    expanded
+}
+
+fn dispatch_planes(
+    source: &str,
+    output: &str,
+    channel: Option<&str>,
+    bit: &str,
+    all: bool)
+{
+    let bit = match bit.parse::<u8>()
+    {
+        Ok(b) if b < 8 => b,
+        _ => error_out("bit must be a number from 0 to 7"),
+    };
+
+    let dyimage = match open(source)
+    {
+        Ok(di) => di,
+        Err(_) => error_out("Error opening source image"),
+    };
+
+    let image = match dyimage
+    {
+        DynamicImage::ImageRgba8(image) => export_planes::<RgbaCodec>(&image, channel, bit, all),
+        DynamicImage::ImageRgb8(image) => export_planes::<RgbCodec>(&image, channel, bit, all),
+        DynamicImage::ImageLumaA8(image) => export_planes::<GrayAlphaCodec>(&image, channel, bit, all),
+        _ => error_out("Unsupported filetype"),
+    };
+
+    if image.save(output).is_err()
+    {
+        error_out("Error saving output image");
+    }
+}
+
+fn export_planes<C: Codec>(
+    image: &C::Input,
+    channel: Option<&str>,
+    bit: u8,
+    all: bool) -> image::GrayImage
+{
+    let channels = channels::<C>(channel);
+
+    if all
+    {
+        println!("rows are channels {}, columns are bits 7 to 0",
+            channels.iter().map(|&c| C::CHANNEL_NAMES[c]).collect::<Vec<_>>().join(", "));
+
+        planes::contact_sheet(C::samples(image), C::CHANNELS, &channels, C::dimensions(image))
+    }
+    else
+    {
+        planes::plane(C::samples(image), C::CHANNELS, channels[0], bit, C::dimensions(image))
+    }
+}
+
+fn dispatch_attack(
+    source: &str,
+    ops: &str,
+    mode: Option<&str>,
+    len: Option<&str>,
+    password: Option<&str>)
+{
+    let ops = ops.split(',')
+        .map(|op| match op.trim().parse::<attack::Op>()
+        {
+            Ok(op) => op,
+            Err(_) => error_out(&format!("Unknown transform {}", op)),
+        })
+        .collect::<Vec<_>>();
+
+    let len = len.map(|len| match len.parse::<usize>()
+    {
+        Ok(l) => l,
+        Err(_) => error_out("length must be a number"),
+    });
+
+    let stego = match open(source)
+    {
+        Ok(di) => di,
+        Err(_) => error_out("Error opening source image"),
+    };
+
+    let (written, payload) = probe(&stego, mode, len, password, None);
+
+    let payload = match payload
+    {
+        Some(p) => p,
+        None => error_out("Nothing could be decoded from the image as it is"),
+    };
+
+    let mut rng = os_rng();
+
+    println!("{:<20} {:>11} {:>10}  recovered", "transform", "size", "bit errors");
+
+    for op in ops
+    {
+        let attacked = attack::apply(op, &stego, &mut rng);
+        let (left, decoded) = probe(&attacked, mode, len, password, Some(written.len()));
+        let (width, height) = attacked.dimensions();
+
+        println!("{:<20} {:>11} {:>9.2}%  {}",
+            op.to_string(),
+            format!("{}x{}", width, height),
+            100.0 * attack::bit_error_rate(&written, &left),
+            if decoded.as_ref() == Some(&payload) { "yes" } else { "no" });
+    }
+}
+
+/// The bytes an encoding takes up in an image, and the payload decoded
+/// from them if there is one. Without `region` the size taken up is read
+/// from the image itself.
+fn probe(
+    image: &DynamicImage,
+    mode: Option<&str>,
+    len: Option<usize>,
+    password: Option<&str>,
+    region: Option<usize>) -> (Vec<u8>, Option<Vec<u8>>)
+{
+    match *image
+    {
+        DynamicImage::ImageRgba8(ref image) => probe_image::<RgbaCodec>(image, mode, len, password, region),
+        DynamicImage::ImageRgb8(ref image) => probe_image::<RgbCodec>(image, mode, len, password, region),
+        DynamicImage::ImageLumaA8(ref image) => probe_image::<GrayAlphaCodec>(image, mode, len, password, region),
+        _ => error_out("Unsupported filetype"),
+    }
+}
+
+fn probe_image<C: Codec>(
+    image: &C::Input,
+    mode: Option<&str>,
+    len: Option<usize>,
+    password: Option<&str>,
+    region: Option<usize>) -> (Vec<u8>, Option<Vec<u8>>)
+{
+    let mode = parse_mode::<C>(mode);
+    let layout = C::layout(image, mode);
+    let samples = C::samples(image);
+
+    let found = find_header::<C>(image, mode);
+
+    let payload = if let Some(password) = password
+    {
+        deniable::decode(samples, &layout, password)
+    }
+    else if let Some(len) = len
+    {
+        let mut buf = vec![0; len];
+
+        if len * 8 <= layout.len()
+        {
+            C::decode(image, &mut buf, len, mode);
+            Some(buf)
+        }
+        else
+        {
+            None
+        }
+    }
+    else
+    {
+        found.as_ref().ok().map(|found| found.1.clone())
+    };
+
+    let region = region.unwrap_or_else(|| match (password, len, &found)
+    {
+        // every slot is written
+        (Some(_), _, _) => layout.len() / 8,
+        (None, Some(len), _) => len,
+        (None, None, &Ok((header, ref payload))) => header.size() + payload.len(),
+        (None, None, &Err(e)) => error_out(e),
+    });
+
+    (extract(samples, &layout, region.min(layout.len() / 8)), payload)
+}
+
+fn dispatch_bench(
+    sources: &[&str],
+    modes: Option<&str>,
+    rates: &str,
+    format: &str,
+    output: Option<&str>)
+{
+    let modes = modes.map(|m| m.split(',').map(str::trim).collect::<Vec<_>>());
+
+    let rates = rates.split(',')
+        .map(|r| match r.trim().parse::<f64>()
+        {
+            Ok(r) if r > 0.0 && r <= 1.0 => r,
+            _ => error_out("rates must be numbers above 0, up to 1"),
+        })
+        .collect::<Vec<_>>();
+
+    let mut rng = os_rng();
+    let mut trials = Vec::new();
+
+    for source in expand_sources(sources)
+    {
+        let dyimage = match open(&source)
+        {
+            Ok(di) => di,
+            Err(_) =>
+            {
+                eprintln!("Skipping {}, it could not be opened as an image", source);
+                continue;
+            },
+        };
+
+        let modes = modes.as_deref();
+
+        match dyimage
+        {
+            DynamicImage::ImageRgba8(image) =>
+                trials.extend(bench_image::<RgbaCodec, _>(&image, modes, &rates, &mut rng)),
+            DynamicImage::ImageRgb8(image) =>
+                trials.extend(bench_image::<RgbCodec, _>(&image, modes, &rates, &mut rng)),
+            DynamicImage::ImageLumaA8(image) =>
+                trials.extend(bench_image::<GrayAlphaCodec, _>(&image, modes, &rates, &mut rng)),
+            _ => eprintln!("Skipping {}, its pixel type is unsupported", source),
+        }
+    }
+
+    if trials.is_empty()
+    {
+        error_out("No images were tested");
+    }
+
+    let summaries = bench::summarize(&trials);
+
+    let report = match format
+    {
+        "json" => bench::to_json(&summaries),
+        _ => bench::to_csv(&summaries),
+    };
+
+    write_output(output, report.as_bytes());
+}
+
+/// Score the cover and stego images made from it at each rate, in every
+/// mode asked for. Modes are named alone, e.g. `alpha`, or after the
+/// codec's channels, e.g. `rgba:alpha`.
+fn bench_image<C: Codec, R: Rng>(
+    image: &C::Input,
+    modes: Option<&[&str]>,
+    rates: &[f64],
+    rng: &mut R) -> Vec<bench::Trial>
+    where C::Input: Clone
+{
+    let codec = C::CHANNEL_NAMES.concat();
+    let width = C::dimensions(image).0 as usize;
+
+    let scores = |image: &C::Input, mode: C::Mode|
+    {
+        let mut scores = [0.0; 3];
+
+        for &c in C::channels(mode)
+        {
+            let plane = analysis::channel(C::samples(image), C::CHANNELS, c);
+
+            for (score, detector) in scores.iter_mut().zip(bench::DETECTORS.iter())
+            {
+                *score = detector.score(&plane, width).max(*score);
+            }
+        }
+
+        scores
+    };
+
+    let mut trials = Vec::new();
+
+    for &name in C::MODE_NAMES
+    {
+        let qualified = format!("{}:{}", codec, name);
+
+        if modes.is_some_and(|m| !m.contains(&name) && !m.contains(&qualified.as_str()))
+        {
+            continue;
+        }
+
+        let mode = parse_mode::<C>(Some(name));
+        let capacity = C::estimate(image, mode)
+            .unwrap_or_else(|| C::layout(image, mode).len() / 8);
+
+        trials.push(bench::Trial
+        {
+            mode: qualified.clone(),
+            rate: 0.0,
+            scores: scores(image, mode),
+            psnr: f64::INFINITY,
+        });
+
+        for &rate in rates
+        {
+            let payload = (0..(capacity as f64 * rate) as usize)
+                .map(|_| rng.gen())
+                .collect::<Vec<u8>>();
+
+            let mut stego = image.clone();
+            C::encode(&mut stego, &payload, mode, &mut *rng);
+
+            trials.push(bench::Trial
+            {
+                mode: qualified.clone(),
+                rate,
+                scores: scores(&stego, mode),
+                psnr: diff::psnr(C::samples(image), C::samples(&stego)),
+            });
+        }
+    }
+
+    trials
+}
+
+fn dispatch_diff(cover: &str, stego: &str, output: Option<&str>, gain: &str)
+{
+    let gain = match gain.parse::<u32>()
+    {
+        Ok(g) => g,
+        Err(_) => error_out("gain must be a number"),
+    };
+
+    let images = open_all(&[cover, stego]);
+
+    if images[0].dimensions() != images[1].dimensions()
+    {
+        error_out("Images differ in size");
+    }
+
+    let image = match (&images[0], &images[1])
+    {
+        (DynamicImage::ImageRgba8(a), DynamicImage::ImageRgba8(b)) => compare::<RgbaCodec>(a, b, gain),
+        (DynamicImage::ImageRgb8(a), DynamicImage::ImageRgb8(b)) => compare::<RgbCodec>(a, b, gain),
+        (DynamicImage::ImageLumaA8(a), DynamicImage::ImageLumaA8(b)) => compare::<GrayAlphaCodec>(a, b, gain),
+        _ => error_out("Images differ in pixel type, or the type is unsupported"),
+    };
+
+    if let Some(output) = output
+    {
+        if image.save(output).is_err()
+        {
+            error_out("Error saving output image");
+        }
+    }
+}
+
+fn compare<C: Codec>(cover: &C::Input, stego: &C::Input, gain: u32) -> image::GrayImage
+{
+    let (cover_samples, stego_samples) = (C::samples(cover), C::samples(stego));
+    let dimensions = C::dimensions(cover);
+
+    println!("{:<8} {:>9} {:>9} {:>9} {:>8} {:>7} {:>19} {:>9}",
+        "channel", "changed", "+-1", "larger", "psnr", "ssim", "lsb ones", "hist");
+
+    for (c, name) in C::CHANNEL_NAMES.iter().enumerate()
+    {
+        let d = diff::compare(cover_samples, stego_samples, C::CHANNELS, c, dimensions);
+
+        println!("{:<8} {:>9} {:>9} {:>9} {:>8.2} {:>7.4} {:>19} {:>9}",
+            name,
+            d.changed,
+            d.unit,
+            d.larger,
+            d.psnr,
+            d.ssim,
+            format!("{} -> {}", d.lsb_ones.0, d.lsb_ones.1),
+            d.histogram_delta);
+    }
+
+    diff::amplified(cover_samples, stego_samples, C::CHANNELS, gain, dimensions)
+}
+
+/// One way of encoding a payload into a candidate carrier
+struct Candidate
+{
+    source: String,
+    mode: &'static str,
+    capacity: usize,
+    measure: rank::Measure,
+    problems: Vec<rank::Problem>,
+    score: Option<f64>,
+}
+
+fn dispatch_rank(sources: &[&str], payload: Option<&str>, files: &[&str], pad: bool)
+{
+    let len = match payload
+    {
+        Some(payload) => encoded_len(read_file(payload).len(), payload_flags(false, pad)),
+        None => encoded_len(pack_files(files).len(), payload_flags(true, pad)),
+    };
+
+    let mut candidates = Vec::new();
+
+    for source in expand_sources(sources)
+    {
+        let dyimage = match open(&source)
+        {
+            Ok(di) => di,
+            Err(_) =>
+            {
+                eprintln!("Skipping {}, it could not be opened as an image", source);
+                continue;
+            },
+        };
+
+        match dyimage
+        {
+            DynamicImage::ImageRgba8(image) =>
+                candidates.extend(rank_image::<RgbaCodec>(&image, &source, len)),
+            DynamicImage::ImageRgb8(image) =>
+                candidates.extend(rank_image::<RgbCodec>(&image, &source, len)),
+            DynamicImage::ImageLumaA8(image) =>
+                candidates.extend(rank_image::<GrayAlphaCodec>(&image, &source, len)),
+            _ => eprintln!("Skipping {}, its pixel type is unsupported", source),
+        }
+    }
+
+    // carriers the payload fits in first, then by score
+    candidates.sort_by(|a, b| match (a.score, b.score)
+    {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+
+    println!("{:>4} {:>5} {:<6} {:>9} {:>6} {:>7}  source",
+        "rank", "score", "mode", "capacity", "rate", "texture");
+
+    for (i, c) in candidates.iter().enumerate()
+    {
+        let problems = c.problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        println!("{:>4} {:>5} {:<6} {:>9} {:>5.0}% {:>7.2}  {}{}",
+            i + 1,
+            c.score.map_or("-".to_string(), |s| format!("{:.2}", s)),
+            c.mode,
+            c.capacity,
+            100.0 * len as f64 / c.capacity.max(1) as f64,
+            c.measure.texture,
+            c.source,
+            if problems.is_empty() { String::new() } else { format!(" ({})", problems.join(", ")) });
+    }
+}
+
+fn rank_image<C: Codec>(image: &C::Input, source: &str, len: usize) -> Vec<Candidate>
+{
+    let alpha = C::CHANNEL_NAMES.iter().position(|&name| name == "a");
+    let width = C::dimensions(image).0 as usize;
+
+    C::MODE_NAMES.iter().map(|&name|
+    {
+        let mode = parse_mode::<C>(Some(name));
+
+        let capacity = C::estimate(image, mode)
+            .unwrap_or_else(|| C::layout(image, mode).len() / 8);
+        let measure = rank::measure(C::samples(image), C::CHANNELS, C::channels(mode), alpha, width);
+        let problems = rank::problems(&measure, Path::new(source));
+        let score = rank::score(&measure, &problems, len, capacity);
+
+        Candidate
+        {
+            source: source.to_string(),
+            mode: name,
+            capacity,
+            measure,
+            problems,
+            score,
+        }
+    }).collect()
+}
+
+fn dispatch_scan(source: &str, top: &str, extract: Option<&str>, len: Option<&str>)
+{
+    let top = match top.parse::<usize>()
+    {
+        Ok(t) => t,
+        Err(_) => error_out("top must be a number"),
+    };
+
+    let len = len.map(|len| match len.parse::<usize>()
+    {
+        Ok(l) => l,
+        Err(_) => error_out("length must be a number"),
+    });
+
+    let dyimage = match open(source)
+    {
+        Ok(di) => di,
+        Err(_) => error_out("Error opening source image"),
+    };
+
+    match dyimage
+    {
+        DynamicImage::ImageRgba8(image) => scan_image::<RgbaCodec>(&image, top, extract, len),
+        DynamicImage::ImageRgb8(image) => scan_image::<RgbCodec>(&image, top, extract, len),
+        DynamicImage::ImageLumaA8(image) => scan_image::<GrayAlphaCodec>(&image, top, extract, len),
+        _ => error_out("Unsupported filetype"),
+    }
+}
+
+fn scan_image<C: Codec>(
+    image: &C::Input,
+    top: usize,
+    extract: Option<&str>,
+    len: Option<usize>)
+{
+    let samples = C::samples(image);
+
+    if let Some(spec) = extract
+    {
+        let layout = match scan::Layout::parse(spec, C::CHANNEL_NAMES)
+        {
+            Some(l) => l,
+            None => error_out("Error parsing layout, expected BITS,CHANNELS,ORDER,TRAVERSAL"),
+        };
+
+        let bytes = layout.read(samples, C::CHANNELS, C::dimensions(image),
+            len.unwrap_or(usize::MAX));
+
+        write_output(None, &bytes);
+
+        return;
     }
-    
-    if let Some(matches) = matches.subcommand_matches("decode")
+
+    let modes = C::MODE_NAMES.iter()
+        .map(|&name| (name, scan::Layout::codec(C::channels(parse_mode::<C>(Some(name))))))
+        .collect::<Vec<_>>();
+
+    let hits = scan::scan(samples, C::CHANNELS, C::dimensions(image));
+
+    if hits.is_empty()
     {
-        dispatch_decode(
-            matches.value_of("mode"),
-            matches.value_of("SOURCE").unwrap(),
-            matches.value_of("length").unwrap()
-        );
+        println!("Nothing found");
     }
 
-    if let Some(matches) = matches.subcommand_matches("estimate")
+    for hit in hits.iter().take(top)
     {
-        dispatch_estimate(
-            matches.value_of("mode"),
-            matches.value_of("SOURCE").unwrap()
-        );
+        let mode = modes.iter()
+            .find(|m| m.1 == hit.layout)
+            .map(|m| format!(" (stag mode {})", m.0))
+            .unwrap_or_default();
+
+        println!("{:>5.1}  {:<16} {}{}",
+            hit.score, hit.layout.describe(C::CHANNEL_NAMES), hit.description, mode);
     }
 }
 
-use std::io::{stdin, stdout, Read, Write};
-use std::str::FromStr;
-
-use image::{open, DynamicImage};
-use rand::StdRng;
+fn dispatch_features(
+    source: &str,
+    set: &str,
+    channel: Option<&str>,
+    output: Option<&str>,
+    format: Option<&str>)
+{
+    let set = match set.parse::<features::Set>()
+    {
+        Ok(s) => s,
+        Err(_) => error_out("Unknown feature set"),
+    };
 
-use codec::Codec;
-use rgba::RgbaCodec;
-use rgb::RgbCodec;
-use gray_alpha::GrayAlphaCodec;
+    let npy = match format
+    {
+        Some(format) => format == "npy",
+        None => output.is_some_and(|o| o.ends_with(".npy")),
+    };
 
-fn dispatch_encode(mode: Option<&str>, source: &str, output: &str)
-{
     let dyimage = match open(source)
     {
         Ok(di) => di,
-        Err(_) => error_out("Error opening source image for encoding")
+        Err(_) => error_out("Error opening source image"),
     };
 
-    match dyimage
+    let (names, rows) = match dyimage
     {
-        DynamicImage::ImageRgba8(image) =>
-        {
-            match encode::<RgbaCodec>(image, mode).save(output)
-            {
-                Ok(_) => {},
-                Err(_) => error_out("Error saving encoded output file"),
-            };
-        },
-        DynamicImage::ImageRgb8(image) =>
-        {
-            match encode::<RgbCodec>(image, mode).save(output)
-            {
-                Ok(_) => {},
-                Err(_) => error_out("Error saving encoded output file"),
-            };
-        },
-        DynamicImage::ImageLumaA8(image) =>
-        {
-            match encode::<GrayAlphaCodec>(image, mode).save(output)
-            {
-                Ok(_) => {},
-                Err(_) => error_out("Error saving encoded output file"),
-            };
-        },
+        DynamicImage::ImageRgba8(image) => extract_features::<RgbaCodec>(&image, set, channel),
+        DynamicImage::ImageRgb8(image) => extract_features::<RgbCodec>(&image, set, channel),
+        DynamicImage::ImageLumaA8(image) => extract_features::<GrayAlphaCodec>(&image, set, channel),
         _ => error_out("Unsupported filetype"),
+    };
+
+    if npy
+    {
+        write_output(output, &features::to_npy(&rows));
     }
+    else
+    {
+        write_output(output, features::to_csv(&names, &rows).as_bytes());
+    }
+}
+
+fn extract_features<C: Codec>(image: &C::Input, set: features::Set, channel: Option<&str>)
+    -> (Vec<&'static str>, Vec<Vec<f64>>)
+{
+    let width = C::dimensions(image).0 as usize;
+
+    channels::<C>(channel).into_iter()
+        .map(|c| (C::CHANNEL_NAMES[c],
+            features::extract(set, &analysis::channel(C::samples(image), C::CHANNELS, c), width)))
+        .unzip()
 }
 
-fn dispatch_decode(mode: Option<&str>, source: &str, len: &str)
+fn dispatch_forensics(source: &str)
 {
-    let len = match len.parse::<usize>()
+    let (format, findings) = match forensics::inspect(&read_file(source))
     {
-        Ok(l) => l,
-        Err(_) => error_out("len argument to decode is not a number"),
+        Ok(f) => f,
+        Err(e) => error_out(e),
+    };
+
+    if findings.is_empty()
+    {
+        println!("Nothing suspicious found in the {} structure", format);
+    }
+
+    for f in findings
+    {
+        println!("{:>10}  {}", f.offset, f.message);
+    }
+}
+
+fn dispatch_chi2(source: &str, channel: Option<&str>, steps: &str)
+{
+    let steps = match steps.parse::<usize>()
+    {
+        Ok(s) if s > 0 => s,
+        _ => error_out("steps must be a positive number"),
     };
 
     let dyimage = match open(source)
     {
         Ok(di) => di,
-        Err(_) => error_out("Error opening source image for decoding"),
+        Err(_) => error_out("Error opening source image for analysis"),
     };
 
     match dyimage
     {
-        DynamicImage::ImageRgba8(image) =>
-        {
-            decode::<RgbaCodec>(image, mode, len);
-        },
-        DynamicImage::ImageRgb8(image) =>
-        {
-            decode::<RgbCodec>(image, mode, len);
-        },
-        DynamicImage::ImageLumaA8(image) =>
-        {
-            decode::<GrayAlphaCodec>(image, mode, len);
-        },
+        DynamicImage::ImageRgba8(image) => analyze_chi2::<RgbaCodec>(&image, channel, steps),
+        DynamicImage::ImageRgb8(image) => analyze_chi2::<RgbCodec>(&image, channel, steps),
+        DynamicImage::ImageLumaA8(image) => analyze_chi2::<GrayAlphaCodec>(&image, channel, steps),
         _ => error_out("Unsupported filetype"),
     }
 }
 
-fn dispatch_estimate(mode: Option<&str>, source: &str)
+fn analyze_chi2<C: Codec>(image: &C::Input, channel: Option<&str>, steps: usize)
+{
+    for c in channels::<C>(channel)
+    {
+        let samples = analysis::channel(C::samples(image), C::CHANNELS, c);
+
+        let whole = chi2::chi2(&samples);
+        println!("channel {}: chi2 {:.2}, {} degrees of freedom, probability {}",
+            C::CHANNEL_NAMES[c], whole.statistic, whole.degrees,
+            format_probability(whole.probability));
+
+        let results = chi2::sequential(&samples, steps);
+        println!("  embedding extends over about {:.0}% of the samples",
+            chi2::extent(&results, samples.len()) * 100.0);
+
+        println!("  scanned  probability");
+        for (len, result) in results
+        {
+            println!("  {:>6.1}%  {}",
+                len as f64 / samples.len().max(1) as f64 * 100.0,
+                format_probability(result.probability));
+        }
+    }
+}
+
+/// Estimates the relative message length in a channel, given its rows' width
+type LengthEstimator = fn(&[u8], usize) -> Option<f64>;
+
+fn dispatch_length(source: &str, channel: Option<&str>, estimator: LengthEstimator)
 {
     let dyimage = match open(source)
     {
         Ok(di) => di,
-        Err(_) => error_out("Error opening source image for estimating"),
+        Err(_) => error_out("Error opening source image for analysis"),
     };
 
     match dyimage
     {
         DynamicImage::ImageRgba8(image) =>
-        {
-            estimate::<RgbaCodec>(image, mode);
-        },
+            analyze_length::<RgbaCodec>(&image, channel, estimator),
         DynamicImage::ImageRgb8(image) =>
-        {
-            estimate::<RgbCodec>(image, mode);
-        },
+            analyze_length::<RgbCodec>(&image, channel, estimator),
         DynamicImage::ImageLumaA8(image) =>
-        {
-            estimate::<GrayAlphaCodec>(image, mode);
-        },
+            analyze_length::<GrayAlphaCodec>(&image, channel, estimator),
         _ => error_out("Unsupported filetype"),
     }
 }
 
-fn encode<C: Codec>(mut image: C::Input, mode: Option<&str>) -> C::Input
+fn analyze_length<C: Codec>(image: &C::Input, channel: Option<&str>, estimator: LengthEstimator)
 {
-    let mode = if let Some(mode) = mode
+    let width = C::dimensions(image).0 as usize;
+
+    for c in channels::<C>(channel)
     {
-        match C::Mode::from_str(mode)
+        let samples = analysis::channel(C::samples(image), C::CHANNELS, c);
+
+        match estimator(&samples, width)
         {
-            Ok(mode) => mode,
-            Err(_) => error_out("Error parsing mode string"),
+            Some(length) => println!("channel {}: estimated message length {:.3} bits per sample",
+                C::CHANNEL_NAMES[c], length),
+            None => println!("channel {}: could not make an estimate", C::CHANNEL_NAMES[c]),
         }
     }
-    else
+}
+
+/// The channels to analyze: the one named, or all of them
+fn channels<C: Codec>(channel: Option<&str>) -> Vec<usize>
+{
+    match channel
     {
-        C::Mode::default()
-    };
+        Some(name) => match C::CHANNEL_NAMES.iter().position(|&n| n == name)
+        {
+            Some(c) => vec![c],
+            None => error_out(&format!("No channel named {}, the image has channels {}",
+                name, C::CHANNEL_NAMES.join(", "))),
+        },
+        None => (0..C::CHANNELS).collect(),
+    }
+}
 
-    let rng = match StdRng::new()
+fn format_probability(probability: Option<f64>) -> String
+{
+    match probability
     {
-        Ok(r) => r,
-        Err(_) => error_out("Error creating source of randomness"),
-    };
+        Some(p) => format!("{:.4}", p),
+        None => "n/a".to_string(),
+    }
+}
 
-    let mut payload = String::new();
-    stdin().read_to_string(&mut payload).unwrap();
+/// Open a source as an indexed image if it is a paletted PNG or a GIF, so
+/// that its palette is kept
+fn open_indexed(source: &str) -> Option<IndexedImage>
+{
+    // a file that can not be read is left to the opener after, which says
+    // so in its own words
+    let bytes = std::fs::read(source).ok()?;
 
-    C::encode(&mut image, payload.as_bytes(), mode, rng);
+    match palette::read(&bytes)
+    {
+        Ok(image) => image,
+        Err(e) => error_out(&format!("Error opening indexed source image: {}", e)),
+    }
+}
 
-    image
+/// A file that an image is written back into, with its metadata and the
+/// way it stores pixels. Several frames are stacked into one image for the
+/// codecs and split back when saved.
+enum Frames
+{
+    Apng(Apng),
+    Tiff(Tiff),
+    Png(PngImage),
 }
 
-fn decode<C: Codec>(image: C::Input, mode: Option<&str>, len: usize)
+impl Frames
 {
-    let mode = if let Some(mode) = mode
+    fn save(&self, image: &DynamicImage, output: &str) -> std::io::Result<()>
     {
-        match C::Mode::from_str(mode)
+        match *self
         {
-            Ok(mode) => mode,
-            Err(_) => error_out("Error parsing mode string"),
+            Frames::Apng(ref apng) => apng.save(&animation::unstack(image, &apng.sizes()), output),
+            Frames::Tiff(ref tiff) => tiff.save(&image.raw_pixels(), output),
+            Frames::Png(ref png) => png.save(image, output),
         }
     }
-    else
+
+    /// Leave out the metadata when saving
+    fn strip(&mut self)
     {
-        C::Mode::default()
+        match *self
+        {
+            Frames::Apng(ref mut apng) => apng.strip(),
+            Frames::Tiff(ref mut tiff) => tiff.strip(),
+            Frames::Png(ref mut png) => png.strip(),
+        }
+    }
+}
+
+/// Open a source image. The frames of an animated PNG come stacked into one
+/// image, so that a codec runs through them in order, along with the file to
+/// split them back into. A PNG or a TIFF page comes with the file to write it
+/// back into with the same depth, colour type and metadata.
+fn open_frames(source: &str) -> Option<(DynamicImage, Option<Frames>)>
+{
+    let mut bytes = Vec::new();
+    File::open(source).and_then(|mut f| f.read_to_end(&mut bytes)).ok()?;
+
+    // image only reads lossy WebPs
+    match webp::read(&bytes)
+    {
+        Ok(Some(image)) => return Some((image, None)),
+        Ok(None) => {},
+        Err(e) => error_out(e),
+    }
+
+    // a TIFF that `open_pages` leaves is a single 8-bit page
+    match Tiff::read(&bytes)
+    {
+        Ok(Some(tiff)) => return Some((tiff.frames().remove(0), Some(Frames::Tiff(tiff)))),
+        Ok(None) => {},
+        Err(e) => error_out(e),
+    }
+
+    if let Ok(Some(apng)) = Apng::read(&bytes)
+    {
+        let image = animation::stack(&apng.frames().ok()?)?;
+
+        return Some((image, Some(Frames::Apng(apng))));
+    }
+
+    // read as stored, as image has no 16-bit samples
+    if let Ok(Some(png)) = PngImage::read(&bytes)
+    {
+        return Some((png.image(), Some(Frames::Png(png))));
+    }
+
+    open(source).ok().map(|image| (image, None))
+}
+
+/// Open a TIFF that only the pages codec can carry. Gives `None` for any
+/// other kind of image, and for a single 8-bit page that `open_frames` opens
+/// like any other image.
+fn open_pages(source: &str) -> Option<Pages>
+{
+    let bytes = std::fs::read(source).ok()?;
+
+    match Tiff::read(&bytes)
+    {
+        Ok(tiff) => tiff.and_then(Pages::new),
+        Err(e) => error_out(&format!("Error opening TIFF source image: {}", e)),
+    }
+}
+
+/// Open a JPEG to encode into its DCT coefficients. Gives `None` for any
+/// other kind of image.
+fn open_jpeg(source: &str, mode: Option<&str>) -> Option<DctImage>
+{
+    let mut bytes = Vec::new();
+    File::open(source).and_then(|mut f| f.read_to_end(&mut bytes)).ok()?;
+
+    match Jpeg::read(&bytes)
+    {
+        Ok(jpeg) => jpeg.map(|jpeg| DctImage::new(jpeg, parse_mode::<DctCodec>(mode))),
+        Err(e) => error_out(&format!("Error opening JPEG source image: {}", e)),
+    }
+}
+
+/// F5 changes how many bits a JPEG holds as it embeds, which the places a
+/// password picks can not follow
+fn check_dct_password(mode: Option<&str>, password: Option<&str>)
+{
+    if password.is_some() && parse_mode::<DctCodec>(mode) != DctMode::Jsteg
+    {
+        error_out("A password on a JPEG needs the jsteg mode, as F5 changes how many bits it holds");
+    }
+}
+
+/// Whether a mode encodes into the order of a palette rather than into its
+/// indices
+fn is_order_mode(mode: Option<&str>) -> bool
+{
+    mode.is_some_and(|mode| PermutationCodec::MODE_NAMES.contains(&mode))
+}
+
+fn open_all(sources: &[&str]) -> Vec<DynamicImage>
+{
+    sources.iter().map(|source| match open(source)
+    {
+        Ok(di) => di,
+        Err(_) => error_out(&format!("Error opening source image {}", source)),
+    }).collect()
+}
+
+/// The path an encoded image is written to in `dir`, named after its source
+fn output_path(dir: &str, source: &str) -> PathBuf
+{
+    match Path::new(source).file_name()
+    {
+        Some(name) => Path::new(dir).join(name),
+        None => error_out("Source image has no file name"),
+    }
+}
+
+/// How many payload bytes fit into an image after a header of `size` bytes
+fn header_capacity(image: &DynamicImage, mode: Option<&str>, size: usize) -> usize
+{
+    let positions = match *image
+    {
+        DynamicImage::ImageRgba8(ref image) =>
+            RgbaCodec::layout(image, parse_mode::<RgbaCodec>(mode)).len(),
+        DynamicImage::ImageRgb8(ref image) =>
+            RgbCodec::layout(image, parse_mode::<RgbCodec>(mode)).len(),
+        DynamicImage::ImageLumaA8(ref image) =>
+            GrayAlphaCodec::layout(image, parse_mode::<GrayAlphaCodec>(mode)).len(),
+        _ => error_out("Unsupported filetype"),
     };
 
-    let mut buf = vec![0; len];
+    (positions / 8).saturating_sub(size)
+}
 
-    C::decode(&image, &mut buf, len, mode);
+fn encode_and_save(
+    image: DynamicImage,
+    mode: Option<&str>,
+    payload: &[u8],
+    header: Header,
+    output: &Path)
+{
+    let output = &*output.to_string_lossy();
+    let format = output_format(output, &image);
 
-    match stdout().write(&buf)
+    let encoded = match image
     {
-        Ok(_) => {},
-        Err(_) => error_out("Error writing decoded payload"),
+        DynamicImage::ImageRgba8(mut image) =>
+        {
+            encode_header::<RgbaCodec>(&mut image, parse_mode::<RgbaCodec>(mode), payload, header);
+            DynamicImage::ImageRgba8(image)
+        },
+        DynamicImage::ImageRgb8(mut image) =>
+        {
+            encode_header::<RgbCodec>(&mut image, parse_mode::<RgbCodec>(mode), payload, header);
+            DynamicImage::ImageRgb8(image)
+        },
+        DynamicImage::ImageLumaA8(mut image) =>
+        {
+            encode_header::<GrayAlphaCodec>(&mut image, parse_mode::<GrayAlphaCodec>(mode), payload, header);
+            DynamicImage::ImageLumaA8(image)
+        },
+        _ => error_out("Unsupported filetype"),
+    };
+
+    if let Err(e) = output::save(&encoded, output, format)
+    {
+        error_out(&format!("Error saving encoded output file: {}", e));
+    }
+
+    verify_output(output, mode, payload, header.flags, None);
+}
+
+/// The format an image is written to `output` in, named by its extension,
+/// stopping when it would lose the payload
+fn output_format(output: &str, image: &DynamicImage) -> Format
+{
+    let format = match Format::from_path(output)
+    {
+        Ok(format) => format,
+        Err(e) => error_out(&e),
+    };
+
+    check_format(format, image, None);
+
+    format
+}
+
+/// Check every output an image set is written to before any is written
+fn check_outputs(images: &[DynamicImage], sources: &[&str], output: &str)
+{
+    for (image, source) in images.iter().zip(sources)
+    {
+        output_format(&output_path(output, source).to_string_lossy(), image);
+    }
+}
+
+fn read_header(source: &str, mode: Option<&str>) -> (Header, Vec<u8>)
+{
+    match open_header(source, mode)
+    {
+        Ok(found) => found,
+        Err(e) => error_out(&format!("{}: {}", source, e)),
+    }
+}
+
+fn open_header(source: &str, mode: Option<&str>)
+    -> Found
+{
+    let dyimage = match open(source)
+    {
+        Ok(di) => di,
+        Err(_) => return Err("Error opening source image"),
+    };
+
+    match dyimage
+    {
+        DynamicImage::ImageRgba8(image) =>
+            find_header::<RgbaCodec>(&image, parse_mode::<RgbaCodec>(mode)),
+        DynamicImage::ImageRgb8(image) =>
+            find_header::<RgbCodec>(&image, parse_mode::<RgbCodec>(mode)),
+        DynamicImage::ImageLumaA8(image) =>
+            find_header::<GrayAlphaCodec>(&image, parse_mode::<GrayAlphaCodec>(mode)),
+        _ => Err("Unsupported filetype"),
+    }
+}
+
+fn write_output(output: Option<&str>, data: &[u8])
+{
+    let written = match output
+    {
+        Some(path) => File::create(path).and_then(|mut f| f.write_all(data)),
+        None => stdout().write_all(data),
     };
+
+    if written.is_err()
+    {
+        error_out("Error writing decoded payload");
+    }
 }
 
-fn estimate<C: Codec>(image: C::Input, mode: Option<&str>)
+fn parse_mode<C: Codec>(mode: Option<&str>) -> C::Mode
 {
-    let mode = if let Some(mode) = mode
+    if let Some(mode) = mode
     {
         match C::Mode::from_str(mode)
         {
@@ -269,12 +3283,26 @@ fn estimate<C: Codec>(image: C::Input, mode: Option<&str>)
     else
     {
         C::Mode::default()
-    };
+    }
+}
 
-    match C::estimate(&image, mode)
+fn os_rng() -> OsRng
+{
+    match OsRng::new()
     {
-        Some(i) => println!("Estimate {} bytes", i),
-        None => println!("Could not make an estimate"),
+        Ok(r) => r,
+        Err(_) => error_out("Error creating source of randomness"),
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8>
+{
+    let mut buf = Vec::new();
+
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut buf))
+    {
+        Ok(_) => buf,
+        Err(_) => error_out("Error reading file"),
     }
 }
 
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImage};
+use image::bmp::BMPEncoder;
+use image::png::PNGEncoder;
+
+use tiff::Tiff;
+use webp;
+
+/// A kind of file an encoded image can be written as
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format
+{
+    Png,
+    Bmp,
+    Tiff,
+    WebpLossless,
+    /// only lossless for an image that already has a palette
+    Gif,
+    /// only lossless for an image encoded into its coefficients
+    Jpeg,
+}
+
+impl Format
+{
+    /// The format the extension of a path names. A WebP could be lossy or
+    /// not, so it has to be asked for by name.
+    pub fn from_path(path: &str) -> Result<Format, String>
+    {
+        let extension = Path::new(path).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        match extension.as_ref().map(|e| &e[..])
+        {
+            Some("png") => Ok(Format::Png),
+            Some("bmp") => Ok(Format::Bmp),
+            Some("tif") | Some("tiff") => Ok(Format::Tiff),
+            Some("gif") => Ok(Format::Gif),
+            Some("jpg") | Some("jpeg") => Ok(Format::Jpeg),
+            Some("webp") => Err(format!(
+                "{} could be a lossy WebP, which would destroy the payload; give --format webp-lossless to write a lossless one",
+                path)),
+            _ => Err(format!(
+                "The format to write {} in can not be told from its name; give --format png, bmp, tiff or webp-lossless",
+                path)),
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Format::Png => "PNG",
+            Format::Bmp => "BMP",
+            Format::Tiff => "TIFF",
+            Format::WebpLossless => "lossless WebP",
+            Format::Gif => "GIF",
+            Format::Jpeg => "JPEG",
+        }
+    }
+
+    /// Whether the format holds every sample of an image as it is, with
+    /// nothing quantised away and no channel dropped
+    pub fn keeps(self, image: &DynamicImage) -> bool
+    {
+        matches!((self, image),
+            (Format::Png, _) | (Format::Tiff, _)
+            | (Format::Bmp, &DynamicImage::ImageRgb8(_))
+            | (Format::WebpLossless, &DynamicImage::ImageRgb8(_))
+            | (Format::WebpLossless, &DynamicImage::ImageRgba8(_)))
+    }
+}
+
+impl FromStr for Format
+{
+    type Err = ();
+
+    /// The formats that can be asked for, which all keep a payload
+    fn from_str(s: &str) -> Result<Format, ()>
+    {
+        match s
+        {
+            "png" => Ok(Format::Png),
+            "bmp" => Ok(Format::Bmp),
+            "tiff" => Ok(Format::Tiff),
+            "webp-lossless" => Ok(Format::WebpLossless),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Write an image in a format, whatever the extension of the path says
+pub fn save(image: &DynamicImage, path: &str, format: Format) -> io::Result<()>
+{
+    let (width, height) = (image.width(), image.height());
+    let raw = image.raw_pixels();
+
+    match format
+    {
+        Format::Png => PNGEncoder::new(File::create(path)?).encode(&raw, width, height, image.color()),
+        Format::Bmp => BMPEncoder::new(&mut File::create(path)?).encode(&raw, width, height, image.color()),
+        Format::Tiff => Tiff::new(::std::slice::from_ref(image)).save(&raw, path),
+        Format::WebpLossless => match webp::write(image)
+        {
+            Some(bytes) => File::create(path)?.write_all(&bytes),
+            None => Err(io::Error::other("a lossless WebP holds RGB or RGBA images of up to 16384 pixels a side")),
+        },
+        Format::Gif | Format::Jpeg => Err(io::Error::other("not a format pixels can be saved in without loss")),
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn formats_from_paths()
+    {
+        assert_eq!(Format::from_path("a/b.PNG"), Ok(Format::Png));
+        assert_eq!(Format::from_path("b.tif"), Ok(Format::Tiff));
+        assert_eq!(Format::from_path("b.jpeg"), Ok(Format::Jpeg));
+        assert!(Format::from_path("b.webp").is_err());
+        assert!(Format::from_path("b").is_err());
+        assert_eq!("webp-lossless".parse(), Ok(Format::WebpLossless));
+        assert!("jpeg".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImage};
+use rand::Rng;
+
+use animation::stacked_size;
+use codec::Codec;
+use tiff::Tiff;
+use utils::extract;
+
+/// The pages of a TIFF that only a TIFF can hold: several of them, 16-bit
+/// samples, or grey ones with no other codec. Each page is encoded into the
+/// channels its own pixel type's codec would use.
+pub struct Pages
+{
+    tiff: Tiff,
+    /// The 8-bit samples of every page, one after the other
+    samples: Vec<u8>,
+    /// The samples per pixel of each page
+    channels: Vec<usize>,
+}
+
+impl Pages
+{
+    /// Gives `None` for a single 8-bit page that a pixel codec can carry by
+    /// itself, which can then be saved in any format that keeps it
+    pub fn new(tiff: Tiff) -> Option<Pages>
+    {
+        let frames = tiff.frames();
+        let ordinary = match frames[..]
+        {
+            [DynamicImage::ImageLuma8(_)] => false,
+            [_] => !tiff.is_deep(),
+            _ => false,
+        };
+
+        if ordinary
+        {
+            return None;
+        }
+
+        let channels = frames.iter()
+            .map(|f| f.raw_pixels().len() / (f.width() * f.height()).max(1) as usize)
+            .collect();
+
+        Some(Pages
+        {
+            samples: frames.iter().flat_map(|f| f.raw_pixels()).collect(),
+            channels,
+            tiff,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        self.tiff.save(&self.samples, path)
+    }
+
+    pub fn strip(&mut self)
+    {
+        self.tiff.strip()
+    }
+}
+
+/// Encodes through the pages of a TIFF in order, into the channels each
+/// page's codec uses for the mode
+pub struct PagesCodec;
+
+impl Codec for PagesCodec
+{
+    type Input = Pages;
+    type Mode = PagesMode;
+
+    const CHANNELS: usize = 1;
+    const CHANNEL_NAMES: &'static [&'static str] = &["s"];
+    const MODE_NAMES: &'static [&'static str] = &["alpha", "all"];
+    /// The pages can differ in pixel type, which the detectors and heat
+    /// maps can not follow
+    const PIXELS: bool = false;
+
+    fn encode<R: Rng>(
+        source: &mut Pages,
+        payload: &[u8],
+        mode: PagesMode,
+        mut rng: R)
+    {
+        let layout = Self::layout(source, mode);
+
+        Self::embed(source, &layout, payload, &mut rng);
+    }
+
+    fn decode(
+        source: &Pages,
+        buffer: &mut [u8],
+        len: usize,
+        mode: PagesMode)
+    {
+        let layout = Self::layout(source, mode);
+
+        buffer[..len].copy_from_slice(&extract(&source.samples, &layout, len));
+    }
+
+    fn estimate(
+        source: &Pages,
+        mode: PagesMode) -> Option<usize>
+    {
+        Some(Self::layout(source, mode).len() / 8)
+    }
+
+    fn channels(_mode: PagesMode) -> &'static [usize]
+    {
+        &[0]
+    }
+
+    fn dimensions(source: &Pages) -> (u32, u32)
+    {
+        stacked_size(&source.tiff.sizes())
+    }
+
+    fn samples(source: &Pages) -> &[u8]
+    {
+        &source.samples
+    }
+
+    fn samples_mut(source: &mut Pages) -> &mut [u8]
+    {
+        &mut source.samples
+    }
+
+    fn layout(
+        source: &Pages,
+        mode: PagesMode) -> Vec<usize>
+    {
+        let mut layout = Vec::new();
+        let mut start = 0;
+
+        for (&(width, height), &channels) in source.tiff.sizes().iter().zip(&source.channels)
+        {
+            let used = page_channels(channels, mode);
+
+            layout.extend((0..(width * height) as usize)
+                .flat_map(|p| used.iter().map(move |c| start + p * channels + c)));
+
+            start += (width * height) as usize * channels;
+        }
+
+        layout
+    }
+}
+
+/// The channels the codec for a page of `channels` samples per pixel uses
+fn page_channels(channels: usize, mode: PagesMode) -> &'static [usize]
+{
+    match (channels, mode)
+    {
+        (2, PagesMode::Alpha) => &[1],
+        (4, PagesMode::Alpha) => &[3],
+        (1, _) => &[0],
+        (2, _) => &[0, 1],
+        (3, _) => &[0, 1, 2],
+        _ => &[0, 1, 2, 3],
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PagesMode
+{
+    /// encode in the alpha of pages that have it, and in every channel of
+    /// those that do not
+    #[default]
+    Alpha,
+    /// encode in every channel of every page
+    All,
+}
+
+impl FromStr for PagesMode
+{
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<PagesMode, ()>
+    {
+        match s
+        {
+            "alpha" => Ok(PagesMode::Alpha),
+            "all" => Ok(PagesMode::All),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::env::temp_dir;
+    use std::fs;
+
+    use image::{ImageBuffer, Luma, Rgba};
+    use rand::StdRng;
+
+    use super::*;
+
+    #[test]
+    fn pages_of_different_types()
+    {
+        let grey = DynamicImage::ImageLuma8(ImageBuffer::from_fn(8, 4, |x, y| Luma { data: [(x * 30 + y) as u8] }));
+        let rgba = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, Rgba { data: [9, 99, 199, 255] }));
+
+        let mut pages = Pages::new(Tiff::new(&[grey, rgba])).unwrap();
+
+        // every grey sample, then the alpha of each RGBA pixel
+        assert_eq!(PagesCodec::estimate(&pages, PagesMode::Alpha), Some((32 + 16) / 8));
+        assert_eq!(PagesCodec::estimate(&pages, PagesMode::All), Some((32 + 64) / 8));
+
+        let payload = b"pages!".to_vec();
+        PagesCodec::encode(&mut pages, &payload, PagesMode::Alpha, StdRng::new().unwrap());
+
+        let path = temp_dir().join("stag-pages-test.tif");
+        pages.save(&path).unwrap();
+
+        let read = Pages::new(Tiff::read(&fs::read(&path).unwrap()).unwrap().unwrap()).unwrap();
+        let mut buf = vec![0; payload.len()];
+        PagesCodec::decode(&read, &mut buf, payload.len(), PagesMode::Alpha);
+
+        assert_eq!(buf, payload);
+        assert_eq!(read.channels, vec![1, 4]);
+    }
+
+    #[test]
+    fn single_pages_are_left_to_the_pixel_codecs()
+    {
+        let rgb = DynamicImage::ImageRgb8(ImageBuffer::new(4, 4));
+        let grey = DynamicImage::ImageLuma8(ImageBuffer::new(4, 4));
+
+        assert!(Pages::new(Tiff::new(&[rgb])).is_none());
+        assert!(Pages::new(Tiff::new(&[grey])).is_some());
+    }
+}
@@ -0,0 +1,1253 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use gif;
+use png::{self, HasParameters};
+use rand::Rng;
+
+use animation::stacked_size;
+use chunks::{self, Ancillary};
+use codec::Codec;
+use utils::{extract, get_bit};
+
+/// The PNG file signature
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Where each Adam7 pass starts and how far apart its pixels are, as
+/// (x, y, x step, y step)
+pub const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// An image whose pixels are indices into a palette of colours, in one or
+/// more frames
+#[derive(Clone)]
+pub struct IndexedImage
+{
+    /// The place of each pixel's colour in its palette sorted by luminance,
+    /// one frame after another
+    ranks: Vec<u8>,
+    frames: Vec<Frame>,
+    /// The palettes the frames use. The first is a PNG's only palette, or a
+    /// GIF's global colour table, which may be empty.
+    palettes: Vec<Palette>,
+    format: Format,
+}
+
+#[derive(Clone)]
+struct Palette
+{
+    /// RGB triples, in the order they were read
+    colours: Vec<u8>,
+    /// The palette index at each place in luminance order
+    order: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct Frame
+{
+    width: u32,
+    height: u32,
+    /// Which of the palettes the frame uses
+    palette: usize,
+    /// Everything about a GIF frame but its pixels: its place, delay,
+    /// disposal and transparent colour
+    gif: Option<gif::Frame<'static>>,
+}
+
+/// What an indexed image was read from, and everything needed to write it
+/// back the same way
+#[derive(Clone)]
+enum Format
+{
+    Png
+    {
+        bit_depth: png::BitDepth,
+        /// The alpha of each palette entry, if any
+        trns: Option<Vec<u8>>,
+        /// Metadata to write back around the pixels
+        ancillary: Ancillary,
+    },
+    Gif
+    {
+        /// The logical screen width and height
+        screen: (u16, u16),
+        /// How many times the animation repeats, 0 for ever, if it says
+        repeat: Option<u16>,
+    },
+}
+
+impl IndexedImage
+{
+    fn new(
+        frames: Vec<Frame>,
+        indices: &[u8],
+        palettes: Vec<Vec<u8>>,
+        format: Format) -> io::Result<Option<IndexedImage>>
+    {
+        let mut start = 0;
+
+        for frame in &frames
+        {
+            let end = start + (frame.width * frame.height) as usize;
+            let colours = palettes[frame.palette].len() / 3;
+
+            // with one colour there is nothing to swap between, so the
+            // pixel codecs carry it instead
+            if colours < 2
+            {
+                return Ok(None);
+            }
+            if indices[start..end].iter().any(|&i| i as usize >= colours)
+            {
+                return Err(invalid("a pixel refers to a colour past the palette"));
+            }
+
+            start = end;
+        }
+
+        let mut image = IndexedImage
+        {
+            ranks: Vec::new(),
+            frames,
+            palettes: palettes.into_iter()
+                .map(|colours| Palette
+                {
+                    colours,
+                    order: Vec::new(),
+                })
+                .collect(),
+            format,
+        };
+        image.set_indices(indices);
+
+        Ok(Some(image))
+    }
+
+    /// Set the palette index of each pixel, sorting the palettes by
+    /// luminance to rank them
+    fn set_indices(&mut self, indices: &[u8])
+    {
+        let mut rank_of = Vec::new();
+
+        for palette in &mut self.palettes
+        {
+            let colours = palette.colours.len() / 3;
+
+            // the sort is stable, so colours of the same luminance stay in
+            // palette order
+            let mut order = (0..colours).map(|i| i as u8).collect::<Vec<_>>();
+            order.sort_by_key(|&i| luminance(&palette.colours[i as usize * 3..][..3]));
+
+            let mut ranks = vec![0u8; colours];
+            for (rank, &i) in order.iter().enumerate()
+            {
+                ranks[i as usize] = rank as u8;
+            }
+
+            palette.order = order;
+            rank_of.push(ranks);
+        }
+
+        self.ranks = self.spans()
+            .flat_map(|(frame, start, end)|
+                indices[start..end].iter().map(|&i| rank_of[frame.palette][i as usize]).collect::<Vec<_>>())
+            .collect();
+    }
+
+    /// Each frame, with where its pixels start and end
+    fn spans(&self) -> impl Iterator<Item = (&Frame, usize, usize)>
+    {
+        self.frames.iter().scan(0, |start, frame|
+        {
+            let span = (*start, *start + (frame.width * frame.height) as usize);
+            *start = span.1;
+
+            Some((frame, span.0, span.1))
+        })
+    }
+
+    /// The palette index of each pixel, one frame after another
+    pub fn indices(&self) -> Vec<u8>
+    {
+        self.spans()
+            .flat_map(|(frame, start, end)|
+            {
+                let order = &self.palettes[frame.palette].order;
+
+                self.ranks[start..end].iter().map(|&r| order[r as usize]).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The number of colours in the palette of the first frame
+    pub fn colours(&self) -> usize
+    {
+        self.palettes[self.frames[0].palette].colours.len() / 3
+    }
+
+    /// The alpha of each entry in the palette of the first frame. A GIF's
+    /// transparent colour belongs to each frame, not to its palette.
+    fn alphas(&self) -> Vec<u8>
+    {
+        (0..self.colours()).map(|i| match self.format
+        {
+            Format::Png { trns: Some(ref trns), .. } => trns.get(i).cloned().unwrap_or(255),
+            Format::Png { trns: None, .. } | Format::Gif { .. } => 255,
+        }).collect()
+    }
+
+    /// The entries of each distinct colour, with alpha, in the palette of
+    /// the first frame, in the order the colours first appear
+    fn groups(&self) -> Vec<([u8; 4], Vec<usize>)>
+    {
+        let alphas = self.alphas();
+        let palette = &self.palettes[self.frames[0].palette];
+        let mut groups: Vec<([u8; 4], Vec<usize>)> = Vec::new();
+
+        for (i, (rgb, &alpha)) in palette.colours.chunks(3).zip(&alphas).enumerate()
+        {
+            let colour = [rgb[0], rgb[1], rgb[2], alpha];
+
+            match groups.iter().position(|g| g.0 == colour)
+            {
+                Some(g) => groups[g].1.push(i),
+                None => groups.push((colour, vec![i])),
+            }
+        }
+
+        groups
+    }
+
+    /// Rearrange the palette of the first frame so that entry `j` is what
+    /// entry `order[j]` was, keeping the colour of every pixel
+    fn reorder(&mut self, order: &[usize])
+    {
+        let target = self.frames[0].palette;
+
+        let mut moved = vec![0u8; order.len()];
+        for (j, &i) in order.iter().enumerate()
+        {
+            moved[i] = j as u8;
+        }
+
+        let mut indices = self.indices();
+        let alphas = self.alphas();
+
+        for (frame, start, end) in self.spans()
+        {
+            if frame.palette == target
+            {
+                for i in &mut indices[start..end]
+                {
+                    *i = moved[*i as usize];
+                }
+            }
+        }
+
+        let colours = order.iter()
+            .flat_map(|&i| self.palettes[target].colours[i * 3..][..3].to_vec())
+            .collect();
+        self.palettes[target].colours = colours;
+
+        if let Format::Png { ref mut trns, .. } = self.format
+        {
+            if trns.is_some()
+            {
+                let mut reordered = order.iter().map(|&i| alphas[i]).collect::<Vec<_>>();
+
+                // entries past the end of tRNS are opaque, and an empty tRNS
+                // is not allowed
+                while reordered.last() == Some(&255)
+                {
+                    reordered.pop();
+                }
+
+                *trns = Some(reordered).filter(|t| !t.is_empty());
+            }
+        }
+
+        for frame in self.frames.iter_mut().filter(|f| f.palette == target)
+        {
+            if let Some(ref mut gif) = frame.gif
+            {
+                // a transparent index past the palette marks no colour, and
+                // is kept as it is
+                gif.transparent = gif.transparent.map(|t| moved.get(t as usize).cloned().unwrap_or(t));
+            }
+        }
+
+        self.set_indices(&indices);
+    }
+
+    /// Leave out the metadata of a PNG when it is saved
+    pub fn strip(&mut self)
+    {
+        if let Format::Png { ref mut ancillary, .. } = self.format
+        {
+            *ancillary = Ancillary::default();
+        }
+    }
+
+    /// Save as an indexed GIF, or else PNG, with the palettes as they were
+    /// read. Only GIFs hold more than one frame.
+    pub fn save<P: AsRef<Path>>(&self, path: P, gif: bool) -> io::Result<()>
+    {
+        let bytes = if gif { self.to_gif()? } else { self.to_png()? };
+
+        File::create(path)?.write_all(&bytes)
+    }
+
+    fn to_png(&self) -> io::Result<Vec<u8>>
+    {
+        if self.frames.len() > 1
+        {
+            return Err(invalid("an animation can only be saved as a GIF"));
+        }
+
+        let frame = &self.frames[0];
+
+        let (bit_depth, trns, ancillary) = match self.format
+        {
+            Format::Png { bit_depth, ref trns, ref ancillary } => (bit_depth, trns.clone(), ancillary.clone()),
+            // a GIF's transparent colour becomes the only one with no alpha
+            Format::Gif { .. } => (png::BitDepth::Eight, frame.gif.as_ref()
+                .and_then(|gif| gif.transparent)
+                .map(|t| (0..=t).map(|i| if i == t { 0 } else { 255 }).collect()), Ancillary::default()),
+        };
+
+        let depth = bit_depth as usize;
+        let row = (frame.width as usize * depth).div_ceil(8);
+        let mut data = vec![0u8; row * frame.height as usize];
+
+        for (p, index) in self.indices().into_iter().enumerate()
+        {
+            let (y, x) = (p / frame.width as usize, p % frame.width as usize);
+            let bit = x * depth;
+
+            // pixels are packed from the most significant bit
+            data[y * row + bit / 8] |= index << (8 - depth - bit % 8);
+        }
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, frame.width, frame.height);
+            encoder.set(png::ColorType::Indexed).set(bit_depth);
+
+            let mut writer = encoder.write_header()?;
+            writer.write_chunk(*b"PLTE", &self.palettes[frame.palette].colours)?;
+            if let Some(trns) = trns
+            {
+                writer.write_chunk(*b"tRNS", &trns)?;
+            }
+            writer.write_image_data(&data)?;
+        }
+
+        ancillary.insert(&bytes).map_err(invalid)
+    }
+
+    fn to_gif(&self) -> io::Result<Vec<u8>>
+    {
+        // imported here, as it clashes with the png crate's `set`
+        use gif::SetParameter;
+
+        let (screen, repeat) = match self.format
+        {
+            Format::Gif { screen, repeat } => (screen, repeat),
+            Format::Png { .. } =>
+            {
+                let frame = &self.frames[0];
+
+                if frame.width > u16::MAX as u32 || frame.height > u16::MAX as u32
+                {
+                    return Err(invalid("the image is too large for a GIF"));
+                }
+
+                ((frame.width as u16, frame.height as u16), None)
+            },
+        };
+
+        // colour tables are a power of two in size. Copies of the brightest
+        // colour sort after it, so padding with them leaves every rank as it
+        // was where black would shift them all
+        let tables = self.palettes.iter()
+            .map(|palette|
+            {
+                let mut colours = palette.colours.clone();
+
+                if let Some(&brightest) = palette.order.last()
+                {
+                    let brightest = brightest as usize * 3;
+
+                    while !(colours.len() / 3).is_power_of_two()
+                    {
+                        colours.extend_from_slice(&palette.colours[brightest..][..3]);
+                    }
+                }
+
+                colours
+            })
+            .collect::<Vec<_>>();
+
+        let indices = self.indices();
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut bytes, screen.0, screen.1, &tables[0])?;
+
+            match repeat
+            {
+                Some(0) => encoder.set(gif::Repeat::Infinite)?,
+                Some(n) => encoder.set(gif::Repeat::Finite(n))?,
+                None => {},
+            }
+
+            for (frame, start, end) in self.spans()
+            {
+                let mut gif = match frame.gif
+                {
+                    Some(ref gif) => gif.clone(),
+                    // a PNG's first colour with no alpha becomes the
+                    // transparent one
+                    None => gif::Frame
+                    {
+                        width: frame.width as u16,
+                        height: frame.height as u16,
+                        transparent: match self.format
+                        {
+                            Format::Png { trns: Some(ref trns), .. } =>
+                                trns.iter().position(|&a| a == 0).map(|t| t as u8),
+                            _ => None,
+                        },
+                        ..gif::Frame::default()
+                    },
+                };
+
+                gif.palette = match frame.palette
+                {
+                    0 => None,
+                    p => Some(tables[p].clone()),
+                };
+                gif.interlaced = false;
+                gif.buffer = Cow::Owned(indices[start..end].to_vec());
+
+                encoder.write_frame(&gif)?;
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Read an indexed PNG or a GIF from the bytes of a file. Gives `None` for
+/// any other kind of image, or one with too few colours to work with.
+pub fn read(bytes: &[u8]) -> io::Result<Option<IndexedImage>>
+{
+    if bytes.starts_with(PNG_SIGNATURE)
+    {
+        read_png(bytes)
+    }
+    else if bytes.starts_with(b"GIF8")
+    {
+        read_gif(bytes)
+    }
+    else
+    {
+        Ok(None)
+    }
+}
+
+fn read_png(bytes: &[u8]) -> io::Result<Option<IndexedImage>>
+{
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set(png::Transformations::IDENTITY);
+
+    let (info, mut reader) = decoder.read_info()?;
+
+    // the frames of an APNG are carried by the pixel codecs instead
+    if info.color_type != png::ColorType::Indexed || reader.info().animation_control.is_some()
+    {
+        return Ok(None);
+    }
+
+    let (palette, trns) = {
+        let info = reader.info();
+        (info.palette.clone().unwrap_or_default(), info.trns.clone())
+    };
+
+    let depth = info.bit_depth as usize;
+    let width = info.width as usize;
+    let mut indices = vec![0u8; width * info.height as usize];
+    let mut next = 0;
+
+    // rows are left packed, and come pass by pass when interlaced
+    while let Some((row, pass)) = reader.next_interlaced_row()?
+    {
+        let (x, y, step) = match pass
+        {
+            Some((pass, line, _)) =>
+            {
+                let (x, y, dx, dy) = ADAM7[pass as usize - 1];
+                (x, y + line as usize * dy, dx)
+            },
+            None =>
+            {
+                next += 1;
+                (0, next - 1, 1)
+            },
+        };
+
+        let mask = ((1u16 << depth) - 1) as u8;
+
+        for (i, x) in (x..width).step_by(step).enumerate()
+        {
+            let bit = i * depth;
+
+            indices[y * width + x] = (row[bit / 8] >> (8 - depth - bit % 8)) & mask;
+        }
+    }
+
+    let frame = Frame
+    {
+        width: info.width,
+        height: info.height,
+        palette: 0,
+        gif: None,
+    };
+
+    IndexedImage::new(vec![frame], &indices, vec![palette], Format::Png
+    {
+        bit_depth: info.bit_depth,
+        trns,
+        ancillary: Ancillary::new(&chunks::read(bytes).map_err(invalid)?),
+    })
+}
+
+fn read_gif(bytes: &[u8]) -> io::Result<Option<IndexedImage>>
+{
+    let mut reader = gif::Decoder::new(bytes)
+        .read_info()
+        .map_err(|e| invalid(&e.to_string()))?;
+
+    let screen = (reader.width(), reader.height());
+    let mut palettes = vec![reader.global_palette().map(|p| p.to_vec()).unwrap_or_default()];
+    let mut frames = Vec::new();
+    let mut indices = Vec::new();
+
+    loop
+    {
+        let mut gif = match reader.read_next_frame()
+        {
+            Ok(Some(frame)) => frame.clone(),
+            Ok(None) => break,
+            Err(e) => return Err(invalid(&e.to_string())),
+        };
+
+        match gif.buffer
+        {
+            Cow::Owned(ref mut buffer) => indices.append(buffer),
+            Cow::Borrowed(buffer) => indices.extend_from_slice(buffer),
+        }
+
+        let palette = match gif.palette.take()
+        {
+            Some(local) =>
+            {
+                palettes.push(local);
+                palettes.len() - 1
+            },
+            None => 0,
+        };
+
+        frames.push(Frame
+        {
+            width: gif.width as u32,
+            height: gif.height as u32,
+            palette,
+            gif: Some(gif),
+        });
+    }
+
+    if frames.is_empty()
+    {
+        return Err(invalid("the GIF has no frames"));
+    }
+
+    IndexedImage::new(frames, &indices, palettes, Format::Gif
+    {
+        screen,
+        repeat: repeat(bytes),
+    })
+}
+
+/// The loop count of the NETSCAPE2.0 application extension, which the
+/// decoder skips over
+fn repeat(bytes: &[u8]) -> Option<u16>
+{
+    let start = bytes.windows(11).position(|w| w == b"NETSCAPE2.0")? + 11;
+    let block = bytes.get(start..start + 4)?;
+
+    if block[0] == 3 && block[1] == 1
+    {
+        Some(u16::from_le_bytes([block[2], block[3]]))
+    }
+    else
+    {
+        None
+    }
+}
+
+/// An indexed image along with the bits held in the order of its palette.
+///
+/// Sorting the distinct colours gives a reference order, and the order they
+/// appear in the palette is read as a number in the factorial number
+/// system, relative to it. Copies of a colour are kept together, after the
+/// first of them.
+pub struct OrderedImage
+{
+    image: IndexedImage,
+    /// The low bits of the number, one to a sample
+    bits: Vec<u8>,
+}
+
+impl OrderedImage
+{
+    pub fn new(image: IndexedImage) -> OrderedImage
+    {
+        let groups = image.groups();
+        let mut left = groups.iter().map(|g| g.0).collect::<Vec<_>>();
+        left.sort();
+
+        // the place of each colour among those not placed before it
+        let digits = groups.iter()
+            .map(|g|
+            {
+                let d = left.iter().position(|&c| c == g.0).unwrap();
+                left.remove(d);
+                d as u32
+            })
+            .collect::<Vec<_>>();
+
+        let m = digits.len();
+        let mut number = Vec::new();
+
+        for (i, &d) in digits.iter().enumerate().rev()
+        {
+            mul_add(&mut number, (m - i) as u32, d);
+        }
+
+        let bits = (0..order_capacity(m))
+            .map(|b| number.get(b / 32).map_or(0, |&limb| (limb >> (b % 32)) & 1) as u8)
+            .collect();
+
+        OrderedImage
+        {
+            image,
+            bits,
+        }
+    }
+
+    /// log2 of the number of orders the distinct colours of the palette can
+    /// be put in, which is log2(n!) for n distinct colours
+    pub fn order_bits(&self) -> f64
+    {
+        (2..=self.image.groups().len()).map(|k| (k as f64).log2()).sum()
+    }
+
+    /// The number of distinct colours in the palette
+    pub fn distinct(&self) -> usize
+    {
+        self.image.groups().len()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, gif: bool) -> io::Result<()>
+    {
+        self.image.save(path, gif)
+    }
+
+    /// Put the palette in the order the bits give
+    fn arrange(&mut self)
+    {
+        let mut groups = self.image.groups();
+        groups.sort_by_key(|g| g.0);
+
+        let mut number = vec![0u32; self.bits.len().div_ceil(32)];
+        for (b, &bit) in self.bits.iter().enumerate()
+        {
+            number[b / 32] |= (bit as u32 & 1) << (b % 32);
+        }
+
+        let m = groups.len();
+        let mut order = Vec::with_capacity(self.image.colours());
+
+        for i in 0..m
+        {
+            let d = div_rem(&mut number, (m - i) as u32);
+            order.extend(groups.remove(d as usize).1);
+        }
+
+        self.image.reorder(&order);
+    }
+}
+
+/// How many whole bits the order of `m` colours can hold, the floor of
+/// log2(m!)
+fn order_capacity(m: usize) -> usize
+{
+    let mut factorial = vec![1];
+
+    for k in 2..=m
+    {
+        mul_add(&mut factorial, k as u32, 0);
+    }
+
+    let top = factorial.iter().rposition(|&limb| limb != 0).unwrap();
+
+    top * 32 + 31 - factorial[top].leading_zeros() as usize
+}
+
+/// Multiply a little endian number in 32 bit limbs by `m` and add `a`
+fn mul_add(number: &mut Vec<u32>, m: u32, a: u32)
+{
+    let mut carry = a as u64;
+
+    for limb in number.iter_mut()
+    {
+        let v = *limb as u64 * m as u64 + carry;
+        *limb = v as u32;
+        carry = v >> 32;
+    }
+
+    if carry > 0
+    {
+        number.push(carry as u32);
+    }
+}
+
+/// Divide a little endian number in 32 bit limbs by `d`, giving the
+/// remainder
+fn div_rem(number: &mut [u32], d: u32) -> u32
+{
+    let mut rem = 0u64;
+
+    for limb in number.iter_mut().rev()
+    {
+        let v = (rem << 32) | *limb as u64;
+        *limb = (v / d as u64) as u32;
+        rem = v % d as u64;
+    }
+
+    rem as u32
+}
+
+/// How bright a colour looks, scaled by 1000
+fn luminance(rgb: &[u8]) -> u32
+{
+    299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32
+}
+
+fn invalid(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Encodes into palette indices the way EzStego does: the palette is sorted
+/// by luminance, and each index is moved to its neighbour in that order
+/// when its place has the wrong parity, so that a changed pixel only ever
+/// takes a colour close to its own.
+pub struct PaletteCodec;
+
+impl Codec for PaletteCodec
+{
+    type Input = IndexedImage;
+    type Mode = PaletteMode;
+
+    const CHANNELS: usize = 1;
+    const CHANNEL_NAMES: &'static [&'static str] = &["i"];
+    const MODE_NAMES: &'static [&'static str] = &["parity"];
+
+    fn encode<R: Rng>(
+        source: &mut IndexedImage,
+        payload: &[u8],
+        mode: PaletteMode,
+        mut rng: R)
+    {
+        let layout = Self::layout(source, mode);
+
+        Self::embed(source, &layout, payload, &mut rng);
+    }
+
+    fn decode(
+        source: &IndexedImage,
+        buffer: &mut [u8],
+        len: usize,
+        mode: PaletteMode)
+    {
+        let layout = Self::layout(source, mode);
+
+        buffer[..len].copy_from_slice(&extract(&source.ranks, &layout, len));
+    }
+
+    fn estimate(
+        source: &IndexedImage,
+        _mode: PaletteMode) -> Option<usize>
+    {
+        Some(source.ranks.len() / 8)
+    }
+
+    fn channels(_mode: PaletteMode) -> &'static [usize]
+    {
+        &[0]
+    }
+
+    fn dimensions(source: &IndexedImage) -> (u32, u32)
+    {
+        let sizes = source.frames.iter().map(|f| (f.width, f.height)).collect::<Vec<_>>();
+
+        stacked_size(&sizes)
+    }
+
+    fn samples(source: &IndexedImage) -> &[u8]
+    {
+        &source.ranks
+    }
+
+    fn samples_mut(source: &mut IndexedImage) -> &mut [u8]
+    {
+        &mut source.ranks
+    }
+
+    fn embed<R: Rng>(
+        source: &mut IndexedImage,
+        positions: &[usize],
+        bytes: &[u8],
+        _rng: &mut R)
+    {
+        // where each frame ends, and the last place in its palette
+        let ends = source.spans()
+            .map(|(frame, _, end)| (end, source.palettes[frame.palette].colours.len() / 3 - 1))
+            .collect::<Vec<_>>();
+
+        for (i, &p) in positions.iter().take(bytes.len() * 8).enumerate()
+        {
+            let last = ends[ends.partition_point(|&(end, _)| end <= p)].1;
+            let rank = &mut source.ranks[p];
+
+            if get_bit(*rank, 0) != get_bit(bytes[i / 8], (i % 8) as u8)
+            {
+                // the brightest colour of an odd sized palette has no pair,
+                // so it steps down instead
+                *rank = if (*rank ^ 1) as usize > last { *rank - 1 } else { *rank ^ 1 };
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub enum PaletteMode
+{
+    /// encode in index parity along the palette sorted by luminance
+    #[default]
+    Parity,
+}
+
+impl FromStr for PaletteMode
+{
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<PaletteMode, ()>
+    {
+        if s == "parity"
+        {
+            Ok(PaletteMode::Parity)
+        }
+        else
+        {
+            Err(())
+        }
+    }
+}
+
+/// Encodes into the order of the palette. Every pixel keeps its colour,
+/// only the palette entry it points to moves.
+pub struct PermutationCodec;
+
+impl Codec for PermutationCodec
+{
+    type Input = OrderedImage;
+    type Mode = PermutationMode;
+
+    const CHANNELS: usize = 1;
+    const CHANNEL_NAMES: &'static [&'static str] = &["o"];
+    const MODE_NAMES: &'static [&'static str] = &["order"];
+    const PIXELS: bool = false;
+
+    fn encode<R: Rng>(
+        source: &mut OrderedImage,
+        payload: &[u8],
+        mode: PermutationMode,
+        mut rng: R)
+    {
+        let layout = Self::layout(source, mode);
+
+        Self::embed(source, &layout, payload, &mut rng);
+    }
+
+    fn decode(
+        source: &OrderedImage,
+        buffer: &mut [u8],
+        len: usize,
+        mode: PermutationMode)
+    {
+        let layout = Self::layout(source, mode);
+
+        buffer[..len].copy_from_slice(&extract(&source.bits, &layout, len));
+    }
+
+    fn estimate(
+        source: &OrderedImage,
+        _mode: PermutationMode) -> Option<usize>
+    {
+        Some(source.bits.len() / 8)
+    }
+
+    fn channels(_mode: PermutationMode) -> &'static [usize]
+    {
+        &[0]
+    }
+
+    fn dimensions(source: &OrderedImage) -> (u32, u32)
+    {
+        PaletteCodec::dimensions(&source.image)
+    }
+
+    fn samples(source: &OrderedImage) -> &[u8]
+    {
+        &source.bits
+    }
+
+    fn samples_mut(source: &mut OrderedImage) -> &mut [u8]
+    {
+        &mut source.bits
+    }
+
+    fn embed<R: Rng>(
+        source: &mut OrderedImage,
+        positions: &[usize],
+        bytes: &[u8],
+        _rng: &mut R)
+    {
+        for (i, &p) in positions.iter().take(bytes.len() * 8).enumerate()
+        {
+            source.bits[p] = get_bit(bytes[i / 8], (i % 8) as u8) as u8;
+        }
+
+        source.arrange();
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub enum PermutationMode
+{
+    /// encode in the order of the palette's distinct colours
+    #[default]
+    Order,
+}
+
+impl FromStr for PermutationMode
+{
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<PermutationMode, ()>
+    {
+        if s == "order"
+        {
+            Ok(PermutationMode::Order)
+        }
+        else
+        {
+            Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::env::temp_dir;
+    use std::fs;
+
+    use rand::StdRng;
+
+    use super::*;
+
+    /// A palette with no order to its brightness, and pixels using every
+    /// colour
+    fn image(colours: u32) -> IndexedImage
+    {
+        let palette = (0..colours)
+            .flat_map(|i| vec![(i * 97 % 256) as u8, (i * 53 % 256) as u8, (i * 151 % 256) as u8])
+            .collect::<Vec<_>>();
+        let indices = (0..40 * 30u32).map(|i| (i * 7 % colours) as u8).collect::<Vec<_>>();
+
+        let frame = Frame
+        {
+            width: 40,
+            height: 30,
+            palette: 0,
+            gif: None,
+        };
+
+        IndexedImage::new(vec![frame], &indices, vec![palette], Format::Png
+        {
+            bit_depth: if colours > 16 { png::BitDepth::Eight } else { png::BitDepth::Four },
+            trns: None,
+            ancillary: Ancillary::default(),
+        }).unwrap().unwrap()
+    }
+
+    #[test]
+    fn moves_to_luminance_neighbours()
+    {
+        // an odd sized palette leaves the brightest colour without a pair
+        for &colours in &[13, 256]
+        {
+            neighbours(colours);
+        }
+    }
+
+    fn neighbours(colours: u32)
+    {
+        let mut image = image(colours);
+        let cover = image.clone();
+        let payload = b"a palette keeps its colours".to_vec();
+        let mut buf = vec![0; payload.len()];
+
+        PaletteCodec::encode(&mut image, &payload, PaletteMode::Parity, StdRng::new().unwrap());
+        PaletteCodec::decode(&image, &mut buf, payload.len(), PaletteMode::Parity);
+
+        assert_eq!(buf, payload);
+        assert_eq!(image.palettes[0].colours, cover.palettes[0].colours);
+
+        let brightness = |image: &IndexedImage, p: usize|
+            luminance(&image.palettes[0].colours[image.indices()[p] as usize * 3..][..3]);
+
+        for p in 0..image.ranks.len()
+        {
+            let step = (image.ranks[p] as i32 - cover.ranks[p] as i32).abs();
+            assert!(step <= 1);
+
+            // no colour between the old and the new one in brightness
+            let (a, b) = (brightness(&cover, p), brightness(&image, p));
+            let (low, high) = (a.min(b), a.max(b));
+            let between = (0..colours as usize)
+                .map(|i| luminance(&image.palettes[0].colours[i * 3..][..3]))
+                .filter(|&l| l > low && l < high)
+                .count();
+            assert_eq!(between, 0);
+        }
+    }
+
+    #[test]
+    fn one_colour_is_left_to_the_pixel_codecs()
+    {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 4, 4);
+            encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_chunk(*b"PLTE", &[200, 100, 50]).unwrap();
+            writer.write_image_data(&[0; 16]).unwrap();
+        }
+
+        assert!(read(&bytes).unwrap().is_none());
+    }
+
+    #[test]
+    fn saves_as_indexed()
+    {
+        let image = image(13);
+
+        for name in &["stag-palette-test.png", "stag-palette-test.gif"]
+        {
+            let path = temp_dir().join(name);
+            image.save(&path, name.ends_with("gif")).unwrap();
+
+            let read = read(&fs::read(&path).unwrap()).unwrap().unwrap();
+
+            assert_eq!(read.indices(), image.indices());
+            // GIFs pad the palette out to a power of two
+            assert_eq!(read.palettes[0].colours[..image.palettes[0].colours.len()], image.palettes[0].colours[..]);
+            assert_eq!(read.ranks, image.ranks);
+            assert_eq!(PaletteCodec::dimensions(&read), (40, 30));
+        }
+    }
+    #[test]
+    fn keeps_gif_animation()
+    {
+        use gif::SetParameter;
+
+        let global = (0..16u32).flat_map(|i| vec![(i * 97 % 256) as u8, (i * 31 % 256) as u8, 7]).collect::<Vec<_>>();
+        let local = (0..8u32).flat_map(|i| vec![3, (i * 89 % 256) as u8, (i * 41 % 256) as u8]).collect::<Vec<_>>();
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut bytes, 20, 16, &global).unwrap();
+            encoder.set(gif::Repeat::Finite(3)).unwrap();
+
+            let first = gif::Frame
+            {
+                width: 20,
+                height: 16,
+                delay: 10,
+                buffer: Cow::Owned((0..320).map(|i| (i * 5 % 16) as u8).collect()),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&first).unwrap();
+
+            let second = gif::Frame
+            {
+                left: 4,
+                top: 2,
+                width: 12,
+                height: 10,
+                delay: 25,
+                dispose: gif::DisposalMethod::Background,
+                transparent: Some(5),
+                palette: Some(local.clone()),
+                buffer: Cow::Owned((0..120).map(|i| (i * 3 % 8) as u8).collect()),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&second).unwrap();
+        }
+
+        let path = temp_dir().join("stag-animation-test.gif");
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+        let mut image = read(&fs::read(&path).unwrap()).unwrap().unwrap();
+        let cover = image.clone();
+        // long enough to run into the second frame
+        let payload = (0..50u32).map(|i| (i * 37 + 11) as u8).collect::<Vec<_>>();
+
+        PaletteCodec::encode(&mut image, &payload, PaletteMode::Parity, StdRng::new().unwrap());
+        image.save(&path, true).unwrap();
+
+        let read = read(&fs::read(&path).unwrap()).unwrap().unwrap();
+        let mut buf = vec![0; payload.len()];
+        PaletteCodec::decode(&read, &mut buf, payload.len(), PaletteMode::Parity);
+
+        assert_eq!(buf, payload);
+        assert!(read.ranks[320..] != cover.ranks[320..]);
+        assert_eq!(read.palettes[1].colours, local);
+
+        match read.format
+        {
+            Format::Gif { screen, repeat } => assert_eq!((screen, repeat), ((20, 16), Some(3))),
+            _ => panic!("not read as a GIF"),
+        }
+
+        let second = read.frames[1].gif.as_ref().unwrap();
+        assert_eq!((second.left, second.top, second.delay), (4, 2, 25));
+        assert_eq!(second.dispose, gif::DisposalMethod::Background);
+        assert_eq!(second.transparent, Some(5));
+    }
+
+    #[test]
+    fn order_capacity_is_log_factorial()
+    {
+        assert_eq!(order_capacity(1), 0);
+        assert_eq!(order_capacity(2), 1);
+        assert_eq!(order_capacity(13), 32);
+        assert_eq!(order_capacity(256), 1683);
+    }
+
+    #[test]
+    fn permutes_without_changing_colours()
+    {
+        let mut cover = image(14);
+
+        // a copy of the first colour, and some transparency
+        let first = cover.palettes[0].colours[..3].to_vec();
+        cover.palettes[0].colours[39..].copy_from_slice(&first);
+        cover.format = Format::Png
+        {
+            bit_depth: png::BitDepth::Four,
+            trns: Some(vec![255, 0, 128]),
+            ancillary: Ancillary::default(),
+        };
+
+        let colours = |image: &IndexedImage|
+        {
+            let alphas = image.alphas();
+
+            image.indices().iter()
+                .map(|&i| (image.palettes[0].colours[i as usize * 3..][..3].to_vec(), alphas[i as usize]))
+                .collect::<Vec<_>>()
+        };
+
+        let mut image = OrderedImage::new(cover.clone());
+        assert_eq!(image.distinct(), 13);
+        assert_eq!(PermutationCodec::estimate(&image, PermutationMode::Order), Some(4));
+
+        let payload = b"ord!".to_vec();
+        PermutationCodec::encode(&mut image, &payload, PermutationMode::Order, StdRng::new().unwrap());
+
+        let path = temp_dir().join("stag-permutation-test.png");
+        image.save(&path, false).unwrap();
+
+        let read = OrderedImage::new(read(&fs::read(&path).unwrap()).unwrap().unwrap());
+        let mut buf = vec![0; payload.len()];
+        PermutationCodec::decode(&read, &mut buf, payload.len(), PermutationMode::Order);
+
+        assert_eq!(buf, payload);
+        assert_eq!(colours(&read.image), colours(&cover));
+        assert!(read.image.indices() != cover.indices());
+    }
+
+    #[test]
+    fn drops_an_opaque_trns()
+    {
+        let mut image = image(4);
+        image.format = Format::Png
+        {
+            bit_depth: png::BitDepth::Four,
+            trns: Some(vec![255, 255]),
+            ancillary: Ancillary::default(),
+        };
+
+        image.reorder(&[3, 2, 1, 0]);
+
+        match image.format
+        {
+            Format::Png { trns, .. } => assert_eq!(trns, None),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn keeps_a_transparent_index_past_the_palette()
+    {
+        for &(transparent, expected) in &[(0, 3), (200, 200)]
+        {
+            let mut image = image(4);
+            image.frames[0].gif = Some(gif::Frame
+            {
+                transparent: Some(transparent),
+                ..gif::Frame::default()
+            });
+
+            image.reorder(&[3, 2, 1, 0]);
+
+            assert_eq!(image.frames[0].gif.as_ref().unwrap().transparent, Some(expected));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use image::{GrayImage, ImageBuffer, Luma};
+
+/// Pixels between planes on a contact sheet
+const GAP: u32 = 4;
+/// The shade of the gaps between planes
+const GAP_SHADE: u8 = 128;
+
+/// One bit plane of a channel as a black and white image, white where the
+/// bit is set
+pub fn plane(
+    samples: &[u8],
+    channels: usize,
+    channel: usize,
+    bit: u8,
+    (width, height): (u32, u32)) -> GrayImage
+{
+    assert!(bit < 8);
+
+    ImageBuffer::from_fn(width, height, |x, y|
+    {
+        let s = samples[(y as usize * width as usize + x as usize) * channels + channel];
+
+        Luma
+        {
+            data: [if (s >> bit) & 1 == 1 { 255 } else { 0 }],
+        }
+    })
+}
+
+/// The bit planes of the given channels laid out in a grid, a row for each
+/// channel and a column for each bit, most significant bit first
+pub fn contact_sheet(
+    samples: &[u8],
+    channels: usize,
+    rows: &[usize],
+    (width, height): (u32, u32)) -> GrayImage
+{
+    let sheet_width = 8 * width + 7 * GAP;
+    let sheet_height = rows.len() as u32 * height + (rows.len() as u32).saturating_sub(1) * GAP;
+
+    let mut sheet = ImageBuffer::from_pixel(sheet_width, sheet_height, Luma
+    {
+        data: [GAP_SHADE],
+    });
+
+    for (row, &channel) in rows.iter().enumerate()
+    {
+        for column in 0..8
+        {
+            let bit = 7 - column as u8;
+            let plane = plane(samples, channels, channel, bit, (width, height));
+
+            let (left, top) = (column * (width + GAP), row as u32 * (height + GAP));
+
+            for (x, y, px) in plane.enumerate_pixels()
+            {
+                sheet.put_pixel(left + x, top + y, *px);
+            }
+        }
+    }
+
+    sheet
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn lsb_plane()
+    {
+        // two pixels of two channels
+        let samples = [1, 2, 4, 3];
+
+        let plane = plane(&samples, 2, 1, 0, (2, 1));
+
+        assert_eq!(plane.get_pixel(0, 0).data, [0]);
+        assert_eq!(plane.get_pixel(1, 0).data, [255]);
+    }
+
+    #[test]
+    fn sheet_size()
+    {
+        let samples = [0u8; 3 * 10 * 5];
+
+        let sheet = contact_sheet(&samples, 3, &[0, 1, 2], (10, 5));
+
+        assert_eq!(sheet.dimensions(), (8 * 10 + 7 * GAP, 3 * 5 + 2 * GAP));
+    }
+}
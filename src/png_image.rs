@@ -0,0 +1,285 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use image::{DynamicImage, ImageBuffer};
+use png::{self, BitDepth, ColorType, HasParameters};
+
+use chunks::{self, Ancillary};
+use palette::ADAM7;
+
+/// A PNG of one truecolour or grey and alpha image, read as it is stored
+/// rather than as image converts it, so that it is written back with the same
+/// bit depth, colour type and metadata
+pub struct PngImage
+{
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    /// Every sample, a 16-bit one in PNG's big-endian order
+    data: Vec<u8>,
+    ancillary: Ancillary,
+}
+
+impl PngImage
+{
+    /// Read a PNG of 8 or 16 bits a sample. Gives `None` for anything else,
+    /// leaving indexed, animated and low bit depth PNGs to other readers, and
+    /// leaving grey ones and those with a colour key to image, which gives
+    /// them the alpha channel a pixel codec needs.
+    pub fn read(bytes: &[u8]) -> Result<Option<PngImage>, &'static str>
+    {
+        if !bytes.starts_with(chunks::SIGNATURE)
+        {
+            return Ok(None);
+        }
+
+        let chunks = chunks::read(bytes)?;
+
+        if chunks.iter().any(|c| &c.kind == b"tRNS")
+        {
+            return Ok(None);
+        }
+
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set(png::Transformations::IDENTITY);
+
+        let (info, mut reader) = decoder.read_info().map_err(|_| "The PNG could not be decoded")?;
+
+        if info.color_type == ColorType::Indexed
+            || info.color_type == ColorType::Grayscale
+            || (info.bit_depth as u8) < 8
+            || reader.info().animation_control.is_some()
+        {
+            return Ok(None);
+        }
+
+        let width = info.width as usize;
+        let pixel = info.color_type.samples() * info.bit_depth as usize / 8;
+        let mut data = vec![0u8; width * info.height as usize * pixel];
+        let mut next = 0;
+
+        // rows come pass by pass when interlaced
+        while let Some((row, pass)) = reader.next_interlaced_row().map_err(|_| "The PNG could not be decoded")?
+        {
+            let (x, y, step) = match pass
+            {
+                Some((pass, line, _)) =>
+                {
+                    let (x, y, dx, dy) = ADAM7[pass as usize - 1];
+                    (x, y + line as usize * dy, dx)
+                },
+                None =>
+                {
+                    next += 1;
+                    (0, next - 1, 1)
+                },
+            };
+
+            for (i, x) in (x..width).step_by(step).enumerate()
+            {
+                let at = (y * width + x) * pixel;
+                data[at..at + pixel].copy_from_slice(&row[i * pixel..(i + 1) * pixel]);
+            }
+        }
+
+        Ok(Some(PngImage
+        {
+            width: info.width,
+            height: info.height,
+            color_type: info.color_type,
+            bit_depth: info.bit_depth,
+            data,
+            ancillary: Ancillary::new(&chunks),
+        }))
+    }
+
+    /// The image with 8 bits a sample. A 16-bit sample gives its low byte,
+    /// which is the one a payload goes into.
+    pub fn image(&self) -> DynamicImage
+    {
+        let raw = self.data.iter()
+            .skip(self.step() - 1)
+            .step_by(self.step())
+            .cloned()
+            .collect::<Vec<_>>();
+        let (w, h) = (self.width, self.height);
+
+        match self.color_type
+        {
+            ColorType::GrayscaleAlpha => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+            ColorType::RGB => DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+            _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+        }
+    }
+
+    /// Whether the samples are 16 bits, whose high bytes only a PNG is
+    /// written with
+    pub fn is_deep(&self) -> bool
+    {
+        self.bit_depth == BitDepth::Sixteen
+    }
+
+    /// Leave out the ancillary chunks when saving
+    pub fn strip(&mut self)
+    {
+        self.ancillary = Ancillary::default();
+    }
+
+    /// Save new pixels of the same size and type in place of the old ones.
+    /// The high byte of a 16-bit sample is kept, and the new image sets its
+    /// low byte.
+    pub fn save<P: AsRef<Path>>(&self, image: &DynamicImage, path: P) -> io::Result<()>
+    {
+        let mut data = self.data.clone();
+
+        for (sample, &value) in data.iter_mut()
+            .skip(self.step() - 1)
+            .step_by(self.step())
+            .zip(&image.raw_pixels())
+        {
+            *sample = value;
+        }
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set(self.color_type).set(self.bit_depth);
+
+            encoder.write_header()?.write_image_data(&data)?;
+        }
+
+        let bytes = self.ancillary.insert(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        File::create(path)?.write_all(&bytes)
+    }
+
+    /// The bytes in a sample
+    fn step(&self) -> usize
+    {
+        self.bit_depth as usize / 8
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::env::temp_dir;
+    use std::fs;
+
+    use image::GenericImage;
+
+    use chunks::Chunk;
+    use super::*;
+
+    /// An interlaced 16-bit PNG of colour type `color_type`, grey or RGB,
+    /// with a gamma, a comment and any colour key given
+    fn png(color_type: u8, trns: Option<&[u8]>) -> Vec<u8>
+    {
+        let (width, height) = (5u32, 4u32);
+        let channels = if color_type == 2 { 3 } else { 1 };
+        let samples = (0..width * height * channels)
+            .map(|i| (i as u16).wrapping_mul(4099).wrapping_add(7))
+            .collect::<Vec<_>>();
+
+        // Adam7 by hand, each row behind its filter byte of none
+        let mut data = Vec::new();
+        for &(x0, y0, dx, dy) in ADAM7.iter()
+        {
+            for y in (y0..height as usize).step_by(dy)
+            {
+                let xs = (x0..width as usize).step_by(dx).collect::<Vec<_>>();
+                if xs.is_empty()
+                {
+                    continue;
+                }
+
+                data.push(0);
+                for x in xs
+                {
+                    for c in 0..channels as usize
+                    {
+                        data.extend_from_slice(&samples[(y * width as usize + x) * channels as usize + c].to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[16, color_type, 0, 0, 1]);
+
+        // zlib of a single stored block
+        let mut idat = vec![0x78, 0x01, 1];
+        idat.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        idat.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+        idat.extend_from_slice(&data);
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &d|
+        {
+            let a = (a + d as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        idat.extend_from_slice(&(b << 16 | a).to_be_bytes());
+
+        let mut chunks = vec![
+            Chunk::new(b"IHDR", header),
+            Chunk::new(b"gAMA", vec![0, 0, 177, 143]),
+        ];
+        chunks.extend(trns.map(|trns| Chunk::new(b"tRNS", trns.to_vec())));
+        chunks.extend(vec![
+            Chunk::new(b"IDAT", idat),
+            Chunk::new(b"tEXt", b"Comment\0hello".to_vec()),
+            Chunk::new(b"IEND", Vec::new()),
+        ]);
+
+        chunks::write(&chunks)
+    }
+
+    #[test]
+    fn keeps_depth_and_chunks()
+    {
+        let bytes = png(2, None);
+        let source = PngImage::read(&bytes).unwrap().unwrap();
+        let mut image = source.image();
+
+        assert!(source.is_deep());
+        assert_eq!(image.dimensions(), (5, 4));
+        // the low byte of the first sample, 7
+        assert_eq!(image.get_pixel(0, 0).data[0], 7);
+
+        if let DynamicImage::ImageRgb8(ref mut rgb) = image
+        {
+            rgb.get_pixel_mut(4, 3).data[2] ^= 1;
+        }
+        else
+        {
+            panic!("an RGB PNG is read as RGB");
+        }
+
+        let path = temp_dir().join("stag-png-image-test.png");
+        source.save(&image, &path).unwrap();
+
+        let written = fs::read(&path).unwrap();
+        let read = PngImage::read(&written).unwrap().unwrap();
+
+        assert_eq!((read.color_type, read.bit_depth), (ColorType::RGB, BitDepth::Sixteen));
+        assert_eq!(read.ancillary, source.ancillary);
+
+        let last = source.data.len() - 1;
+        assert_eq!(read.data[last], source.data[last] ^ 1);
+        assert_eq!(read.data[..last], source.data[..last]);
+
+        let kinds = chunks::read(&written).unwrap().into_iter().map(|c| c.kind).collect::<Vec<_>>();
+        assert!(kinds.contains(b"gAMA") && kinds.contains(b"tEXt"));
+    }
+
+    #[test]
+    fn leaves_grey_and_colour_keys_to_image()
+    {
+        assert!(PngImage::read(&png(2, Some(&[0, 7, 0, 8, 0, 9]))).unwrap().is_none());
+        assert!(PngImage::read(&png(0, Some(&[0, 7]))).unwrap().is_none());
+        assert!(PngImage::read(&png(0, None)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::path::Path;
+
+use analysis::channel;
+
+/// Mean neighbour differences below this are too smooth to hide changes in
+const MIN_TEXTURE: f64 = 2.0;
+/// Mean neighbour differences above this add nothing more
+const MAX_TEXTURE: f64 = 16.0;
+/// More equal neighbours than this leaves too little texture
+const MAX_SMOOTH: f64 = 0.5;
+/// More samples at 0 or 255 than this can only change one way
+const MAX_SATURATED: f64 = 0.2;
+/// What each problem takes off the score
+const PENALTY: f64 = 0.25;
+
+/// Extensions of formats that are lossy, whose sources carry compression
+/// artifacts
+const LOSSY: &[&str] = &["jpg", "jpeg", "jpe", "jfif", "webp"];
+
+/// What the channels a mode encodes into are like
+#[derive(Copy, Clone, Debug)]
+pub struct Measure
+{
+    /// The mean difference between neighbouring samples
+    pub texture: f64,
+    /// The share of neighbouring samples that are equal
+    pub smooth: f64,
+    /// The share of samples at 0 or 255
+    pub saturated: f64,
+    /// Whether an alpha channel being encoded into is fully opaque
+    pub opaque: bool,
+}
+
+/// Something that makes an image a worse carrier
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Problem
+{
+    OpaqueAlpha,
+    Saturated,
+    Lossy,
+    Smooth,
+}
+
+impl fmt::Display for Problem
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.write_str(match *self
+        {
+            Problem::OpaqueAlpha => "opaque alpha",
+            Problem::Saturated => "saturated",
+            Problem::Lossy => "lossy source",
+            Problem::Smooth => "too smooth",
+        })
+    }
+}
+
+/// Measure the `used` channels of an image, `alpha` being its alpha
+/// channel if it has one
+pub fn measure(
+    samples: &[u8],
+    channels: usize,
+    used: &[usize],
+    alpha: Option<usize>,
+    width: usize) -> Measure
+{
+    let (mut differences, mut equal, mut pairs, mut saturated) = (0u64, 0, 0, 0);
+
+    for &c in used
+    {
+        let plane = channel(samples, channels, c);
+
+        for row in plane.chunks(width)
+        {
+            for pair in row.windows(2)
+            {
+                let d = pair[0].abs_diff(pair[1]);
+
+                differences += d as u64;
+                equal += (d == 0) as usize;
+                pairs += 1;
+            }
+        }
+
+        saturated += plane.iter().filter(|&&s| s == 0 || s == 255).count();
+    }
+
+    let total = (samples.len() / channels * used.len()).max(1);
+    let pairs = pairs.max(1);
+
+    Measure
+    {
+        texture: differences as f64 / pairs as f64,
+        smooth: equal as f64 / pairs as f64,
+        saturated: saturated as f64 / total as f64,
+        opaque: alpha.is_some_and(|a| used.contains(&a)
+            && samples.iter().skip(a).step_by(channels).all(|&s| s == 255)),
+    }
+}
+
+/// The problems an image has as a carrier
+pub fn problems(measure: &Measure, path: &Path) -> Vec<Problem>
+{
+    let mut problems = Vec::new();
+
+    if measure.opaque
+    {
+        problems.push(Problem::OpaqueAlpha);
+    }
+    if measure.saturated > MAX_SATURATED
+    {
+        problems.push(Problem::Saturated);
+    }
+    if is_lossy(path)
+    {
+        problems.push(Problem::Lossy);
+    }
+    if measure.texture < MIN_TEXTURE || measure.smooth > MAX_SMOOTH
+    {
+        problems.push(Problem::Smooth);
+    }
+
+    problems
+}
+
+/// Whether a path names a lossy format, by its extension
+pub fn is_lossy(path: &Path) -> bool
+{
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| LOSSY.contains(&e.to_lowercase().as_str()))
+}
+
+/// How good a carrier is for a payload of `len` bytes, from 0 to 1, or
+/// `None` if it does not fit. Busier images, lower embedding rates and
+/// fewer problems score higher.
+pub fn score(measure: &Measure, problems: &[Problem], len: usize, capacity: usize) -> Option<f64>
+{
+    if len > capacity
+    {
+        return None;
+    }
+
+    let texture = measure.texture.min(MAX_TEXTURE) / MAX_TEXTURE;
+    let rate = len as f64 / capacity.max(1) as f64;
+
+    Some((texture * (1.0 - rate) - PENALTY * problems.len() as f64).max(0.0))
+}
+
+#[cfg(test)]
+mod test
+{
+    use analysis::test::{cover, rng};
+
+    use super::*;
+
+    #[test]
+    fn flat_opaque_image()
+    {
+        // grey and opaque, two channels
+        let samples = [128u8, 255].repeat(100 * 100);
+        let flat = measure(&samples, 2, &[0, 1], Some(1), 100);
+
+        assert!(flat.opaque);
+        assert_eq!(flat.texture, 0.0);
+        assert_eq!(
+            problems(&flat, Path::new("flat.JPG")),
+            vec![Problem::OpaqueAlpha, Problem::Saturated, Problem::Lossy, Problem::Smooth]);
+        assert_eq!(score(&flat, &[], 10, 100), Some(0.0));
+        assert_eq!(score(&flat, &[], 101, 100), None);
+
+        let grey = measure(&samples, 2, &[0], Some(1), 100);
+
+        assert!(!grey.opaque);
+        assert_eq!(grey.saturated, 0.0);
+    }
+
+    #[test]
+    fn busier_is_better()
+    {
+        let (plane, width) = cover(&mut rng());
+        let busy = measure(&plane, 1, &[0], None, width);
+
+        assert!(problems(&busy, Path::new("busy.png")).is_empty());
+
+        let smooth = plane.iter().map(|s| s & !7).collect::<Vec<_>>();
+        let smooth = measure(&smooth, 1, &[0], None, width);
+
+        assert!(score(&busy, &[], 100, 1000) > score(&smooth, &[], 100, 1000));
+    }
+}
@@ -5,6 +5,9 @@ use itertools::Itertools;
 use utils::*;
 use codec::Codec;
 
+/// Packs each 3 bytes of payload into the low bits of a run of 8 pixels. A
+/// payload whose length is not a multiple of 3 has its last bytes padded
+/// out with zeros, so it takes `len.div_ceil(3)` runs.
 pub struct RgbCodec;
 
 impl Codec for RgbCodec
@@ -12,6 +15,10 @@ impl Codec for RgbCodec
     type Input = RgbImage;
     type Mode = RgbMode;
 
+    const CHANNELS: usize = 3;
+    const CHANNEL_NAMES: &'static [&'static str] = &["r", "g", "b"];
+    const MODE_NAMES: &'static [&'static str] = &["all"];
+
     fn encode<R: Rng>(
         source: &mut RgbImage,
         payload: &[u8],
@@ -31,12 +38,17 @@ impl Codec for RgbCodec
                  // make them also vectors
                  .map(|ch| ch.collect::<Vec<_>>()))
         {
-            // if any of the chunks are short, abort
-            if pixels.len() != 8 || bytes.len() != 3
+            // if the pixels run short, abort
+            if pixels.len() != 8
             {
                 return;
             }
 
+            // the last chunk of bytes can be short, so fill it out; decoding
+            // stops at the length and never reads the filler
+            let mut bytes = bytes;
+            bytes.resize(3, 0);
+
             let rng = &mut rng;
             // i looked for a better way...
             fix_u8(&mut pixels[0].data[0], get_bit(bytes[0], 0), rng);
@@ -76,15 +88,13 @@ impl Codec for RgbCodec
     {
         assert!(len <= payload.len());
 
-        let exlen = len + (len % 3);
-
         // iterate over the pixels
         for (index, pixels) in source.pixels()
             // grab them in chunks of 8
             .chunks(8).into_iter()
             // make them vectors
             .map(|ch| ch.collect::<Vec<_>>())
-            .take(exlen / 3) // exlen is always exactly divisible by 3
+            .take(len.div_ceil(3))
             .enumerate()
         {
             if pixels.len() != 8
@@ -148,6 +158,29 @@ impl Codec for RgbCodec
         })
     }
 
+    fn channels(mode: RgbMode) -> &'static [usize]
+    {
+        match mode
+        {
+            RgbMode::All => &[0, 1, 2],
+        }
+    }
+
+    fn dimensions(source: &RgbImage) -> (u32, u32)
+    {
+        source.dimensions()
+    }
+
+    fn samples(source: &RgbImage) -> &[u8]
+    {
+        source
+    }
+
+    fn samples_mut(source: &mut RgbImage) -> &mut [u8]
+    {
+        source
+    }
+
 }
 
 #[derive(Copy, Clone)]
@@ -221,4 +254,20 @@ mod test
 
         assert_eq!(payload, buf);
     }
+
+    #[test]
+    fn uneven_length()
+    {
+        for len in 19..22
+        {
+            let mut image = ImageBuffer::from_pixel(30, 8, Rgb { data: [127u8; 3] });
+            let payload = (0..len).map(|i| (i * 37 + 5) as u8).collect::<Vec<_>>();
+            let mut buf = vec![0; len];
+
+            RgbCodec::encode(&mut image, &payload, RgbMode::All, StdRng::new().unwrap());
+            RgbCodec::decode(&image, &mut buf, len, RgbMode::All);
+
+            assert_eq!(payload, buf);
+        }
+    }
 }
@@ -12,6 +12,10 @@ impl Codec for RgbaCodec
     type Input = RgbaImage;
     type Mode = RgbaMode;
 
+    const CHANNELS: usize = 4;
+    const CHANNEL_NAMES: &'static [&'static str] = &["r", "g", "b", "a"];
+    const MODE_NAMES: &'static [&'static str] = &["alpha", "all"];
+
     fn encode<R: Rng>(
         source: &mut RgbaImage,
         payload: &[u8],
@@ -248,6 +252,31 @@ impl Codec for RgbaCodec
                 source.width() as usize * source.height() as usize / 2,
         })
     }
+
+    fn channels(mode: RgbaMode) -> &'static [usize]
+    {
+        match mode
+        {
+            RgbaMode::Alpha => &[3],
+            RgbaMode::All => &[0, 1, 2, 3],
+        }
+    }
+
+    fn dimensions(source: &RgbaImage) -> (u32, u32)
+    {
+        source.dimensions()
+    }
+
+    fn samples(source: &RgbaImage) -> &[u8]
+    {
+        source
+    }
+
+    fn samples_mut(source: &mut RgbaImage) -> &mut [u8]
+    {
+        source
+    }
+
 }
 
 /// The encoding/decoding mode
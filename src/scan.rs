@@ -0,0 +1,415 @@
+use header::Header;
+
+/// How many bytes of each layout are read to look for hits
+const PREVIEW: usize = 64;
+/// The shortest run of text worth reporting
+const MIN_TEXT: usize = 8;
+
+/// Signatures of common file types
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "PNG image"),
+    (b"\xff\xd8\xff", "JPEG image"),
+    (b"GIF87a", "GIF image"),
+    (b"GIF89a", "GIF image"),
+    (b"BM", "BMP image"),
+    (b"PK\x03\x04", "zip archive"),
+    (b"%PDF-", "PDF document"),
+    (b"\x1f\x8b\x08", "gzip data"),
+    (b"BZh", "bzip2 data"),
+    (b"\xfd7zXZ\x00", "xz data"),
+    (b"7z\xbc\xaf\x27\x1c", "7-zip archive"),
+    (b"\x7fELF", "ELF executable"),
+    (b"RIFF", "RIFF data"),
+    (b"OggS", "Ogg data"),
+    (b"ID3", "MP3 audio"),
+    (b"-----BEGIN ", "PEM or PGP armor"),
+    (b"\x85\x01\x0c", "PGP message"),
+];
+
+/// The order pixels are visited in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Traversal
+{
+    /// Left to right, top to bottom, as the codecs do
+    Rows,
+    /// Top to bottom, left to right
+    Columns,
+    /// Right to left, bottom to top
+    Reversed,
+}
+
+/// One way bits could have been hidden
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout
+{
+    /// The lowest and highest bit read from each sample
+    pub bits: (u8, u8),
+    /// Whether the highest of `bits` is read from each sample first
+    pub high_first: bool,
+    /// The channels read from each pixel, in order
+    pub channels: Vec<usize>,
+    /// Whether bits fill bytes most significant first
+    pub msb_first: bool,
+    pub traversal: Traversal,
+}
+
+/// A layout whose bits look like something
+#[derive(Clone, Debug)]
+pub struct Hit
+{
+    pub layout: Layout,
+    /// Higher is more likely to be real
+    pub score: f64,
+    pub description: String,
+}
+
+impl Layout
+{
+    /// The layout the codecs use for a mode with these channels
+    pub fn codec(channels: &[usize]) -> Layout
+    {
+        Layout
+        {
+            bits: (0, 0),
+            high_first: false,
+            channels: channels.to_vec(),
+            msb_first: false,
+            traversal: Traversal::Rows,
+        }
+    }
+
+    /// Describe the layout as `BITS,CHANNELS,ORDER,TRAVERSAL`, e.g.
+    /// `0,rgb,lsb,xy` or `0-1,a,msb,yx`. Bits written high to low, as in
+    /// `1-0`, are read from each sample highest first.
+    pub fn describe(&self, names: &[&str]) -> String
+    {
+        let bits = if self.bits.0 == self.bits.1
+        {
+            self.bits.0.to_string()
+        }
+        else if self.high_first
+        {
+            format!("{}-{}", self.bits.1, self.bits.0)
+        }
+        else
+        {
+            format!("{}-{}", self.bits.0, self.bits.1)
+        };
+
+        let channels = self.channels.iter().map(|&c| names[c]).collect::<String>();
+
+        format!("{},{},{},{}",
+            bits,
+            channels,
+            if self.msb_first { "msb" } else { "lsb" },
+            match self.traversal
+            {
+                Traversal::Rows => "xy",
+                Traversal::Columns => "yx",
+                Traversal::Reversed => "XY",
+            })
+    }
+
+    /// Parse a description written by `describe`
+    pub fn parse(spec: &str, names: &[&str]) -> Option<Layout>
+    {
+        let parts = spec.split(',').collect::<Vec<_>>();
+
+        if parts.len() != 4
+        {
+            return None;
+        }
+
+        let bits: (u8, u8) = match parts[0].find('-')
+        {
+            Some(i) => (parts[0][..i].parse().ok()?, parts[0][i + 1..].parse().ok()?),
+            None => (parts[0].parse().ok()?, parts[0].parse().ok()?),
+        };
+
+        let high_first = bits.0 > bits.1;
+        let bits = (bits.0.min(bits.1), bits.0.max(bits.1));
+
+        if bits.1 > 7
+        {
+            return None;
+        }
+
+        let channels = parts[1].chars()
+            .map(|ch| names.iter().position(|n| n.len() == 1 && n.starts_with(ch)))
+            .collect::<Option<Vec<_>>>()?;
+
+        if channels.is_empty()
+        {
+            return None;
+        }
+
+        let msb_first = match parts[2]
+        {
+            "lsb" => false,
+            "msb" => true,
+            _ => return None,
+        };
+
+        let traversal = match parts[3]
+        {
+            "xy" => Traversal::Rows,
+            "yx" => Traversal::Columns,
+            "XY" => Traversal::Reversed,
+            _ => return None,
+        };
+
+        Some(Layout
+        {
+            bits,
+            high_first,
+            channels,
+            msb_first,
+            traversal,
+        })
+    }
+
+    /// Read up to `len` bytes from samples with `channels` channels
+    pub fn read(
+        &self,
+        samples: &[u8],
+        channels: usize,
+        (width, height): (u32, u32),
+        len: usize) -> Vec<u8>
+    {
+        let (width, height) = (width as usize, height as usize);
+        let pixels = width * height;
+
+        let bits = if self.high_first
+        {
+            (self.bits.0..=self.bits.1).rev().collect::<Vec<_>>()
+        }
+        else
+        {
+            (self.bits.0..=self.bits.1).collect()
+        };
+
+        let mut bytes = Vec::with_capacity(len);
+        let mut byte = 0u8;
+        let mut filled = 0;
+
+        for i in 0..pixels
+        {
+            let pixel = match self.traversal
+            {
+                Traversal::Rows => i,
+                Traversal::Columns => (i % height) * width + i / height,
+                Traversal::Reversed => pixels - 1 - i,
+            };
+
+            for &c in &self.channels
+            {
+                let sample = samples[pixel * channels + c];
+
+                for &b in &bits
+                {
+                    let bit = (sample >> b) & 1;
+
+                    if self.msb_first
+                    {
+                        byte = (byte << 1) | bit;
+                    }
+                    else
+                    {
+                        byte |= bit << filled;
+                    }
+
+                    filled += 1;
+
+                    if filled == 8
+                    {
+                        bytes.push(byte);
+                        byte = 0;
+                        filled = 0;
+
+                        if bytes.len() == len
+                        {
+                            return bytes;
+                        }
+                    }
+                }
+            }
+        }
+
+        bytes
+    }
+}
+
+/// Every layout worth trying for an image with `channels` channels: each
+/// set of channels in both orders, single bits and runs of low bits read
+/// either way round, both orders of bits in a byte, and each traversal
+pub fn layouts(channels: usize) -> Vec<Layout>
+{
+    let mut orders = Vec::new();
+
+    for set in 1..(1usize << channels)
+    {
+        let forward = (0..channels).filter(|c| set & (1 << c) != 0).collect::<Vec<_>>();
+        let backward = forward.iter().rev().cloned().collect::<Vec<_>>();
+
+        if backward != forward
+        {
+            orders.push(backward);
+        }
+        orders.push(forward);
+    }
+
+    let bit_sets = (0..8).map(|b| ((b, b), false))
+        .chain((1..4).flat_map(|b| vec![((0, b), false), ((0, b), true)]))
+        .collect::<Vec<_>>();
+
+    let mut layouts = Vec::new();
+
+    for channels in &orders
+    {
+        for &(bits, high_first) in &bit_sets
+        {
+            for &msb_first in &[false, true]
+            {
+                for &traversal in &[Traversal::Rows, Traversal::Columns, Traversal::Reversed]
+                {
+                    layouts.push(Layout
+                    {
+                        bits,
+                        high_first,
+                        channels: channels.clone(),
+                        msb_first,
+                        traversal,
+                    });
+                }
+            }
+        }
+    }
+
+    layouts
+}
+
+/// Try every layout, returning those that look like something, best first
+pub fn scan(samples: &[u8], channels: usize, dimensions: (u32, u32)) -> Vec<Hit>
+{
+    let mut hits = layouts(channels).into_iter()
+        .filter_map(|layout|
+        {
+            let bytes = layout.read(samples, channels, dimensions, PREVIEW);
+
+            classify(&bytes, samples.len()).map(|(score, description)| Hit
+            {
+                layout,
+                score,
+                description,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    hits
+}
+
+/// Score the start of some bytes: a stag header, a known file type, or
+/// readable text
+pub fn classify(bytes: &[u8], samples: usize) -> Option<(f64, String)>
+{
+    if let Some(header) = Header::from_bytes(bytes)
+    {
+        if (header.length as usize) < samples
+        {
+            return Some((100.0, format!("stag header, {} byte payload, flags {:#04x}",
+                header.length, header.flags)));
+        }
+    }
+
+    for &(magic, name) in MAGIC
+    {
+        if bytes.starts_with(magic)
+        {
+            // short signatures turn up by chance, so score them lower
+            return Some((40.0 + 5.0 * magic.len() as f64, name.to_string()));
+        }
+    }
+
+    let text = bytes.iter()
+        .take_while(|&&b| (0x20..0x7f).contains(&b) || b == b'\n' || b == b'\r' || b == b'\t')
+        .count();
+
+    let mut distinct = bytes[..text].to_vec();
+    distinct.sort();
+    distinct.dedup();
+
+    if text >= MIN_TEXT
+        && distinct.len() >= 4
+        && bytes[..text].iter().any(|b| b.is_ascii_alphabetic())
+    {
+        let preview = String::from_utf8_lossy(&bytes[..text]).escape_debug().to_string();
+
+        return Some((20.0 + text as f64 / 2.0, format!("text \"{}\"", preview)));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    const NAMES: &[&str] = &["r", "g", "b", "a"];
+
+    #[test]
+    fn describe_and_parse()
+    {
+        for layout in layouts(4)
+        {
+            assert_eq!(Layout::parse(&layout.describe(NAMES), NAMES), Some(layout));
+        }
+    }
+
+    #[test]
+    fn finds_msb_column_text()
+    {
+        let (width, height) = (16, 16);
+        let mut samples = vec![0x40u8; width * height * 4];
+
+        let message = b"hidden in plain sight";
+        let layout = Layout::parse("1,gb,msb,yx", NAMES).unwrap();
+
+        // write the message into bit 1 of g then b, column by column
+        let bits = message.iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+        let slots = (0..width * height)
+            .map(|i| (i % height) * width + i / height)
+            .flat_map(|p| vec![p * 4 + 1, p * 4 + 2]);
+
+        for (bit, slot) in bits.zip(slots)
+        {
+            samples[slot] = (samples[slot] & !2) | (bit << 1);
+        }
+
+        let hits = scan(&samples, 4, (width as u32, height as u32));
+
+        assert_eq!(hits[0].layout, layout);
+        assert!(hits[0].description.contains("hidden in plain sight"));
+    }
+
+    #[test]
+    fn bit_and_byte_order_apart()
+    {
+        // bits 1 then 0 of one sample, packed into bytes low bit first
+        let layout = Layout::parse("1-0,r,lsb,xy", NAMES).unwrap();
+        assert!(layout.high_first && !layout.msb_first);
+        assert_eq!(layout.describe(NAMES), "1-0,r,lsb,xy");
+
+        let samples = [0b10, 0, 0, 0, 0b01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(layout.read(&samples, 4, (4, 1), 1), vec![0b0000_1001]);
+
+        let other = Layout { high_first: false, ..layout.clone() };
+        assert_eq!(other.read(&samples, 4, (4, 1), 1), vec![0b0000_0110]);
+
+        let msb = Layout { msb_first: true, ..layout };
+        assert_eq!(msb.read(&samples, 4, (4, 1), 1), vec![0b1001_0000]);
+    }
+}
@@ -0,0 +1,146 @@
+use rand::Rng;
+
+/// One share of a secret: its x coordinate, and a byte for each byte of the
+/// secret
+pub type Share = (u8, Vec<u8>);
+
+/// Split a secret into `n` shares, any `k` of which recover it.
+///
+/// Each byte of the secret is the constant term of its own random
+/// polynomial of degree `k - 1` over GF(256), and share `x` holds each
+/// polynomial evaluated at `x`.
+pub fn split<R: Rng>(secret: &[u8], k: u8, n: u8, rng: &mut R) -> Vec<Share>
+{
+    assert!(k >= 1 && k <= n);
+
+    let mut shares = (1..=n)
+        .map(|x| (x, Vec::with_capacity(secret.len())))
+        .collect::<Vec<_>>();
+
+    let mut coefficients = vec![0u8; k as usize];
+
+    for &byte in secret
+    {
+        coefficients[0] = byte;
+        for c in coefficients.iter_mut().skip(1)
+        {
+            *c = rng.gen();
+        }
+
+        for share in &mut shares
+        {
+            let x = share.0;
+            // horner's method, from the highest coefficient down
+            let y = coefficients.iter().rev().fold(0, |acc, &c| mul(acc, x) ^ c);
+            share.1.push(y);
+        }
+    }
+
+    shares
+}
+
+/// Recover a secret from shares with distinct x coordinates, by Lagrange
+/// interpolation at zero. With fewer than `k` shares the result is
+/// meaningless, not an error.
+pub fn combine(shares: &[Share]) -> Option<Vec<u8>>
+{
+    let len = shares.first()?.1.len();
+
+    if shares.iter().any(|s| s.0 == 0 || s.1.len() != len)
+    {
+        return None;
+    }
+
+    for (i, a) in shares.iter().enumerate()
+    {
+        if shares[i + 1..].iter().any(|b| a.0 == b.0)
+        {
+            return None;
+        }
+    }
+
+    // the basis polynomial for each share, evaluated at zero
+    let basis = shares.iter().map(|a|
+    {
+        shares.iter()
+            .filter(|b| b.0 != a.0)
+            .fold(1, |acc, b| mul(acc, div(b.0, a.0 ^ b.0)))
+    }).collect::<Vec<_>>();
+
+    Some((0..len).map(|i|
+    {
+        shares.iter()
+            .zip(basis.iter())
+            .fold(0, |acc, (share, &l)| acc ^ mul(share.1[i], l))
+    }).collect())
+}
+
+/// Multiply in GF(256) with the AES polynomial
+fn mul(mut a: u8, mut b: u8) -> u8
+{
+    let mut product = 0;
+
+    while b != 0
+    {
+        if b & 1 == 1
+        {
+            product ^= a;
+        }
+
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry
+        {
+            a ^= 0x1b;
+        }
+
+        b >>= 1;
+    }
+
+    product
+}
+
+/// Divide in GF(256), `b` must not be zero
+fn div(a: u8, b: u8) -> u8
+{
+    // b^254 is the inverse of b
+    let mut inverse = 1;
+    for _ in 0..254
+    {
+        inverse = mul(inverse, b);
+    }
+
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod test
+{
+    use rand::StdRng;
+
+    use super::*;
+
+    #[test]
+    fn any_k_shares()
+    {
+        let mut rng = StdRng::new().unwrap();
+        let secret = b"attack at dawn".to_vec();
+
+        let shares = split(&secret, 3, 5, &mut rng);
+
+        assert_eq!(combine(&shares[..3]), Some(secret.clone()));
+        assert_eq!(combine(&shares[2..]), Some(secret.clone()));
+        assert_eq!(combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]),
+            Some(secret.clone()));
+        assert!(combine(&shares[..2]) != Some(secret));
+    }
+
+    #[test]
+    fn field()
+    {
+        for a in 1..=255u8
+        {
+            assert_eq!(mul(a, div(1, a)), 1);
+        }
+    }
+}
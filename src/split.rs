@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use header::Piece;
+
+/// Why pieces could not be joined
+#[derive(Debug, PartialEq)]
+pub enum JoinError
+{
+    /// There were no pieces
+    Empty,
+    /// The pieces came from more than one message, with these ids
+    Mixed(Vec<u64>),
+    /// Some pieces were missing, with these indices out of `count`
+    Missing(Vec<u16>, u16),
+}
+
+/// Split `len` bytes across carriers with the given capacities, filling
+/// each in turn. Carriers that are not needed get an empty range.
+pub fn plan(len: usize, capacities: &[usize]) -> Option<Vec<Range<usize>>>
+{
+    let mut start = 0;
+    let mut ranges = Vec::with_capacity(capacities.len());
+
+    for &capacity in capacities
+    {
+        let end = len.min(start + capacity);
+        ranges.push(start..end);
+        start = end;
+    }
+
+    if start < len
+    {
+        None
+    }
+    else
+    {
+        Some(ranges)
+    }
+}
+
+/// Reassemble a message from its pieces, given in any order
+pub fn join(pieces: Vec<(Piece, Vec<u8>)>) -> Result<Vec<u8>, JoinError>
+{
+    let mut ids = pieces.iter().map(|p| p.0.id).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+
+    if ids.is_empty()
+    {
+        return Err(JoinError::Empty);
+    }
+    if ids.len() > 1
+    {
+        return Err(JoinError::Mixed(ids));
+    }
+
+    let count = pieces[0].0.count;
+
+    let mut ordered = BTreeMap::new();
+    for (piece, data) in pieces
+    {
+        ordered.insert(piece.index, data);
+    }
+
+    let missing = (0..count)
+        .filter(|i| !ordered.contains_key(i))
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty()
+    {
+        return Err(JoinError::Missing(missing, count));
+    }
+
+    Ok(ordered.into_iter()
+        .filter(|&(i, _)| i < count)
+        .flat_map(|(_, data)| data)
+        .collect())
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn piece(index: u16) -> Piece
+    {
+        Piece
+        {
+            id: 7,
+            index,
+            count: 3,
+        }
+    }
+
+    #[test]
+    fn plan_fills_in_order()
+    {
+        assert_eq!(plan(10, &[4, 4, 4]), Some(vec![0..4, 4..8, 8..10]));
+        assert_eq!(plan(4, &[4, 4]), Some(vec![0..4, 4..4]));
+        assert_eq!(plan(10, &[4, 4]), None);
+    }
+
+    #[test]
+    fn join_any_order()
+    {
+        let pieces = vec![
+            (piece(2), vec![5]),
+            (piece(0), vec![1, 2]),
+            (piece(1), vec![3, 4]),
+        ];
+
+        assert_eq!(join(pieces), Ok(vec![1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn join_missing()
+    {
+        let pieces = vec![(piece(1), vec![3, 4])];
+
+        assert_eq!(join(pieces), Err(JoinError::Missing(vec![0, 2], 3)));
+    }
+}
@@ -0,0 +1,875 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use image::{DynamicImage, GenericImage, ImageBuffer};
+use inflate::inflate_bytes_zlib;
+use lzw::{DecoderEarlyChange, MsbReader};
+
+/// Tags written out from the pixel data of a page rather than kept
+const LAYOUT_TAGS: &[u16] = &[
+    256, // ImageWidth
+    257, // ImageLength
+    258, // BitsPerSample
+    259, // Compression
+    273, // StripOffsets
+    277, // SamplesPerPixel
+    278, // RowsPerStrip
+    279, // StripByteCounts
+    284, // PlanarConfiguration
+    317, // Predictor
+    322, 323, 324, 325, // tiles
+];
+
+/// Tags that point at other places in the file, which move when it is
+/// written again
+const POINTER_TAGS: &[u16] = &[
+    330, // SubIFDs
+    513, 514, // JPEGInterchangeFormat
+];
+
+/// Tags that point at an IFD of metadata, which is copied and pointed at
+/// where it is written again
+const SUB_IFD_TAGS: &[u16] = &[
+    34665, // Exif IFD
+    34853, // GPS IFD
+    40965, // Interoperability IFD
+];
+
+/// Tags that say how to read the pixels of a page, kept when the rest are
+/// stripped
+const PIXEL_TAGS: &[u16] = &[
+    254, // NewSubfileType
+    262, // PhotometricInterpretation
+    338, // ExtraSamples
+    339, // SampleFormat
+];
+
+/// A TIFF file, every page in order
+pub struct Tiff
+{
+    /// Whether the file is in Motorola byte order, which it is written back in
+    big_endian: bool,
+    pages: Vec<Page>,
+}
+
+#[derive(Clone)]
+struct Page
+{
+    width: u32,
+    height: u32,
+    /// Samples per pixel, from grey to RGBA
+    channels: usize,
+    /// Bits per sample, 8 or 16
+    depth: u8,
+    /// Every sample as stored, a 16-bit one in the file's byte order
+    data: Vec<u8>,
+    /// The other tags of the page, as they were
+    tags: Vec<Entry>,
+    /// The Exif and GPS IFDs the page points at
+    subs: Vec<SubIfd>,
+}
+
+/// An IFD of metadata that a page, or another such IFD, points at
+#[derive(Clone, Debug, PartialEq)]
+struct SubIfd
+{
+    /// The tag that points at it
+    tag: u16,
+    entries: Vec<Entry>,
+    /// The IFDs it points at in turn, like the Interoperability one in Exif
+    subs: Vec<SubIfd>,
+}
+
+/// An IFD entry with its value bytes, in the file's byte order
+#[derive(Clone, Debug, PartialEq)]
+struct Entry
+{
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+impl Tiff
+{
+    /// A TIFF of 8-bit pages, one for each image, to save images that came
+    /// from some other kind of file
+    pub fn new(images: &[DynamicImage]) -> Tiff
+    {
+        let short = |tag, value: u16| Entry
+        {
+            tag,
+            kind: 3,
+            count: 1,
+            value: value.to_le_bytes().to_vec(),
+        };
+
+        let pages = images.iter().map(|image|
+        {
+            let (photometric, channels) = match *image
+            {
+                DynamicImage::ImageLuma8(_) => (1, 1),
+                DynamicImage::ImageLumaA8(_) => (1, 2),
+                DynamicImage::ImageRgb8(_) => (2, 3),
+                _ => (2, 4),
+            };
+
+            let mut tags = vec![short(262, photometric)];
+            if channels % 2 == 0
+            {
+                // ExtraSamples, of unassociated alpha
+                tags.push(short(338, 2));
+            }
+
+            Page
+            {
+                width: image.width(),
+                height: image.height(),
+                channels,
+                depth: 8,
+                data: image.raw_pixels(),
+                tags,
+                subs: Vec::new(),
+            }
+        }).collect();
+
+        Tiff
+        {
+            big_endian: false,
+            pages,
+        }
+    }
+
+    /// Read a TIFF file. Gives `None` for anything that is not one.
+    pub fn read(bytes: &[u8]) -> Result<Option<Tiff>, &'static str>
+    {
+        let big_endian = match bytes.get(..4)
+        {
+            Some(b"II*\0") => false,
+            Some(b"MM\0*") => true,
+            _ => return Ok(None),
+        };
+
+        let reader = Reader
+        {
+            bytes,
+            big_endian,
+        };
+
+        let mut pages = Vec::new();
+        let mut seen = Vec::new();
+        let mut offset = reader.u32(4)?;
+
+        while offset != 0
+        {
+            // a loop of IFDs would never end
+            if seen.contains(&offset)
+            {
+                return Err("The TIFF's pages point back at each other");
+            }
+            seen.push(offset);
+
+            let (entries, next) = reader.ifd(offset as usize)?;
+            pages.push(reader.page(entries)?);
+            offset = next;
+        }
+
+        if pages.is_empty()
+        {
+            return Err("The TIFF has no pages");
+        }
+
+        Ok(Some(Tiff
+        {
+            big_endian,
+            pages,
+        }))
+    }
+
+    /// Every page as an 8-bit image. A 16-bit page gives the low byte of each
+    /// sample, which is the one a payload goes into.
+    pub fn frames(&self) -> Vec<DynamicImage>
+    {
+        self.pages.iter().map(|page|
+        {
+            let low = if page.depth == 16 { self.big_endian as usize } else { 0 };
+            let raw = page.data.iter()
+                .skip(low)
+                .step_by(page.depth as usize / 8)
+                .cloned()
+                .collect::<Vec<_>>();
+            let (w, h) = (page.width, page.height);
+
+            match page.channels
+            {
+                1 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+                2 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+                3 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+                _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, raw).unwrap()),
+            }
+        }).collect()
+    }
+
+    pub fn sizes(&self) -> Vec<(u32, u32)>
+    {
+        self.pages.iter().map(|p| (p.width, p.height)).collect()
+    }
+
+    /// Whether any page has 16-bit samples, whose high bytes only a TIFF is
+    /// written with
+    pub fn is_deep(&self) -> bool
+    {
+        self.pages.iter().any(|p| p.depth == 16)
+    }
+
+    /// Leave out every tag but the ones that say how to read the pixels
+    pub fn strip(&mut self)
+    {
+        for page in &mut self.pages
+        {
+            page.tags.retain(|e| PIXEL_TAGS.contains(&e.tag));
+            page.subs.clear();
+        }
+    }
+
+    /// Save new 8-bit samples, every page's one after the other as `frames`
+    /// gives them, in place of the old ones. The high byte of a 16-bit sample
+    /// is kept, and the new one sets its low byte.
+    pub fn save<P: AsRef<Path>>(&self, samples: &[u8], path: P) -> io::Result<()>
+    {
+        let mut start = 0;
+
+        let pages = self.pages.iter().map(|page|
+        {
+            let mut page = page.clone();
+            let step = page.depth as usize / 8;
+            let low = if page.depth == 16 { self.big_endian as usize } else { 0 };
+            let end = start + page.data.len() / step;
+
+            for (sample, &value) in page.data.iter_mut()
+                .skip(low)
+                .step_by(step)
+                .zip(&samples[start..end])
+            {
+                *sample = value;
+            }
+
+            start = end;
+            page
+        }).collect::<Vec<_>>();
+
+        File::create(path)?.write_all(&write(&pages, self.big_endian))
+    }
+}
+
+/// Reads the numbers of a TIFF file in its byte order
+struct Reader<'a>
+{
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a>
+{
+    fn get(&self, offset: usize, len: usize) -> Result<&'a [u8], &'static str>
+    {
+        self.bytes.get(offset..offset + len).ok_or("The TIFF ends early")
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, &'static str>
+    {
+        let b = self.get(offset, 2)?;
+
+        Ok(if self.big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, &'static str>
+    {
+        let b = self.get(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    /// The entries of the IFD at `offset`, and the offset of the next one
+    fn ifd(&self, offset: usize) -> Result<(Vec<Entry>, u32), &'static str>
+    {
+        let count = self.u16(offset)? as usize;
+        let mut entries = Vec::with_capacity(count);
+
+        for i in 0..count
+        {
+            let at = offset + 2 + i * 12;
+            let kind = self.u16(at + 2)?;
+            let count = self.u32(at + 4)?;
+
+            // an entry of a type this does not know can not be copied
+            let size = match type_size(kind)
+            {
+                Some(size) => size * count as usize,
+                None => continue,
+            };
+
+            let value = if size <= 4
+            {
+                self.get(at + 8, size)?
+            }
+            else
+            {
+                self.get(self.u32(at + 8)? as usize, size)?
+            };
+
+            entries.push(Entry
+            {
+                tag: self.u16(at)?,
+                kind,
+                count,
+                value: value.to_vec(),
+            });
+        }
+
+        Ok((entries, self.u32(offset + 2 + count * 12)?))
+    }
+
+    /// The numbers held by an entry of BYTE, SHORT or LONG type
+    fn values(&self, entry: &Entry) -> Vec<u32>
+    {
+        let reader = Reader
+        {
+            bytes: &entry.value,
+            big_endian: self.big_endian,
+        };
+
+        (0..entry.count as usize).filter_map(|i| match entry.kind
+        {
+            1 => Some(entry.value[i] as u32),
+            3 => reader.u16(i * 2).ok().map(|v| v as u32),
+            4 | 13 => reader.u32(i * 4).ok(),
+            _ => None,
+        }).collect()
+    }
+
+    /// The metadata IFDs that entries point at. One that can not be read is
+    /// left out rather than failing the file, and nesting deeper than Exif
+    /// holds is not followed.
+    fn subs(&self, entries: &[Entry], depth: usize) -> Vec<SubIfd>
+    {
+        if depth > 1
+        {
+            return Vec::new();
+        }
+
+        entries.iter()
+            .filter(|e| SUB_IFD_TAGS.contains(&e.tag))
+            .filter_map(|e|
+            {
+                let offset = *self.values(e).first()?;
+                let (entries, _) = self.ifd(offset as usize).ok()?;
+
+                Some(SubIfd
+                {
+                    tag: e.tag,
+                    subs: self.subs(&entries, depth + 1),
+                    entries: entries.into_iter()
+                        .filter(|e| !POINTER_TAGS.contains(&e.tag) && !SUB_IFD_TAGS.contains(&e.tag))
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn page(&self, entries: Vec<Entry>) -> Result<Page, &'static str>
+    {
+        let find = |tag: u16| entries.iter()
+            .find(|e| e.tag == tag)
+            .map(|e| self.values(e));
+        let first = |tag: u16, default: Option<u32>| find(tag)
+            .and_then(|v| v.first().cloned())
+            .or(default)
+            .ok_or("A TIFF page is missing a tag it needs");
+
+        let width = first(256, None)?;
+        let height = first(257, None)?;
+
+        if width == 0 || height == 0
+        {
+            return Err("A TIFF page has no pixels");
+        }
+
+        let channels = first(277, Some(1))? as usize;
+        let bits = find(258).unwrap_or_else(|| vec![1]);
+        let compression = first(259, Some(1))?;
+        let predictor = first(317, Some(1))?;
+
+        // the predictor is not written back, so one that can not be undone
+        // would leave the samples differenced
+        if predictor != 1 && predictor != 2
+        {
+            return Err("Only TIFF pages with no predictor or horizontal differencing are supported");
+        }
+
+        if find(322).is_some()
+        {
+            return Err("Tiled TIFFs are not supported");
+        }
+        if channels == 0 || channels > 4
+        {
+            return Err("Only TIFF pages of one to four samples per pixel are supported");
+        }
+        if channels > 1 && first(284, Some(1))? != 1
+        {
+            return Err("Only TIFF pages with their samples interleaved are supported");
+        }
+        match (first(262, None)?, channels)
+        {
+            (0..=1, 1..=2) | (2, 3..=4) => {},
+            (0..=2, _) => return Err("A TIFF page has the wrong samples per pixel for its colour type"),
+            _ => return Err("Only grey and RGB TIFF pages are supported"),
+        }
+
+        let depth = match bits.first()
+        {
+            Some(&d) if (d == 8 || d == 16) && bits.iter().all(|&b| b == d) => d as u8,
+            _ => return Err("Only TIFF pages of 8 or 16 bits per sample are supported"),
+        };
+
+        // the most a byte of each compression can expand to
+        let expansion: usize = match compression
+        {
+            1 => 1,
+            5 => 4096,
+            8 | 32946 => 1032,
+            32773 => 64,
+            _ => return Err("The TIFF's compression is not supported"),
+        };
+
+        let offsets = find(273).ok_or("A TIFF page has no strips")?;
+        let counts = find(279).ok_or("A TIFF page has no strips")?;
+        let stored = counts.iter().fold(0usize, |sum, &c| sum.saturating_add(c as usize));
+
+        let (row, len) = match (width as usize).checked_mul(channels * depth as usize / 8)
+            .and_then(|row| Some((row, row.checked_mul(height as usize)?)))
+        {
+            Some((row, len)) if len <= stored.saturating_mul(expansion) => (row, len),
+            _ => return Err("A TIFF page is larger than its strips could hold"),
+        };
+
+        let mut data = Vec::new();
+
+        for (&offset, &count) in offsets.iter().zip(&counts)
+        {
+            let strip = self.get(offset as usize, count as usize)?;
+
+            match compression
+            {
+                1 => data.extend_from_slice(strip),
+                5 => data.append(&mut lzw(strip, len - data.len().min(len))?),
+                8 | 32946 => data.append(&mut inflate_bytes_zlib(strip)
+                    .map_err(|_| "A TIFF strip does not inflate")?),
+                _ => data.append(&mut unpack_bits(strip)),
+            }
+        }
+
+        if data.len() < len
+        {
+            return Err("A TIFF page holds fewer samples than its size says");
+        }
+        data.truncate(len);
+
+        if predictor == 2
+        {
+            for line in data.chunks_mut(row)
+            {
+                undo_predictor(line, channels, depth, self.big_endian);
+            }
+        }
+
+        Ok(Page
+        {
+            width,
+            height,
+            channels,
+            depth,
+            data,
+            subs: self.subs(&entries, 0),
+            tags: entries.into_iter()
+                .filter(|e| !LAYOUT_TAGS.contains(&e.tag)
+                    && !POINTER_TAGS.contains(&e.tag)
+                    && !SUB_IFD_TAGS.contains(&e.tag))
+                .collect(),
+        })
+    }
+}
+
+/// The size in bytes of a value of an IFD entry type
+fn type_size(kind: u16) -> Option<usize>
+{
+    match kind
+    {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Decompress an LZW strip, which ends with a code the decoder does not tell
+/// apart from a clear code, so it stops at the bytes the page needs
+fn lzw(strip: &[u8], needed: usize) -> Result<Vec<u8>, &'static str>
+{
+    let mut decoder = DecoderEarlyChange::new(MsbReader::new(), 8);
+    let mut input = strip;
+    let mut out = Vec::new();
+
+    while !input.is_empty() && out.len() < needed
+    {
+        let (consumed, bytes) = decoder.decode_bytes(input)
+            .map_err(|_| "A TIFF strip is not valid LZW")?;
+
+        if consumed == 0
+        {
+            break;
+        }
+
+        out.extend_from_slice(bytes);
+        input = &input[consumed..];
+    }
+
+    Ok(out)
+}
+
+/// Expand PackBits, a run length coding
+fn unpack_bits(strip: &[u8]) -> Vec<u8>
+{
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < strip.len()
+    {
+        let n = strip[i] as i8;
+        i += 1;
+
+        if n >= 0
+        {
+            let end = (i + n as usize + 1).min(strip.len());
+            out.extend_from_slice(&strip[i..end]);
+            i = end;
+        }
+        else if n != -128
+        {
+            if let Some(&b) = strip.get(i)
+            {
+                out.extend(::std::iter::repeat_n(b, (1 - n as isize) as usize));
+            }
+            i += 1;
+        }
+    }
+
+    out
+}
+
+/// Turn the differences of the horizontal predictor back into samples
+fn undo_predictor(line: &mut [u8], channels: usize, depth: u8, big_endian: bool)
+{
+    if depth == 8
+    {
+        for i in channels..line.len()
+        {
+            line[i] = line[i].wrapping_add(line[i - channels]);
+        }
+
+        return;
+    }
+
+    let read = |b: &[u8]| if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) };
+    let step = channels * 2;
+
+    for i in (step..line.len()).step_by(2)
+    {
+        let value = read(&line[i..]).wrapping_add(read(&line[i - step..]));
+        let bytes = if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        line[i..i + 2].copy_from_slice(&bytes);
+    }
+}
+
+/// Put pages together into an uncompressed TIFF file, one strip to a page
+fn write(pages: &[Page], big_endian: bool) -> Vec<u8>
+{
+    let u16b = |v: u16| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+    let u32b = |v: u32| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+
+    let mut bytes = if big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+    // where the offset of the next IFD goes
+    let mut link = bytes.len();
+    bytes.extend_from_slice(&[0; 4]);
+
+    for page in pages
+    {
+        let strip = bytes.len() as u32;
+        bytes.extend_from_slice(&page.data);
+        if bytes.len() % 2 == 1
+        {
+            bytes.push(0);
+        }
+
+        let short = |tag, values: &[u16]| Entry
+        {
+            tag,
+            kind: 3,
+            count: values.len() as u32,
+            value: values.iter().flat_map(|&v| u16b(v)).collect(),
+        };
+        let long = |tag, value: u32| Entry
+        {
+            tag,
+            kind: 4,
+            count: 1,
+            value: u32b(value),
+        };
+
+        let mut entries = page.tags.clone();
+        entries.extend(page.subs.iter().map(|sub| long(sub.tag, write_sub(&mut bytes, sub, big_endian))));
+        entries.extend(vec![
+            long(256, page.width),
+            long(257, page.height),
+            short(258, &vec![page.depth as u16; page.channels]),
+            short(259, &[1]),
+            long(273, strip),
+            short(277, &[page.channels as u16]),
+            long(278, page.height),
+            long(279, page.data.len() as u32),
+            short(284, &[1]),
+        ]);
+
+        let offset = u32b(bytes.len() as u32);
+        bytes[link..link + 4].copy_from_slice(&offset);
+
+        link = write_ifd(&mut bytes, entries, big_endian);
+    }
+
+    bytes
+}
+
+/// Write a metadata IFD after the ones it points at, giving its offset
+fn write_sub(bytes: &mut Vec<u8>, sub: &SubIfd, big_endian: bool) -> u32
+{
+    let mut entries = sub.entries.clone();
+
+    for inner in &sub.subs
+    {
+        let offset = write_sub(bytes, inner, big_endian);
+
+        entries.push(Entry
+        {
+            tag: inner.tag,
+            kind: 4,
+            count: 1,
+            value: if big_endian { offset.to_be_bytes().to_vec() } else { offset.to_le_bytes().to_vec() },
+        });
+    }
+
+    let offset = bytes.len() as u32;
+    write_ifd(bytes, entries, big_endian);
+
+    offset
+}
+
+/// Write an IFD of entries at the end of the file, with no next one. Gives
+/// where the offset of the next IFD goes.
+fn write_ifd(bytes: &mut Vec<u8>, mut entries: Vec<Entry>, big_endian: bool) -> usize
+{
+    let u16b = |v: u16| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+    let u32b = |v: u32| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+
+    entries.sort_by_key(|e| e.tag);
+
+    let ifd = bytes.len();
+
+    // values too large for their entry follow the IFD
+    let mut extra = ifd + 2 + entries.len() * 12 + 4;
+    let mut values = Vec::new();
+
+    bytes.extend(u16b(entries.len() as u16));
+    for entry in &entries
+    {
+        bytes.extend(u16b(entry.tag));
+        bytes.extend(u16b(entry.kind));
+        bytes.extend(u32b(entry.count));
+
+        if entry.value.len() <= 4
+        {
+            let mut inline = entry.value.clone();
+            inline.resize(4, 0);
+            bytes.extend(inline);
+        }
+        else
+        {
+            bytes.extend(u32b(extra as u32));
+            values.extend_from_slice(&entry.value);
+            if values.len() % 2 == 1
+            {
+                values.push(0);
+            }
+            extra = ifd + 2 + entries.len() * 12 + 4 + values.len();
+        }
+    }
+
+    let link = bytes.len();
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.append(&mut values);
+
+    link
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::env::temp_dir;
+    use std::fs;
+
+    use super::*;
+
+    /// A page of 16-bit RGB with a description, and a smaller 16-bit one
+    fn tiff(big_endian: bool) -> Tiff
+    {
+        let page = |width: u32, height: u32, seed: u16|
+        {
+            let data = (0..width * height * 3)
+                .map(|i| (i as u16).wrapping_mul(2731).wrapping_add(seed))
+                .flat_map(|v| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() })
+                .collect();
+
+            Page
+            {
+                width,
+                height,
+                channels: 3,
+                depth: 16,
+                data,
+                tags: vec![
+                    Entry { tag: 262, kind: 3, count: 1, value: if big_endian { vec![0, 2] } else { vec![2, 0] } },
+                    Entry { tag: 270, kind: 2, count: 12, value: b"page of two\0".to_vec() },
+                ],
+                subs: Vec::new(),
+            }
+        };
+
+        Tiff
+        {
+            big_endian,
+            pages: vec![page(6, 5, 1), page(6, 2, 9)],
+        }
+    }
+
+    #[test]
+    fn pages_round_trip()
+    {
+        for &big_endian in &[false, true]
+        {
+            let source = tiff(big_endian);
+            let bytes = write(&source.pages, big_endian);
+            let read = Tiff::read(&bytes).unwrap().unwrap();
+
+            assert_eq!(read.sizes(), vec![(6, 5), (6, 2)]);
+            for (a, b) in read.pages.iter().zip(&source.pages)
+            {
+                assert_eq!((a.depth, a.channels), (16, 3));
+                assert_eq!(a.data, b.data);
+                assert_eq!(a.tags, b.tags);
+            }
+
+            // a new low byte leaves the high one as it was
+            let mut frames = read.frames();
+            if let DynamicImage::ImageRgb8(ref mut image) = frames[1]
+            {
+                image.get_pixel_mut(0, 0).data[0] ^= 1;
+            }
+
+            let samples = frames.iter().flat_map(|f| f.raw_pixels()).collect::<Vec<_>>();
+            let path = temp_dir().join("stag-tiff-test.tif");
+            read.save(&samples, &path).unwrap();
+            let saved = Tiff::read(&fs::read(&path).unwrap()).unwrap().unwrap();
+
+            let old = &source.pages[1].data;
+            let new = &saved.pages[1].data;
+            let low = big_endian as usize;
+            assert_eq!(new[low], old[low] ^ 1);
+            assert_eq!(new[1 - low], old[1 - low]);
+            assert_eq!(new[2..], old[2..]);
+            assert_eq!(saved.pages[0].data, source.pages[0].data);
+        }
+    }
+
+    /// A little-endian TIFF of one page with the given LONG tags, whose
+    /// one strip is the file's own first 8 bytes
+    fn hostile(tags: &[(u16, u32)]) -> Vec<u8>
+    {
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+
+        let entries = [(273, 0), (279, 8)].iter().chain(tags)
+            .map(|&(tag, value)| Entry { tag, kind: 4, count: 1, value: value.to_le_bytes().to_vec() })
+            .collect();
+        write_ifd(&mut bytes, entries, false);
+
+        bytes
+    }
+
+    #[test]
+    fn refuses_hostile_headers()
+    {
+        let read = |tags: &[(u16, u32)]| Tiff::read(&hostile(tags)).err();
+
+        assert_eq!(read(&[(256, 0), (257, 1), (258, 8), (262, 1), (317, 2)]),
+            Some("A TIFF page has no pixels"));
+        assert_eq!(read(&[(256, 1), (257, 0), (258, 8), (262, 1)]),
+            Some("A TIFF page has no pixels"));
+        assert_eq!(read(&[(256, 2), (257, 1), (258, 8), (262, 2), (277, 2)]),
+            Some("A TIFF page has the wrong samples per pixel for its colour type"));
+        assert_eq!(read(&[(256, 2), (257, 1), (258, 8), (262, 1), (277, 4)]),
+            Some("A TIFF page has the wrong samples per pixel for its colour type"));
+        assert_eq!(read(&[(256, u32::MAX), (257, u32::MAX), (258, 16), (262, 2), (277, 4)]),
+            Some("A TIFF page is larger than its strips could hold"));
+        assert_eq!(read(&[(256, 65535), (257, 65535), (258, 8), (262, 1)]),
+            Some("A TIFF page is larger than its strips could hold"));
+
+        // the strip holds exactly the 8 samples of a 4x2 grey page
+        assert!(Tiff::read(&hostile(&[(256, 4), (257, 2), (258, 8), (262, 1)])).unwrap().is_some());
+    }
+
+    #[test]
+    fn undoes_compression()
+    {
+        assert_eq!(unpack_bits(&[2, 1, 2, 3, 0xfe, 7, 0x80, 0, 9]), vec![1, 2, 3, 7, 7, 7, 9]);
+
+        let mut line = vec![10, 20, 1, 1, 2, 2];
+        undo_predictor(&mut line, 2, 8, false);
+        assert_eq!(line, vec![10, 20, 11, 21, 13, 23]);
+    }
+
+    #[test]
+    fn keeps_exif_and_gps()
+    {
+        for &big_endian in &[false, true]
+        {
+            let entry = |tag, value: &[u8]| Entry { tag, kind: 2, count: value.len() as u32, value: value.to_vec() };
+            let interop = SubIfd { tag: 40965, entries: vec![entry(1, b"R98\0")], subs: Vec::new() };
+            let exif = SubIfd { tag: 34665, entries: vec![entry(36867, b"2020:01:02 03:04:05\0")], subs: vec![interop] };
+            let gps = SubIfd { tag: 34853, entries: vec![entry(1, b"N\0")], subs: Vec::new() };
+
+            let mut source = tiff(big_endian);
+            source.pages[0].subs = vec![exif, gps];
+
+            let mut read = Tiff::read(&write(&source.pages, big_endian)).unwrap().unwrap();
+            assert_eq!(read.pages[0].subs, source.pages[0].subs);
+            assert_eq!(read.pages[0].tags, source.pages[0].tags);
+            assert!(read.pages[1].subs.is_empty());
+
+            read.strip();
+            let stripped = Tiff::read(&write(&read.pages, big_endian)).unwrap().unwrap();
+            assert!(stripped.pages[0].subs.is_empty());
+        }
+    }
+}
@@ -69,3 +69,31 @@ pub fn fix_u8<R: Rng>(source: &mut u8, value: bool, rng: &mut R)
         }
     }
 }
+
+/// Encode bytes into the least significant bits of `samples` at `positions`,
+/// least significant bit first, the same way the codecs do
+pub fn embed<R: Rng>(
+    samples: &mut [u8],
+    positions: &[usize],
+    bytes: &[u8],
+    rng: &mut R)
+{
+    for (i, &p) in positions.iter().take(bytes.len() * 8).enumerate()
+    {
+        fix_u8(&mut samples[p], get_bit(bytes[i / 8], (i % 8) as u8), rng);
+    }
+}
+
+/// Decode `len` bytes from the least significant bits of `samples` at
+/// `positions`
+pub fn extract(samples: &[u8], positions: &[usize], len: usize) -> Vec<u8>
+{
+    let mut bytes = vec![0u8; len];
+
+    for (i, &p) in positions.iter().take(len * 8).enumerate()
+    {
+        bytes[i / 8] = set_bit(bytes[i / 8], (i % 8) as u8, samples[p] % 2 == 1);
+    }
+
+    bytes
+}
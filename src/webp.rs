@@ -0,0 +1,577 @@
+use image::{DynamicImage, GenericImage, ImageBuffer};
+
+/// The order the lengths of the code length code are written in
+const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// The sizes of the alphabets of the green, red, blue, alpha and distance
+/// codes, when there is no colour cache
+const ALPHABETS: [usize; 5] = [256 + 24, 256, 256, 256, 40];
+
+/// Write an RGB or RGBA image as a lossless WebP. Every pixel is a literal,
+/// with no transforms, colour cache or backward references, so the file is
+/// bigger than one from cwebp but holds each sample as it is.
+pub fn write(image: &DynamicImage) -> Option<Vec<u8>>
+{
+    let (alpha, argb) = match *image
+    {
+        DynamicImage::ImageRgb8(ref image) =>
+            (false, image.pixels().map(|p| [p.data[1], p.data[0], p.data[2], 255]).collect::<Vec<_>>()),
+        DynamicImage::ImageRgba8(ref image) =>
+            (true, image.pixels().map(|p| [p.data[1], p.data[0], p.data[2], p.data[3]]).collect()),
+        _ => return None,
+    };
+
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 || width > 1 << 14 || height > 1 << 14
+    {
+        return None;
+    }
+
+    let mut writer = BitWriter::default();
+    writer.put(0x2f, 8);
+    writer.put(width - 1, 14);
+    writer.put(height - 1, 14);
+    writer.put(alpha as u32, 1);
+    writer.put(0, 3);
+    // no transform, no colour cache and one group of codes for every pixel
+    writer.put(0, 3);
+
+    let codes = (0..5).map(|c|
+    {
+        let mut counts = vec![0u64; ALPHABETS[c]];
+        if c < 4
+        {
+            for pixel in &argb
+            {
+                counts[pixel[c] as usize] += 1;
+            }
+        }
+
+        let lengths = lengths(&counts, 15);
+        writer.code(&lengths);
+
+        codes(&lengths)
+    }).collect::<Vec<_>>();
+
+    for pixel in &argb
+    {
+        for (c, &value) in pixel.iter().enumerate()
+        {
+            let (code, len) = codes[c][value as usize];
+            writer.put(code, len);
+        }
+    }
+
+    let mut data = writer.finish();
+    let size = data.len() as u32;
+    if data.len() % 2 == 1
+    {
+        data.push(0);
+    }
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(data.len() as u32 + 12).to_le_bytes());
+    bytes.extend_from_slice(b"WEBPVP8L");
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.append(&mut data);
+
+    Some(bytes)
+}
+
+/// Read a lossless WebP of the kind `write` makes. Gives `None` for anything
+/// that is not a WebP or holds a lossy image, and refuses one that uses
+/// transforms, a colour cache or backward references.
+pub fn read(bytes: &[u8]) -> Result<Option<DynamicImage>, &'static str>
+{
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP"
+    {
+        return Ok(None);
+    }
+
+    let mut offset = 12;
+    let data = loop
+    {
+        let header = match bytes.get(offset..offset + 8)
+        {
+            Some(header) => header,
+            None => return Err("The WebP has no image in it"),
+        };
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = bytes.get(offset + 8..offset + 8 + len).ok_or("A WebP chunk runs past the end of the file")?;
+
+        match &header[..4]
+        {
+            b"VP8L" => break data,
+            b"VP8 " => return Ok(None),
+            b"ANIM" => return Err("Animated WebPs are not supported"),
+            _ => offset += 8 + len + len % 2,
+        }
+    };
+
+    let mut reader = BitReader
+    {
+        bytes: data,
+        at: 0,
+    };
+
+    if reader.get(8)? != 0x2f
+    {
+        return Err("The WebP's lossless image has the wrong signature");
+    }
+
+    let width = reader.get(14)? + 1;
+    let height = reader.get(14)? + 1;
+    let alpha = reader.get(1)? == 1;
+
+    if reader.get(3)? != 0
+    {
+        return Err("The WebP's lossless image is of an unknown version");
+    }
+    if reader.get(3)? != 0
+    {
+        return Err("Only lossless WebPs without transforms, a colour cache or meta codes are supported");
+    }
+
+    let codes = ALPHABETS.iter()
+        .map(|&size| reader.code(size))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the size comes from the header, so the pixels are only given room as
+    // they decode
+    let mut raw = Vec::new();
+    for _ in 0..width * height
+    {
+        let green = codes[0].decode(&mut reader)?;
+        if green >= 256
+        {
+            return Err("Lossless WebPs with backward references are not supported");
+        }
+
+        let red = codes[1].decode(&mut reader)? as u8;
+        let blue = codes[2].decode(&mut reader)? as u8;
+        let a = codes[3].decode(&mut reader)? as u8;
+
+        raw.push(red);
+        raw.push(green as u8);
+        raw.push(blue);
+        if alpha
+        {
+            raw.push(a);
+        }
+    }
+
+    Ok(Some(if alpha
+    {
+        DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw).unwrap())
+    }
+    else
+    {
+        DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw).unwrap())
+    }))
+}
+
+/// The lengths of the Huffman codes for symbols of the given counts, no
+/// longer than `limit`. Rare symbols are counted as more common until the
+/// codes fit.
+fn lengths(counts: &[u64], limit: usize) -> Vec<usize>
+{
+    let mut floor = 1;
+
+    loop
+    {
+        let mut weights = counts.iter()
+            .map(|&c| if c == 0 { 0 } else { c.max(floor) })
+            .collect::<Vec<_>>();
+        let mut lengths = vec![0; counts.len()];
+        // the symbols under each node, which lengthen as nodes merge
+        let mut members = (0..counts.len()).map(|s| vec![s]).collect::<Vec<_>>();
+
+        loop
+        {
+            let least = |weights: &[u64], not: Option<usize>| (0..weights.len())
+                .filter(|&i| weights[i] > 0 && Some(i) != not)
+                .min_by_key(|&i| weights[i]);
+
+            let (a, b) = match least(&weights, None).and_then(|a| least(&weights, Some(a)).map(|b| (a, b)))
+            {
+                Some(pair) => pair,
+                None => break,
+            };
+
+            weights[a] += weights[b];
+            weights[b] = 0;
+
+            let moved = ::std::mem::take(&mut members[b]);
+            members[a].extend(moved);
+            for &s in &members[a]
+            {
+                lengths[s] += 1;
+            }
+        }
+
+        // a lone symbol never merges, but still needs a length
+        for (length, &count) in lengths.iter_mut().zip(counts)
+        {
+            if count > 0 && *length == 0
+            {
+                *length = 1;
+            }
+        }
+
+        if lengths.iter().all(|&l| l <= limit)
+        {
+            return lengths;
+        }
+
+        floor *= 2;
+    }
+}
+
+/// The canonical codes for a set of lengths, with their bits reversed as
+/// they are written first bit first
+fn codes(lengths: &[usize]) -> Vec<(u32, usize)>
+{
+    let mut codes = vec![(0, 0); lengths.len()];
+
+    // a lone symbol takes no bits at all
+    if lengths.iter().filter(|&&l| l > 0).count() < 2
+    {
+        return codes;
+    }
+
+    let mut code = 0u32;
+    for len in 1..16
+    {
+        for (s, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == len)
+        {
+            let reversed = (0..len).fold(0, |r, i| r << 1 | (code >> i & 1));
+            codes[s] = (reversed, len);
+            code += 1;
+        }
+
+        code <<= 1;
+    }
+
+    codes
+}
+
+#[derive(Default)]
+struct BitWriter
+{
+    bytes: Vec<u8>,
+    bits: u64,
+    count: usize,
+}
+
+impl BitWriter
+{
+    fn put(&mut self, value: u32, len: usize)
+    {
+        self.bits |= (value as u64) << self.count;
+        self.count += len;
+
+        while self.count >= 8
+        {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write the lengths of a prefix code, as a simple code when it has one
+    /// symbol and as a normal one otherwise
+    fn code(&mut self, lengths: &[usize])
+    {
+        let used = lengths.iter().filter(|&&l| l > 0).count();
+
+        if used < 2
+        {
+            let symbol = lengths.iter().position(|&l| l > 0).unwrap_or(0) as u32;
+            let wide = symbol > 1;
+
+            self.put(1, 1);
+            self.put(0, 1);
+            self.put(wide as u32, 1);
+            self.put(symbol, if wide { 8 } else { 1 });
+
+            return;
+        }
+
+        let mut counts = vec![0u64; 19];
+        for &l in lengths
+        {
+            counts[l] += 1;
+        }
+
+        let code_lengths = self::lengths(&counts, 7);
+        let length_codes = codes(&code_lengths);
+
+        self.put(0, 1);
+        self.put(19 - 4, 4);
+        for &i in CODE_LENGTH_ORDER.iter()
+        {
+            self.put(code_lengths[i] as u32, 3);
+        }
+        // every symbol has a length
+        self.put(0, 1);
+
+        for &l in lengths
+        {
+            let (code, len) = length_codes[l];
+            self.put(code, len);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8>
+    {
+        if self.count > 0
+        {
+            self.bytes.push(self.bits as u8);
+        }
+
+        self.bytes
+    }
+}
+
+struct BitReader<'a>
+{
+    bytes: &'a [u8],
+    /// The next bit to read
+    at: usize,
+}
+
+impl<'a> BitReader<'a>
+{
+    fn bit(&mut self) -> Result<u32, &'static str>
+    {
+        let byte = self.bytes.get(self.at / 8).ok_or("The WebP ends early")?;
+        let bit = byte >> (self.at % 8) & 1;
+        self.at += 1;
+
+        Ok(bit as u32)
+    }
+
+    fn get(&mut self, len: usize) -> Result<u32, &'static str>
+    {
+        (0..len).try_fold(0, |value, i| Ok(value | self.bit()? << i))
+    }
+
+    fn code(&mut self, size: usize) -> Result<Code, &'static str>
+    {
+        let mut lengths = vec![0; size];
+
+        if self.get(1)? == 1
+        {
+            let count = self.get(1)? + 1;
+            let first = if self.get(1)? == 1 { self.get(8)? } else { self.get(1)? };
+            let mut symbols = vec![first];
+            if count == 2
+            {
+                symbols.push(self.get(8)?);
+            }
+
+            for s in symbols
+            {
+                *lengths.get_mut(s as usize).ok_or("A WebP code has a symbol outside its alphabet")? = 1;
+            }
+
+            return Code::new(&lengths);
+        }
+
+        let mut code_lengths = [0; 19];
+        let count = self.get(4)? as usize + 4;
+        for &i in &CODE_LENGTH_ORDER[..count]
+        {
+            code_lengths[i] = self.get(3)? as usize;
+        }
+        let length_code = Code::new(&code_lengths)?;
+
+        let mut max = size;
+        if self.get(1)? == 1
+        {
+            let bits = 2 + 2 * self.get(3)? as usize;
+            max = 2 + self.get(bits)? as usize;
+        }
+
+        let mut symbol = 0;
+        let mut previous = 8;
+        while symbol < size && max > 0
+        {
+            max -= 1;
+
+            let (value, repeat) = match length_code.decode(self)?
+            {
+                l if l < 16 => (l, 1),
+                16 => (previous, 3 + self.get(2)? as usize),
+                17 => (0, 3 + self.get(3)? as usize),
+                _ => (0, 11 + self.get(7)? as usize),
+            };
+
+            if symbol + repeat > size
+            {
+                return Err("A WebP code has more lengths than symbols");
+            }
+
+            for length in &mut lengths[symbol..symbol + repeat]
+            {
+                *length = value;
+            }
+            symbol += repeat;
+
+            if value != 0
+            {
+                previous = value;
+            }
+        }
+
+        Code::new(&lengths)
+    }
+}
+
+/// A prefix code, as the number of codes of each length and the symbols in
+/// the order their codes run
+struct Code
+{
+    counts: [usize; 16],
+    symbols: Vec<usize>,
+}
+
+impl Code
+{
+    fn new(lengths: &[usize]) -> Result<Code, &'static str>
+    {
+        let mut counts = [0; 16];
+        for &l in lengths
+        {
+            counts[l] += 1;
+        }
+        counts[0] = 0;
+
+        let symbols = (1..16)
+            .flat_map(|len| lengths.iter().enumerate().filter(move |&(_, &l)| l == len).map(|(s, _)| s))
+            .collect::<Vec<_>>();
+
+        if symbols.is_empty()
+        {
+            return Err("A WebP code has no symbols");
+        }
+
+        Ok(Code
+        {
+            counts,
+            symbols,
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, &'static str>
+    {
+        if self.symbols.len() == 1
+        {
+            return Ok(self.symbols[0]);
+        }
+
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for len in 1..16
+        {
+            code |= reader.bit()? as usize;
+
+            if code - first < self.counts[len]
+            {
+                return Ok(self.symbols[index + code - first]);
+            }
+
+            index += self.counts[len];
+            first = (first + self.counts[len]) << 1;
+            code <<= 1;
+        }
+
+        Err("The WebP holds a code its tables do not")
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+        let rgba = ImageBuffer::from_fn(37, 11, |x, y|
+            Rgba { data: [(x * 7) as u8, (y * 23) as u8, (x * y) as u8, if x % 5 == 0 { 128 } else { 255 }] });
+        let image = DynamicImage::ImageRgba8(rgba);
+
+        let written = read(&write(&image).unwrap()).unwrap().unwrap();
+        assert_eq!(written.raw_pixels(), image.raw_pixels());
+
+        // a single colour makes every code a lone symbol
+        let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 3, ::image::Rgb { data: [9, 200, 3] }));
+        let written = read(&write(&flat).unwrap()).unwrap().unwrap();
+        assert_eq!(written.raw_pixels(), flat.raw_pixels());
+        assert_eq!(written.color(), flat.color());
+    }
+
+    /// An image of noise from a linear congruential generator, with no alpha
+    /// of 0 as libwebp does not keep the colour of transparent pixels
+    fn noise(width: u32, height: u32, alpha: bool, seed: u32) -> DynamicImage
+    {
+        let mut x = seed;
+        let mut raw = (0..width * height * if alpha { 4 } else { 3 })
+            .map(|_|
+            {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+                (x >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+
+        if alpha
+        {
+            for a in raw.iter_mut().skip(3).step_by(4)
+            {
+                *a |= 1;
+            }
+
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, raw).unwrap())
+        }
+        else
+        {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, raw).unwrap())
+        }
+    }
+
+    #[test]
+    fn reads_libwebp()
+    {
+        let rgb = read(include_bytes!("../testdata/libwebp-rgb.webp")).unwrap().unwrap();
+        let rgba = read(include_bytes!("../testdata/libwebp-rgba.webp")).unwrap().unwrap();
+
+        assert_eq!(rgb.color(), ::image::RGB(8));
+        assert_eq!(rgb.raw_pixels(), noise(17, 16, false, 4).raw_pixels());
+        assert_eq!(rgba.color(), ::image::RGBA(8));
+        assert_eq!(rgba.raw_pixels(), noise(17, 16, true, 1).raw_pixels());
+    }
+
+    #[test]
+    fn writes_what_libwebp_reads()
+    {
+        let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 3, ::image::Rgb { data: [9, 200, 3] }));
+
+        assert_eq!(write(&noise(6, 5, true, 7)).unwrap(), &include_bytes!("../testdata/write-rgba.webp")[..]);
+        assert_eq!(write(&flat).unwrap(), &include_bytes!("../testdata/write-flat.webp")[..]);
+    }
+
+    #[test]
+    fn lengths_fit()
+    {
+        // counts doubling each time would want codes as long as there are
+        // symbols
+        let counts = (0..40).map(|i| 1u64 << i).collect::<Vec<_>>();
+        let lengths = lengths(&counts, 15);
+
+        assert!(lengths.iter().all(|&l| (1..=15).contains(&l)));
+        // a complete code
+        assert_eq!(lengths.iter().map(|&l| 1u64 << (15 - l)).sum::<u64>(), 1 << 15);
+    }
+}